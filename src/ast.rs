@@ -0,0 +1,472 @@
+//! A read-only view of a compiled expression.
+//!
+//! [`XPath::to_ast`](../struct.XPath.html#method.to_ast) copies the
+//! structure of a compiled expression into these types so that it can
+//! be inspected, for example to find which axes and name tests each
+//! step uses. Changing the copy has no effect on the compiled
+//! expression.
+//!
+//! The abbreviations are expanded by the time an expression is
+//! compiled, so `//a` is seen as the steps
+//! `/descendant-or-self::node()/child::a`.
+//!
+//! Expressions are displayed in unabbreviated XPath syntax, with
+//! parentheses wherever they are needed to keep the same structure.
+//! Compiling the displayed text gives an equal AST.
+//!
+//! ```
+//! use sxd_xpath::ast::{Axis, Expr, NodeTest};
+//! use sxd_xpath::Factory;
+//!
+//! let xpath = Factory::new().build("/book/@id").unwrap();
+//!
+//! match xpath.to_ast() {
+//!     Expr::Path { start, steps } => {
+//!         assert_eq!(Expr::RootNode, *start);
+//!         assert_eq!(Axis::Child, steps[0].axis);
+//!         assert_eq!(Axis::Attribute, steps[1].axis);
+//!         assert_eq!(NodeTest::name(None, "id"), steps[1].node_test);
+//!     }
+//!     other => panic!("expected a path, got {:?}", other),
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::{OwnedPrefixedName, Value};
+
+pub use crate::axis::Axis;
+
+/// An expression.
+///
+/// Chains of `or`, `and` and `|` are compiled into balanced trees, so
+/// `a or b or c or d` is seen as `(a or b) or (c or d)`. The other
+/// binary operators associate to the left.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Two expressions combined by an operator
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// Unary minus
+    Negation(Box<Expr>),
+    /// A string or number literal
+    Literal(Value<'static>),
+    /// A variable reference, with the name as written
+    Variable(OwnedPrefixedName),
+    /// A function call, with the name as written
+    Function {
+        name: OwnedPrefixedName,
+        arguments: Vec<Expr>,
+    },
+    /// The context node, which relative location paths start from
+    ContextNode,
+    /// The root of the context node's document, which absolute
+    /// location paths start from
+    RootNode,
+    /// Steps taken from the nodes that `start` selects
+    Path { start: Box<Expr>, steps: Vec<Step> },
+    /// A predicate applied to the nodes that `expression` selects, as
+    /// in `(a | b)[1]`
+    Filter {
+        expression: Box<Expr>,
+        predicate: Box<Expr>,
+    },
+}
+
+/// The operators that combine two expressions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Addition,
+    Subtraction,
+    Multiplication,
+    Division,
+    Remainder,
+    Union,
+}
+
+/// A location step, such as `child::a[1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub axis: Axis,
+    pub node_test: NodeTest,
+    /// The predicates, in the order they are applied
+    pub predicates: Vec<Expr>,
+}
+
+/// The test that a step applies to each node on its axis.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeTest {
+    /// `node()`
+    Node,
+    /// `text()`
+    Text,
+    /// `comment()`
+    Comment,
+    /// `processing-instruction()`, optionally with a target
+    ProcessingInstruction(Option<String>),
+    /// A name test against the principal node type of the axis. A
+    /// `local_part` of `*` matches any local name and a `prefix` of
+    /// `*` matches any namespace.
+    Name {
+        prefix: Option<String>,
+        local_part: String,
+    },
+}
+
+impl NodeTest {
+    /// A name test, for comparing against.
+    pub fn name(prefix: Option<&str>, local_part: &str) -> NodeTest {
+        NodeTest::Name {
+            prefix: prefix.map(Into::into),
+            local_part: local_part.into(),
+        }
+    }
+}
+
+/// How tightly an expression binds, from `or` up to expressions that
+/// never need parentheses.
+fn precedence(expr: &Expr) -> u8 {
+    use self::BinaryOperator::*;
+
+    match *expr {
+        Expr::Binary { operator, .. } => match operator {
+            Or => 1,
+            And => 2,
+            Equal | NotEqual => 3,
+            LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual => 4,
+            Addition | Subtraction => 5,
+            Multiplication | Division | Remainder => 6,
+            Union => 8,
+        },
+        Expr::Negation(..) => 7,
+        _ => 9,
+    }
+}
+
+/// Writes an expression that is part of a larger one, parenthesizing
+/// it when it binds less tightly than `minimum`. A lone `/` is always
+/// parenthesized, as a following `*` would otherwise be read as a
+/// name test.
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expr, minimum: u8) -> fmt::Result {
+    if precedence(expr) < minimum || *expr == Expr::RootNode {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+fn write_number(f: &mut fmt::Formatter<'_>, n: f64) -> fmt::Result {
+    if n.is_nan() {
+        f.write_str("(0 div 0)")
+    } else if n.is_infinite() {
+        write!(f, "({} div 0)", n.signum())
+    } else if n.is_sign_negative() {
+        write!(f, "(-{})", -n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+/// Writes an expression that a predicate or further steps can follow
+/// without being parenthesized.
+fn write_primary(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    match *expr {
+        Expr::Literal(..) | Expr::Variable(..) | Expr::Function { .. } | Expr::Filter { .. } => {
+            write!(f, "{}", expr)
+        }
+        _ => write!(f, "({})", expr),
+    }
+}
+
+/// XPath strings have no escapes, so a string containing both kinds
+/// of quote is written as a call to `concat`.
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    if !s.contains('\'') {
+        write!(f, "'{}'", s)
+    } else if !s.contains('"') {
+        write!(f, "\"{}\"", s)
+    } else {
+        let pieces: Vec<_> = s.split('\'').map(|p| format!("'{}'", p)).collect();
+        write!(f, "concat({})", pieces.join(", \"'\", "))
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Expr::Binary {
+                operator,
+                ref left,
+                ref right,
+            } => {
+                let level = precedence(self);
+                write_operand(f, left, level)?;
+                write!(f, " {} ", operator)?;
+
+                // Chains of associative operators are compiled into
+                // balanced trees, which are rebuilt from the flat chain.
+                let associative = match **right {
+                    Expr::Binary { operator: o, .. } => o == operator && operator.is_associative(),
+                    _ => false,
+                };
+                if associative {
+                    write_operand(f, right, level)
+                } else {
+                    write_operand(f, right, level + 1)
+                }
+            }
+            Expr::Negation(ref expr) => {
+                f.write_str("-")?;
+                write_operand(f, expr, precedence(self))
+            }
+            Expr::Literal(ref value) => match *value {
+                Value::Number(n) => write_number(f, n),
+                Value::String(ref s) => write_string(f, s),
+                Value::Boolean(b) => write!(f, "{}()", b),
+                // Only an empty nodeset can be written literally
+                Value::Nodeset(..) => f.write_str("(/..)"),
+            },
+            Expr::Variable(ref name) => write!(f, "${}", name),
+            Expr::Function {
+                ref name,
+                ref arguments,
+            } => {
+                write!(f, "{}(", name)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_str(")")
+            }
+            Expr::ContextNode => f.write_str("self::node()"),
+            Expr::RootNode => f.write_str("/"),
+            Expr::Path {
+                ref start,
+                ref steps,
+            } => {
+                match **start {
+                    Expr::ContextNode if !steps.is_empty() => {}
+                    Expr::RootNode => f.write_str("/")?,
+                    _ => {
+                        write_primary(f, start)?;
+                        if !steps.is_empty() {
+                            f.write_str("/")?;
+                        }
+                    }
+                }
+                for (i, step) in steps.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("/")?;
+                    }
+                    write!(f, "{}", step)?;
+                }
+                Ok(())
+            }
+            Expr::Filter {
+                ref expression,
+                ref predicate,
+            } => {
+                write_primary(f, expression)?;
+                write!(f, "[{}]", predicate)
+            }
+        }
+    }
+}
+
+impl BinaryOperator {
+    fn is_associative(self) -> bool {
+        match self {
+            BinaryOperator::Or | BinaryOperator::And | BinaryOperator::Union => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::BinaryOperator::*;
+
+        let s = match *self {
+            Or => "or",
+            And => "and",
+            Equal => "=",
+            NotEqual => "!=",
+            LessThan => "<",
+            LessThanOrEqual => "<=",
+            GreaterThan => ">",
+            GreaterThanOrEqual => ">=",
+            Addition => "+",
+            Subtraction => "-",
+            Multiplication => "*",
+            Division => "div",
+            Remainder => "mod",
+            Union => "|",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Axis::*;
+
+        let axis = match self.axis {
+            Ancestor => "ancestor",
+            AncestorOrSelf => "ancestor-or-self",
+            Attribute => "attribute",
+            Namespace => "namespace",
+            Child => "child",
+            Descendant => "descendant",
+            DescendantOrSelf => "descendant-or-self",
+            Parent => "parent",
+            PrecedingSibling => "preceding-sibling",
+            FollowingSibling => "following-sibling",
+            Preceding => "preceding",
+            Following => "following",
+            SelfAxis => "self",
+        };
+        write!(f, "{}::{}", axis, self.node_test)?;
+
+        for predicate in &self.predicates {
+            write!(f, "[{}]", predicate)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for NodeTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            NodeTest::Node => f.write_str("node()"),
+            NodeTest::Text => f.write_str("text()"),
+            NodeTest::Comment => f.write_str("comment()"),
+            NodeTest::ProcessingInstruction(None) => f.write_str("processing-instruction()"),
+            NodeTest::ProcessingInstruction(Some(ref target)) => {
+                f.write_str("processing-instruction(")?;
+                write_string(f, target)?;
+                f.write_str(")")
+            }
+            NodeTest::Name {
+                ref prefix,
+                ref local_part,
+            } => match *prefix {
+                Some(ref prefix) => write!(f, "{}:{}", prefix, local_part),
+                None => f.write_str(local_part),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Factory;
+
+    use super::{Expr, Step};
+    use crate::Value;
+
+    fn display(xpath: &str) -> String {
+        let xpath = Factory::new().build(xpath).expect("XPath should build");
+        let displayed = xpath.to_string();
+
+        let rebuilt = Factory::new()
+            .build(&displayed)
+            .expect("displayed XPath should build");
+        assert_eq!(xpath.to_ast(), rebuilt.to_ast(), "rebuilding {}", displayed);
+
+        displayed
+    }
+
+    #[test]
+    fn operators_are_parenthesized_only_when_needed() {
+        assert_eq!("1 + 2 * 3", display("1 + 2 * 3"));
+        assert_eq!("(1 + 2) * 3", display("(1 + 2) * 3"));
+        assert_eq!("1 - 2 - 3", display("1 - 2 - 3"));
+        assert_eq!("1 - (2 - 3)", display("1 - (2 - 3)"));
+        assert_eq!("1 = 2 != 3", display("1 = 2 != 3"));
+        assert_eq!("1 = (2 != 3)", display("1 = (2 != 3)"));
+        assert_eq!("1 < 2 = 2 > 1", display("1 < 2 = 2 > 1"));
+        assert_eq!("(1 or 0) and 1", display("(1 or 0) and 1"));
+        assert_eq!("1 or 0 and 1", display("1 or (0 and 1)"));
+        assert_eq!("-(1 + 2)", display("-(1+2)"));
+        assert_eq!("--1", display("- - 1"));
+        assert_eq!("1 - -2", display("1--2"));
+        assert_eq!("4 div 2 mod 3", display("4 div 2 mod 3"));
+        assert_eq!("-$a | $b", display("-($a | $b)"));
+    }
+
+    #[test]
+    fn chains_of_associative_operators_are_not_parenthesized() {
+        assert_eq!("$a or $b or $c or $d", display("$a or $b or $c or $d"));
+        assert_eq!("$a and ($b or $c)", display("$a and ($b or $c)"));
+        assert_eq!("child::a | child::b | child::c", display("a | b | c"));
+    }
+
+    #[test]
+    fn string_literals_are_quoted_to_suit_their_contents() {
+        assert_eq!("'plain'", display("'plain'"));
+        assert_eq!("\"it's\"", display("\"it's\""));
+        assert_eq!("'say \"hi\"'", display("'say \"hi\"'"));
+
+        let both = Expr::Literal(Value::String("it's \"here\"".into()));
+        assert_eq!("concat('it', \"'\", 's \"here\"')", both.to_string());
+    }
+
+    #[test]
+    fn abbreviated_steps_are_expanded() {
+        assert_eq!("self::node()", display("."));
+        assert_eq!("parent::node()/attribute::id", display("../@id"));
+        assert_eq!(
+            "/descendant-or-self::node()/child::a[position() = 1]",
+            display("//a[position() = 1]")
+        );
+        assert_eq!("/", display("/"));
+        assert_eq!("(/)[1]", display("(/)[1]"));
+        assert_eq!("(/) | child::a", display("/ | a"));
+        assert_eq!(
+            "child::ns:*/attribute::*/child::processing-instruction('x')",
+            display("ns:*/@*/processing-instruction('x')")
+        );
+    }
+
+    #[test]
+    fn filters_and_paths_keep_their_grouping() {
+        assert_eq!("$a[1][2]/child::b", display("$a[1][2]/b"));
+        assert_eq!("(child::a/child::b)[1]", display("(a/b)[1]"));
+        assert_eq!("(child::a/child::b)/child::c", display("(a/b)/c"));
+        assert_eq!(
+            "count((child::a | child::b)[last()], 2)",
+            display("count((a | b)[last()], 2)")
+        );
+        assert_eq!("$ns:v/child::text()", display("$ns:v/text()"));
+    }
+
+    #[test]
+    fn numbers_that_have_no_literal_are_written_as_expressions() {
+        let number = |n| Expr::Literal(Value::Number(n)).to_string();
+
+        assert_eq!("0.5", number(0.5));
+        assert_eq!("(-2)", number(-2.0));
+        assert_eq!("(0 div 0)", number(::std::f64::NAN));
+        assert_eq!("(-1 div 0)", number(::std::f64::NEG_INFINITY));
+
+        let step = Step {
+            axis: super::Axis::Child,
+            node_test: super::NodeTest::Text,
+            predicates: vec![],
+        };
+        assert_eq!("child::text()", step.to_string());
+    }
+}
@@ -14,7 +14,9 @@ pub enum PrincipalNodeType {
 /// A directed traversal of Nodes.
 pub trait AxisLike: fmt::Debug {
     /// Applies the given node test to the nodes selected by this axis,
-    /// adding matching nodes to the nodeset.
+    /// adding matching nodes to the nodeset. The nodes are in the
+    /// order of the axis, which is the order that predicates number
+    /// them in, so a reverse axis returns the nearest node first.
     fn select_nodes<'c, 'd>(
         &self,
         context: &context::Evaluation<'c, 'd>,
@@ -27,7 +29,10 @@ pub trait AxisLike: fmt::Debug {
     }
 }
 
+/// The axes that a location step can move along. `SelfAxis` is the
+/// `self` axis.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Axis {
     Ancestor,
     AncestorOrSelf,
@@ -44,6 +49,20 @@ pub enum Axis {
     SelfAxis,
 }
 
+impl Axis {
+    /// Whether the axis moves backwards in document order, so that
+    /// `[1]` in a predicate is the node nearest to the context node
+    /// rather than the first in the document.
+    pub fn is_reverse(self) -> bool {
+        match self {
+            Axis::Ancestor | Axis::AncestorOrSelf | Axis::Preceding | Axis::PrecedingSibling => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 struct CompleteNodeTest<'c, 'd> {
     context: &'c context::Evaluation<'c, 'd>,
     node_test: &'c dyn NodeTest,
@@ -87,14 +106,8 @@ impl AxisLike for Axis {
             }
             Namespace => {
                 if let Node::Element(ref e) = context.node {
-                    for ns in e.namespaces_in_scope() {
-                        let ns = Node::Namespace(nodeset::Namespace {
-                            parent: *e,
-                            prefix: ns.prefix(),
-                            uri: ns.uri(),
-                        });
-
-                        node_test.run(ns);
+                    for ns in nodeset::Namespace::in_scope(*e) {
+                        node_test.run(Node::Namespace(ns));
                     }
                 }
             }
@@ -129,11 +142,26 @@ impl AxisLike for Axis {
                     postorder_right_to_left(sibling, |n| node_test.run(n));
                 }
             }),
-            Following => node_and_each_parent(context.node, |node| {
-                for sibling in node.following_siblings() {
-                    preorder_left_to_right(sibling, |n| node_test.run(n));
+            Following => {
+                // Attribute and namespace nodes come before the
+                // children of their element, which are not their
+                // descendants.
+                match context.node {
+                    Node::Attribute(..) | Node::Namespace(..) => {
+                        if let Some(parent) = context.node.parent() {
+                            for child in parent.children() {
+                                preorder_left_to_right(child, |n| node_test.run(n));
+                            }
+                        }
+                    }
+                    _ => {}
                 }
-            }),
+                node_and_each_parent(context.node, |node| {
+                    for sibling in node.following_siblings() {
+                        preorder_left_to_right(sibling, |n| node_test.run(n));
+                    }
+                })
+            }
             SelfAxis => node_test.run(context.node),
         }
 
@@ -215,6 +243,7 @@ mod test {
 
     use super::Axis::*;
     use super::*;
+    use crate::ast;
 
     #[derive(Debug)]
     struct DummyNodeTest;
@@ -226,6 +255,10 @@ mod test {
         ) {
             result.add(context.node)
         }
+
+        fn to_ast(&self) -> ast::NodeTest {
+            ast::NodeTest::Node
+        }
     }
 
     fn execute<'n, N>(axis: Axis, node: N) -> OrderedNodes<'n>
@@ -256,6 +289,16 @@ mod test {
         assert_eq!(result, ordered_nodes![level1, level0]);
     }
 
+    #[test]
+    fn ancestor_of_root_is_empty() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let result = execute(Ancestor, doc.root());
+
+        assert_eq!(result, ordered_nodes![]);
+    }
+
     #[test]
     fn ancestor_or_self_also_includes_self() {
         let package = Package::new();
@@ -273,6 +316,43 @@ mod test {
         assert_eq!(result, ordered_nodes![level2, level1, level0]);
     }
 
+    #[test]
+    fn ancestor_or_self_of_attribute_includes_its_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let level0 = doc.root();
+        let level1 = doc.create_element("b");
+        let level2 = doc.create_element("c");
+        let attribute = level2.set_attribute_value("d", "value");
+
+        level0.append_child(level1);
+        level1.append_child(level2);
+
+        let result = execute(AncestorOrSelf, attribute);
+
+        assert_eq!(result, ordered_nodes![attribute, level2, level1, level0]);
+    }
+
+    #[test]
+    fn parent_of_attribute_and_namespace_is_their_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("a");
+        doc.root().append_child(element);
+        let attribute = element.set_attribute_value("b", "c");
+        let namespace = Node::Namespace(nodeset::Namespace {
+            parent: element,
+            prefix: "p",
+            uri: "urn:p",
+        });
+
+        assert_eq!(execute(Parent, attribute), ordered_nodes![element]);
+        assert_eq!(execute(Parent, namespace), ordered_nodes![element]);
+        assert_eq!(execute(Parent, doc.root()), ordered_nodes![]);
+    }
+
     #[test]
     fn descendant_includes_parents() {
         let package = Package::new();
@@ -307,6 +387,25 @@ mod test {
         assert_eq!(result, ordered_nodes![level0, level1, level2]);
     }
 
+    #[test]
+    fn only_backwards_axes_are_reverse() {
+        let reverse = [Ancestor, AncestorOrSelf, Preceding, PrecedingSibling];
+        let forward = [
+            Attribute,
+            Namespace,
+            Child,
+            Descendant,
+            DescendantOrSelf,
+            Parent,
+            FollowingSibling,
+            Following,
+            SelfAxis,
+        ];
+
+        assert!(reverse.iter().all(|a| a.is_reverse()));
+        assert!(forward.iter().all(|a| !a.is_reverse()));
+    }
+
     #[test]
     fn preceding_sibling_selects_in_reverse_document_order() {
         let package = Package::new();
@@ -426,4 +525,62 @@ mod test {
 
         assert_eq!(result, ordered_nodes![c[4], b[2], c[5], c[6]]);
     }
+
+    #[test]
+    fn following_excludes_descendants() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let PrecedingFollowing { b, c, .. } = PrecedingFollowing::new(doc);
+
+        let result = execute(Following, b[1]);
+
+        assert_eq!(result, ordered_nodes![b[2], c[5], c[6]]);
+    }
+
+    #[test]
+    fn following_of_attribute_includes_the_children_of_its_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let PrecedingFollowing { b, c, .. } = PrecedingFollowing::new(doc);
+        let attribute = b[1].set_attribute_value("id", "b1");
+
+        let result = execute(Following, attribute);
+
+        assert_eq!(result, ordered_nodes![c[2], c[3], c[4], b[2], c[5], c[6]]);
+    }
+
+    #[test]
+    fn following_does_not_recurse_with_document_depth() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let top = doc.create_element("top");
+        doc.root().append_child(top);
+        let mut parent = top;
+        let mut deepest = top;
+        for _ in 0..100_000 {
+            let element = doc.create_element("deep");
+            parent.append_child(element);
+            parent = element;
+            deepest = element;
+        }
+        let after = doc.create_element("after");
+        top.append_child(after);
+
+        let result = execute(Following, deepest);
+
+        assert_eq!(result, ordered_nodes![after]);
+    }
+
+    #[test]
+    fn preceding_of_attribute_excludes_its_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let PrecedingFollowing { b, c, midpoint } = PrecedingFollowing::new(doc);
+        let attribute = midpoint.set_attribute_value("id", "c3");
+
+        let result = execute(Preceding, attribute);
+
+        assert_eq!(result, ordered_nodes![c[2], c[1], c[0], b[0]]);
+    }
 }
@@ -1,21 +1,113 @@
 //! Support for the various types of contexts before and during XPath
 //! evaluation.
 
-use sxd_document::QName;
+use sxd_document::{dom, Package, QName};
+use typed_arena::Arena;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::iter;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::function;
-use crate::nodeset::{Node, OrderedNodes};
-use crate::{OwnedQName, Value};
+use crate::nodeset::{Node, Nodeset, OrderedNodes};
+use crate::{Factory, OwnedQName, Value, XPath};
 
 /// A mapping of names to XPath functions.
-type Functions = HashMap<OwnedQName, Box<dyn function::Function + 'static>>;
+///
+/// Functions are shared, so the table can be cloned cheaply and used
+/// from several threads, such as by giving each thread's context a
+/// clone with [`Context::set_functions`](struct.Context.html#method.set_functions).
+pub type Functions = HashMap<OwnedQName, Arc<dyn function::Function + Send + Sync + 'static>>;
 /// A mapping of names to XPath variables.
 type Variables<'d> = HashMap<OwnedQName, Value<'d>>;
+/// A mapping of names to values reported by `system-property`.
+type Properties<'d> = HashMap<OwnedQName, Value<'d>>;
+/// A mapping of unparsed entity names to their URIs.
+type UnparsedEntities = HashMap<String, String>;
 /// A mapping of namespace prefixes to namespace URIs.
 type Namespaces = HashMap<String, String>;
+/// A mapping of key names to XSLT-style key definitions.
+type Keys = HashMap<OwnedQName, Key>;
+/// The nodes of a document that match a key, grouped by key value.
+type KeyIndex<'d> = HashMap<String, Nodeset<'d>>;
+/// Key indexes that have been built so far, per key and document root.
+/// An index that is still being built has no value yet.
+type KeyIndexes<'d> = RefCell<HashMap<(OwnedQName, Node<'d>), Option<Rc<KeyIndex<'d>>>>>;
+/// The numbers given to the topmost node of each tree that has needed
+/// one, in the order they were first needed.
+type TreeNumbers<'d> = RefCell<HashMap<Node<'d>, usize>>;
+/// Handles calls to functions that have not been registered.
+type FunctionFallback = Box<
+    dyn for<'c, 'd> Fn(
+            QName<'_>,
+            &Evaluation<'c, 'd>,
+            Vec<Value<'d>>,
+        ) -> Option<Result<Value<'d>, function::Error>>
+        + 'static,
+>;
+/// Turns a URI into a parsed document for the `document` function.
+type DocumentResolver = Box<dyn Fn(&str) -> Result<Package, String> + 'static>;
+
+/// The deepest that evaluations may be nested inside one another,
+/// such as by `dyn:evaluate` evaluating an expression that calls
+/// `dyn:evaluate` again.
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+/// Owns the documents loaded by the `document` function.
+///
+/// Every node in a [`Nodeset`](../nodeset/struct.Nodeset.html)
+/// borrows from the document it belongs to, so documents loaded
+/// while evaluating an XPath must live at least as long as the
+/// document being queried. The pool is created before the
+/// [`Context`](struct.Context.html) and handed to
+/// [`Context::set_document_resolver`](struct.Context.html#method.set_document_resolver);
+/// loaded documents are freed when the pool is dropped.
+///
+/// A nodeset may then mix nodes from several documents. As with
+/// detached nodes, there is no document order between nodes of
+/// different documents.
+#[derive(Default)]
+pub struct DocumentPool(Arena<Package>);
+
+impl DocumentPool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// An XSLT-style key, as used by the `key` function.
+struct Key {
+    matching: XPath,
+    using: XPath,
+}
+
+/// Loads documents through the user's resolver, remembering each
+/// one so that the same URI always yields the same nodes.
+struct DocumentLoader<'d> {
+    pool: &'d DocumentPool,
+    resolver: DocumentResolver,
+    loaded: RefCell<HashMap<String, dom::Document<'d>>>,
+}
+
+impl<'d> DocumentLoader<'d> {
+    fn load(&self, uri: &str) -> Result<dom::Document<'d>, function::Error> {
+        if let Some(&doc) = self.loaded.borrow().get(uri) {
+            return Ok(doc);
+        }
+
+        let package =
+            (self.resolver)(uri).map_err(|what| function::Error::DocumentUnavailable {
+                uri: uri.into(),
+                what,
+            })?;
+        let doc = self.pool.0.alloc(package).as_document();
+        self.loaded.borrow_mut().insert(uri.into(), doc);
+        Ok(doc)
+    }
+}
 
 /// Contains the context in which XPath expressions are executed. The
 /// context contains functions, variables, and namespace mappings.
@@ -76,8 +168,19 @@ type Namespaces = HashMap<String, String>;
 ///
 pub struct Context<'d> {
     functions: Functions,
+    function_fallback: Option<FunctionFallback>,
     variables: Variables<'d>,
+    properties: Properties<'d>,
+    unparsed_entities: UnparsedEntities,
     namespaces: Namespaces,
+    default_element_namespace: Option<String>,
+    keys: Keys,
+    key_indexes: KeyIndexes<'d>,
+    tree_numbers: TreeNumbers<'d>,
+    documents: Option<DocumentLoader<'d>>,
+    scratch: Option<dom::Document<'d>>,
+    now: Option<String>,
+    factory: Factory,
 }
 
 impl<'d> Context<'d> {
@@ -92,18 +195,132 @@ impl<'d> Context<'d> {
     pub fn without_core_functions() -> Self {
         Context {
             functions: Default::default(),
+            function_fallback: None,
             variables: Default::default(),
+            properties: Default::default(),
+            unparsed_entities: Default::default(),
             namespaces: Default::default(),
+            default_element_namespace: None,
+            keys: Default::default(),
+            key_indexes: Default::default(),
+            tree_numbers: Default::default(),
+            documents: None,
+            scratch: None,
+            now: None,
+            factory: Factory::new(),
         }
     }
 
     /// Register a function within the context
+    ///
+    /// A name with a namespace URI, such as `("urn:example",
+    /// "replace")`, is called as `prefix:replace()` once a prefix has
+    /// been bound to the URI with
+    /// [`set_namespace`](#method.set_namespace). Calling a function
+    /// through an unbound prefix is an error that names the prefix.
     pub fn set_function<N, F>(&mut self, name: N, function: F)
     where
         N: Into<OwnedQName>,
-        F: function::Function + 'static,
+        F: function::Function + Send + Sync + 'static,
     {
-        self.functions.insert(name.into(), Box::new(function));
+        self.functions.insert(name.into(), Arc::new(function));
+    }
+
+    /// The functions registered within the context
+    pub fn functions(&self) -> &Functions {
+        &self.functions
+    }
+
+    /// Replaces all the functions registered within the context
+    pub fn set_functions(&mut self, functions: Functions) {
+        self.functions = functions;
+    }
+
+    /// Register a closure as a function within the context
+    ///
+    /// ### Examples
+    ///
+    /// ```
+    /// use sxd_document::Package;
+    /// use sxd_xpath::function::Args;
+    /// use sxd_xpath::{Context, Factory, Value};
+    ///
+    /// let package = Package::new();
+    /// let document = package.as_document();
+    ///
+    /// let mut context = Context::new();
+    /// context.set_function_fn("double", |_context, args| {
+    ///     let mut args = Args(args);
+    ///     args.exactly(1)?;
+    ///     let n = args.pop_number()?;
+    ///     Ok(Value::Number(n * 2.0))
+    /// });
+    ///
+    /// let xpath = Factory::new().build("double(21)").expect("Could not compile XPath");
+    /// let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+    ///
+    /// assert_eq!(Value::Number(42.0), value);
+    /// ```
+    pub fn set_function_fn<N, F>(&mut self, name: N, function: F)
+    where
+        N: Into<OwnedQName>,
+        F: for<'a, 'b> Fn(
+                &Evaluation<'a, 'b>,
+                Vec<Value<'b>>,
+            ) -> Result<Value<'b>, function::Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.set_function(name, function);
+    }
+
+    /// Handles calls to functions that have not been registered.
+    ///
+    /// The handler is only consulted when no function with the called
+    /// name has been registered. It receives the expanded name of the
+    /// function, the evaluation context and the evaluated arguments,
+    /// and returns `None` to decline the call, which is then reported
+    /// as an unknown function.
+    ///
+    /// ### Examples
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, Factory, Value};
+    ///
+    /// let package = parser::parse("<root a='1' b='2'/>").expect("failed to parse XML");
+    /// let document = package.as_document();
+    /// let node = document.root().children()[0];
+    ///
+    /// let mut context = Context::new();
+    /// context.set_namespace("attr", "urn:attributes");
+    /// context.set_function_fallback(|name, context, _args| {
+    ///     if name.namespace_uri() != Some("urn:attributes") {
+    ///         return None;
+    ///     }
+    ///     let element = context.node.element()?;
+    ///     let value = element.attribute_value(name.local_part())?;
+    ///     Some(Ok(Value::String(value.into())))
+    /// });
+    ///
+    /// let xpath = Factory::new()
+    ///     .build("concat(attr:a(), attr:b())")
+    ///     .expect("Could not compile XPath");
+    /// let value = xpath.evaluate(&context, node).expect("XPath evaluation failed");
+    ///
+    /// assert_eq!("12", value.string());
+    /// ```
+    pub fn set_function_fallback<F>(&mut self, handler: F)
+    where
+        F: for<'a, 'b> Fn(
+                QName<'_>,
+                &Evaluation<'a, 'b>,
+                Vec<Value<'b>>,
+            ) -> Option<Result<Value<'b>, function::Error>>
+            + 'static,
+    {
+        self.function_fallback = Some(Box::new(handler));
     }
 
     /// Register a variable within the context
@@ -115,10 +332,205 @@ impl<'d> Context<'d> {
         self.variables.insert(name.into(), value.into());
     }
 
+    /// Register a value to be reported by the `system-property`
+    /// function
+    pub fn set_system_property<N, V>(&mut self, name: N, value: V)
+    where
+        N: Into<OwnedQName>,
+        V: Into<Value<'d>>,
+    {
+        self.properties.insert(name.into(), value.into());
+    }
+
+    /// Register the URI of an unparsed entity, as reported by the
+    /// `unparsed-entity-uri` function. The document's DTD is not
+    /// consulted, so entities must be registered here.
+    pub fn set_unparsed_entity(&mut self, name: &str, uri: &str) {
+        self.unparsed_entities.insert(name.into(), uri.into());
+    }
+
     /// Register a namespace prefix within the context
     pub fn set_namespace(&mut self, prefix: &str, uri: &str) {
         self.namespaces.insert(prefix.into(), uri.into());
     }
+
+    /// Looks up the namespace URI registered for the given prefix
+    pub fn namespace_for(&self, prefix: &str) -> Option<&str> {
+        self.namespaces.get(prefix).map(String::as_str)
+    }
+
+    /// Makes unprefixed element name tests match elements in this
+    /// namespace instead of elements in no namespace, like XSLT 2.0's
+    /// `xpath-default-namespace`. Attribute name tests are not
+    /// affected. This is not XPath 1.0 behavior, so it is off unless
+    /// set.
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{Context, Factory, Value};
+    ///
+    /// let package = parser::parse("<feed xmlns='http://www.w3.org/2005/Atom'/>")
+    ///     .expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let mut context = Context::new();
+    /// context.set_default_element_namespace("http://www.w3.org/2005/Atom");
+    ///
+    /// let xpath = Factory::new().build("count(/feed)").expect("XPath is valid");
+    /// let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+    ///
+    /// assert_eq!(Value::Number(1.0), value);
+    /// ```
+    pub fn set_default_element_namespace(&mut self, uri: &str) {
+        self.default_element_namespace = Some(uri.into());
+    }
+
+    /// Register a key for use by the `key` function, which is added by
+    /// [`register_xslt_functions`](../function/fn.register_xslt_functions.html).
+    ///
+    /// `matching` is evaluated from the root of a document to select
+    /// the nodes that carry the key. `using` is then evaluated with
+    /// each of those nodes as the context node; the string-value of
+    /// each resulting node (or the string value of a non-nodeset
+    /// result) is a value the node can be looked up by. The index
+    /// for a document is built the first time the key is used with
+    /// it and kept for later evaluations, so it does not see changes
+    /// made to the document afterwards; call
+    /// [`clear_key_indexes`](#method.clear_key_indexes) once the
+    /// document has been changed.
+    ///
+    /// ### Examples
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::{function, Context, Factory};
+    ///
+    /// let package = parser::parse("<people><person id='a'>Alice</person></people>")
+    ///     .expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let factory = Factory::new();
+    /// let mut context = Context::new();
+    /// function::register_xslt_functions(&mut context);
+    /// context.set_key(
+    ///     "person-by-id",
+    ///     factory.build("//person").expect("Could not compile XPath"),
+    ///     factory.build("@id").expect("Could not compile XPath"),
+    /// );
+    ///
+    /// let xpath = factory.build("key('person-by-id', 'a')").expect("Could not compile XPath");
+    /// let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+    ///
+    /// assert_eq!("Alice", value.string());
+    /// ```
+    pub fn set_key<N>(&mut self, name: N, matching: XPath, using: XPath)
+    where
+        N: Into<OwnedQName>,
+    {
+        self.keys.insert(name.into(), Key { matching, using });
+        self.key_indexes.borrow_mut().clear();
+    }
+
+    /// Forgets the key indexes built so far, so that they are built
+    /// again from the current state of their documents the next time
+    /// a key is used.
+    pub fn clear_key_indexes(&self) {
+        self.key_indexes.borrow_mut().clear();
+    }
+
+    /// Register a document in which functions may create new nodes,
+    /// such as the results of the EXSLT `str:tokenize` function.
+    ///
+    /// Created nodes are appended beneath the document element of
+    /// this document, so they are in document order relative to each
+    /// other. As with any nodes from different documents, they have
+    /// no order relative to the nodes of the document being queried.
+    ///
+    /// Created nodes are never removed, so the scratch document grows
+    /// with every evaluation that creates nodes. Call
+    /// [`clear_scratch_document`](#method.clear_scratch_document)
+    /// once the results of earlier evaluations are no longer needed.
+    /// The memory of the nodes is only released when the package
+    /// holding the document is dropped, so a long-lived context should
+    /// also be given a fresh scratch document from time to time.
+    pub fn set_scratch_document(&mut self, document: dom::Document<'d>) {
+        self.scratch = Some(document);
+    }
+
+    /// Detaches every node created in the scratch document, so that
+    /// later evaluations start from an empty document. Nodes returned
+    /// by earlier evaluations remain usable but no longer belong to
+    /// the scratch document's tree.
+    pub fn clear_scratch_document(&mut self) {
+        if let Some(document) = self.scratch {
+            document.root().clear_children();
+        }
+    }
+
+    /// Fixes the date and time that date functions, such as the EXSLT
+    /// `date:date-time`, treat as the present. The value should be an
+    /// ISO 8601 date and time such as `2001-02-03T04:05:06Z`. Without
+    /// this, the system clock is used.
+    pub fn set_current_date_time(&mut self, date_time: &str) {
+        self.now = Some(date_time.into());
+    }
+
+    /// Sets the factory used by functions that build expressions while
+    /// evaluating, such as the EXSLT `dyn:evaluate`. Giving it the same
+    /// settings as the factory that built the calling expression, such
+    /// as its function signatures and nesting limit, means both are
+    /// parsed by the same rules. Without this, a default factory is
+    /// used.
+    pub fn set_factory(&mut self, factory: Factory) {
+        self.factory = factory;
+    }
+
+    /// Register the resolver used by the `document` function, which is
+    /// added by
+    /// [`register_xslt_functions`](../function/fn.register_xslt_functions.html).
+    ///
+    /// The resolver is called at most once per URI; the resulting
+    /// document is moved into `pool` and reused for the lifetime of
+    /// this context.
+    ///
+    /// ### Examples
+    ///
+    /// ```
+    /// use sxd_document::parser;
+    /// use sxd_xpath::context::DocumentPool;
+    /// use sxd_xpath::{function, Context, Factory};
+    ///
+    /// let package = parser::parse("<root/>").expect("failed to parse XML");
+    /// let document = package.as_document();
+    ///
+    /// let pool = DocumentPool::new();
+    /// let mut context = Context::new();
+    /// function::register_xslt_functions(&mut context);
+    /// context.set_document_resolver(&pool, |uri| match uri {
+    ///     "other.xml" => parser::parse("<item>hello</item>"),
+    ///     _ => parser::parse("not xml"),
+    /// });
+    ///
+    /// let xpath = Factory::new()
+    ///     .build("document('other.xml')//item")
+    ///     .expect("Could not compile XPath");
+    /// let value = xpath
+    ///     .evaluate(&context, document.root())
+    ///     .expect("XPath evaluation failed");
+    ///
+    /// assert_eq!("hello", value.string());
+    /// ```
+    pub fn set_document_resolver<F, E>(&mut self, pool: &'d DocumentPool, resolver: F)
+    where
+        F: Fn(&str) -> Result<Package, E> + 'static,
+        E: fmt::Display,
+    {
+        self.documents = Some(DocumentLoader {
+            pool,
+            resolver: Box::new(move |uri| resolver(uri).map_err(|e| e.to_string())),
+            loaded: Default::default(),
+        });
+    }
 }
 
 impl<'d> Default for Context<'d> {
@@ -145,22 +557,63 @@ pub struct Evaluation<'c, 'd> {
     pub position: usize,
     /// The context size
     pub size: usize,
+    /// The node the evaluation started from. Unlike `node`, this does
+    /// not change when entering steps or predicates.
+    pub current: Node<'d>,
     functions: &'c Functions,
+    function_fallback: Option<&'c FunctionFallback>,
     variables: &'c Variables<'d>,
+    properties: &'c Properties<'d>,
+    unparsed_entities: &'c UnparsedEntities,
     namespaces: &'c Namespaces,
+    default_element_namespace: Option<&'c str>,
+    keys: &'c Keys,
+    key_indexes: &'c KeyIndexes<'d>,
+    tree_numbers: &'c TreeNumbers<'d>,
+    documents: Option<&'c DocumentLoader<'d>>,
+    scratch: Option<dom::Document<'d>>,
+    now: Option<&'c str>,
+    factory: &'c Factory,
+    depth: usize,
 }
 
 impl<'c, 'd> Evaluation<'c, 'd> {
     /// Prepares the context used while evaluating the XPath expression
     pub fn new(context: &'c Context<'d>, node: Node<'d>) -> Evaluation<'c, 'd> {
-        Evaluation {
+        let evaluation = Evaluation {
             node,
+            current: node,
             functions: &context.functions,
+            function_fallback: context.function_fallback.as_ref(),
             variables: &context.variables,
+            properties: &context.properties,
+            unparsed_entities: &context.unparsed_entities,
             namespaces: &context.namespaces,
+            default_element_namespace: context
+                .default_element_namespace
+                .as_ref()
+                .map(String::as_str),
+            keys: &context.keys,
+            key_indexes: &context.key_indexes,
+            tree_numbers: &context.tree_numbers,
+            documents: context.documents.as_ref(),
+            scratch: context.scratch,
+            now: context.now.as_ref().map(String::as_str),
+            factory: &context.factory,
             position: 1,
             size: 1,
+            depth: 0,
+        };
+
+        // The tree being evaluated is numbered before any other, so
+        // its number does not depend on which nodes are asked about.
+        let mut top = node;
+        while let Some(parent) = top.parent() {
+            top = parent;
         }
+        evaluation.tree_number(top);
+
+        evaluation
     }
 
     /// Creates a new context node using the provided node
@@ -174,11 +627,76 @@ impl<'c, 'd> Evaluation<'c, 'd> {
         }
     }
 
+    /// The date and time registered with
+    /// [`Context::set_current_date_time`](struct.Context.html#method.set_current_date_time),
+    /// if any.
+    pub fn fixed_date_time(&self) -> Option<&'c str> {
+        self.now
+    }
+
+    /// The factory registered with
+    /// [`Context::set_factory`](struct.Context.html#method.set_factory),
+    /// for functions that build expressions.
+    pub fn factory(&self) -> &'c Factory {
+        self.factory
+    }
+
+    /// Prepares to evaluate another expression within this one, with
+    /// the same context node, position and size. Returns `None` if
+    /// that would nest evaluations more than [`MAX_NESTING_DEPTH`][]
+    /// deep.
+    ///
+    /// [`MAX_NESTING_DEPTH`]: constant.MAX_NESTING_DEPTH.html
+    pub fn nested(&self) -> Option<Evaluation<'c, 'd>> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return None;
+        }
+        Some(Evaluation {
+            depth: self.depth + 1,
+            ..*self
+        })
+    }
+
+    /// The [string-value][] of the context node, used by functions
+    /// that operate on the context node when called without an
+    /// argument.
+    ///
+    /// [string-value]: https://www.w3.org/TR/xpath/#dt-string-value
+    pub fn string_value(&self) -> String {
+        self.node.string_value()
+    }
+
     /// Looks up the function with the given name
     pub fn function_for_name(&self, name: QName<'_>) -> Option<&'c dyn function::Function> {
         // FIXME: remove allocation
         let name = name.into();
-        self.functions.get(&name).map(AsRef::as_ref)
+        self.functions
+            .get(&name)
+            .map(|f| &**f as &dyn function::Function)
+    }
+
+    /// Checks if a handler for unregistered functions has been set
+    /// with
+    /// [`Context::set_function_fallback`](struct.Context.html#method.set_function_fallback).
+    pub(crate) fn has_function_fallback(&self) -> bool {
+        self.function_fallback.is_some()
+    }
+
+    /// Offers a call to an unregistered function to the fallback
+    /// handler. Returns `None` if there is no handler or it declines
+    /// the call.
+    pub(crate) fn call_function_fallback(
+        &self,
+        name: QName<'_>,
+        args: Vec<Value<'d>>,
+    ) -> Option<Result<Value<'d>, function::Error>> {
+        self.function_fallback
+            .and_then(|fallback| fallback(name, self, args))
+    }
+
+    /// Checks if a function with the given name has been registered
+    pub fn has_function(&self, name: &OwnedQName) -> bool {
+        self.functions.contains_key(name)
     }
 
     /// Looks up the value of the variable
@@ -188,11 +706,210 @@ impl<'c, 'd> Evaluation<'c, 'd> {
         self.variables.get(&name)
     }
 
+    /// Looks up the value of the system property
+    pub fn system_property(&self, name: &OwnedQName) -> Option<&Value<'d>> {
+        self.properties.get(name)
+    }
+
+    /// Looks up the URI of the unparsed entity
+    pub fn unparsed_entity_uri(&self, name: &str) -> Option<&str> {
+        self.unparsed_entities.get(name).map(String::as_str)
+    }
+
     /// Looks up the namespace URI for the given prefix
-    pub fn namespace_for(&self, prefix: &str) -> Option<&str> {
+    pub fn namespace_for(&self, prefix: &str) -> Option<&'c str> {
         self.namespaces.get(prefix).map(String::as_str)
     }
 
+    /// The namespace registered with
+    /// [`Context::set_default_element_namespace`](struct.Context.html#method.set_default_element_namespace),
+    /// if any.
+    pub fn default_element_namespace(&self) -> Option<&'c str> {
+        self.default_element_namespace
+    }
+
+    /// The prefixes registered with
+    /// [`Context::set_namespace`](struct.Context.html#method.set_namespace)
+    /// and the namespace URIs they are bound to, in no particular
+    /// order.
+    pub fn namespaces(&self) -> impl Iterator<Item = (&'c str, &'c str)> {
+        self.namespaces
+            .iter()
+            .map(|(prefix, uri)| (prefix.as_str(), uri.as_str()))
+    }
+
+    /// A number that tells apart the tree whose topmost node is
+    /// `top` from every other tree, such as the trees of other
+    /// documents or of nodes not yet added to a document. Trees are
+    /// numbered from 0 in the order they are first asked about, and
+    /// keep their number for the lifetime of the context. The tree
+    /// of the node an evaluation starts from is asked about as soon
+    /// as the evaluation is created.
+    pub fn tree_number(&self, top: Node<'d>) -> usize {
+        let mut numbers = self.tree_numbers.borrow_mut();
+        let next = numbers.len();
+        *numbers.entry(top).or_insert(next)
+    }
+
+    /// Finds the nodes in the context node's document that have the
+    /// given value for the named key.
+    pub fn nodes_with_key(
+        &self,
+        name: &OwnedQName,
+        value: &str,
+    ) -> Result<Nodeset<'d>, function::Error> {
+        let root = Node::Root(self.node.document().root());
+        let index_key = (name.clone(), root);
+
+        let existing = self.key_indexes.borrow().get(&index_key).cloned();
+        let index = match existing {
+            Some(Some(index)) => index,
+            Some(None) => return Err(function::Error::RecursiveKey { name: name.clone() }),
+            None => {
+                let key = self
+                    .keys
+                    .get(name)
+                    .ok_or_else(|| function::Error::UnknownKey { name: name.clone() })?;
+                // The index is built without holding the borrow, as
+                // the key's expressions may themselves call `key`.
+                // Marking it as being built stops them from using
+                // this same index.
+                self.key_indexes
+                    .borrow_mut()
+                    .insert(index_key.clone(), None);
+                let index = match self.build_key_index(key, root) {
+                    Ok(index) => Rc::new(index),
+                    Err(e) => {
+                        self.key_indexes.borrow_mut().remove(&index_key);
+                        return Err(e);
+                    }
+                };
+                self.key_indexes
+                    .borrow_mut()
+                    .insert(index_key, Some(index.clone()));
+                index
+            }
+        };
+
+        Ok(index.get(value).cloned().unwrap_or_else(Nodeset::new))
+    }
+
+    fn build_key_index(&self, key: &Key, root: Node<'d>) -> Result<KeyIndex<'d>, function::Error> {
+        fn evaluate<'c, 'd>(
+            xpath: &XPath,
+            context: &Evaluation<'c, 'd>,
+        ) -> Result<Value<'d>, function::Error> {
+            xpath
+                .0
+                .evaluate(context)
+                .map_err(|e| function::Error::Other {
+                    what: e.to_string(),
+                })
+        }
+
+        let top = Evaluation {
+            node: root,
+            position: 1,
+            size: 1,
+            ..*self
+        };
+        let matched = match evaluate(&key.matching, &top)? {
+            Value::Nodeset(nodes) => nodes,
+            _ => {
+                return Err(function::Error::Other {
+                    what: "the match expression of a key must select a nodeset".into(),
+                })
+            }
+        };
+
+        let mut index = KeyIndex::new();
+        for node in matched.iter() {
+            let values = match evaluate(&key.using, &top.new_context_for(node))? {
+                Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+                other => vec![other.into_string()],
+            };
+            for value in values {
+                index.entry(value).or_default().add(node);
+            }
+        }
+        Ok(index)
+    }
+
+    /// The document in which functions may create new nodes.
+    ///
+    /// Nodes created here live as long as the document being queried,
+    /// so a function can return them in a `Value::Nodeset`. Append
+    /// them to [`scratch_container`](#method.scratch_container) to
+    /// give them a document order.
+    pub fn scratch_document(&self) -> Result<dom::Document<'d>, function::Error> {
+        self.scratch.ok_or(function::Error::NoScratchDocument)
+    }
+
+    /// The element of the scratch document that created nodes should
+    /// be appended to, created the first time it is needed.
+    ///
+    /// A document has a single document element, so keeping created
+    /// nodes beneath it puts them in document order: each node
+    /// follows the nodes appended before it, including those created
+    /// by earlier calls. Like any nodes from different documents, they
+    /// have no order relative to the nodes of the document being
+    /// queried.
+    ///
+    /// ### Examples
+    ///
+    /// ```
+    /// use sxd_document::{parser, Package};
+    /// use sxd_xpath::nodeset::Nodeset;
+    /// use sxd_xpath::{Context, Factory, Value};
+    ///
+    /// let package = parser::parse("<root/>").expect("failed to parse XML");
+    /// let document = package.as_document();
+    /// let scratch = Package::new();
+    ///
+    /// let mut context = Context::new();
+    /// context.set_scratch_document(scratch.as_document());
+    /// context.set_function_fn("words", |context, _args| {
+    ///     let doc = context.scratch_document()?;
+    ///     let container = context.scratch_container()?;
+    ///     let mut nodes = Nodeset::new();
+    ///     for word in &["hello", "world"] {
+    ///         let text = doc.create_text(word);
+    ///         container.append_child(text);
+    ///         nodes.add(text);
+    ///     }
+    ///     Ok(Value::Nodeset(nodes))
+    /// });
+    ///
+    /// let xpath = Factory::new().build("words()[2]").expect("Could not compile XPath");
+    /// let value = xpath.evaluate(&context, document.root()).expect("XPath evaluation failed");
+    ///
+    /// assert_eq!("world", value.string());
+    /// ```
+    pub fn scratch_container(&self) -> Result<dom::Element<'d>, function::Error> {
+        let doc = self.scratch_document()?;
+        let existing = doc
+            .root()
+            .children()
+            .into_iter()
+            .filter_map(|c| c.element())
+            .next();
+
+        Ok(existing.unwrap_or_else(|| {
+            let element = doc.create_element("scratch");
+            doc.root().append_child(element);
+            element
+        }))
+    }
+
+    /// Loads the document at the given URI using the resolver
+    /// registered with the context.
+    pub fn load_document(&self, uri: &str) -> Result<dom::Document<'d>, function::Error> {
+        match self.documents {
+            Some(documents) => documents.load(uri),
+            None => Err(function::Error::NoDocumentResolver),
+        }
+    }
+
     /// Yields a new `Evaluation` context for each node in the nodeset.
     pub fn new_contexts_for(self, nodes: OrderedNodes<'d>) -> EvaluationNodesetIter<'c, 'd> {
         let sz = nodes.size();
@@ -0,0 +1,265 @@
+//! Conversions between the XPath value types.
+//!
+//! These follow the rules of the [`string`][], [`number`][] and
+//! [`boolean`][] functions and are what the built-in functions use.
+//! Custom [`Function`](../function/trait.Function.html)s can call them
+//! to treat their arguments the same way.
+//!
+//! ```
+//! use sxd_xpath::{convert, Value};
+//!
+//! assert_eq!(12.5, convert::to_number(&Value::String(" 12.5 ".into())));
+//! assert_eq!("-Infinity", convert::to_string(&Value::Number(-1.0 / 0.0)));
+//! assert!(!convert::to_boolean(&Value::Number(::std::f64::NAN)));
+//! ```
+//!
+//! [`string`]: https://www.w3.org/TR/xpath/#function-string
+//! [`number`]: https://www.w3.org/TR/xpath/#function-number
+//! [`boolean`]: https://www.w3.org/TR/xpath/#function-boolean
+
+use std::borrow::ToOwned;
+
+use crate::Value;
+
+/// Converts a value to a string.
+///
+/// A nodeset becomes the string-value of its first node in document
+/// order, or the empty string when it is empty. Numbers are formatted
+/// by [`number_to_string`](fn.number_to_string.html).
+pub fn to_string(value: &Value<'_>) -> String {
+    match *value {
+        Value::Boolean(b) => boolean_to_string(b).to_owned(),
+        Value::Number(n) => number_to_string(n),
+        Value::String(ref s) => s.clone(),
+        Value::Nodeset(ref ns) => match ns.document_order_first() {
+            Some(n) => n.string_value(),
+            None => String::new(),
+        },
+    }
+}
+
+/// Converts a value to a number.
+///
+/// Strings, and the string-value of a nodeset, are parsed by
+/// [`string_to_number`](fn.string_to_number.html). `true` is 1 and
+/// `false` is 0.
+pub fn to_number(value: &Value<'_>) -> f64 {
+    match *value {
+        Value::Boolean(b) => {
+            if b {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        Value::Number(n) => n,
+        Value::String(ref s) => string_to_number(s),
+        Value::Nodeset(..) => string_to_number(&to_string(value)),
+    }
+}
+
+/// Converts a value to a boolean.
+///
+/// Numbers are true unless they are zero or NaN, strings and
+/// nodesets are true unless they are empty.
+pub fn to_boolean(value: &Value<'_>) -> bool {
+    match *value {
+        Value::Boolean(b) => b,
+        Value::Number(n) => n != 0.0 && !n.is_nan(),
+        Value::String(ref s) => !s.is_empty(),
+        Value::Nodeset(ref ns) => ns.size() > 0,
+    }
+}
+
+/// Formats a boolean as `true` or `false`.
+pub fn boolean_to_string(b: bool) -> &'static str {
+    if b {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+/// Formats a number the way XPath does.
+///
+/// NaN is `NaN`, infinities are `Infinity` and `-Infinity`, both
+/// zeroes are `0`, and integers have no decimal point. Other numbers
+/// are written with as few digits as distinguish them from every
+/// other number, and never with an exponent.
+pub fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_owned()
+    } else if n.is_infinite() {
+        if n.signum() < 0.0 {
+            "-Infinity".to_owned()
+        } else {
+            "Infinity".to_owned()
+        }
+    } else if n == 0.0 {
+        // Covers negative zero, which XPath formats as "0"
+        "0".to_owned()
+    } else {
+        // Rust writes the shortest digits that round-trip and never
+        // uses an exponent, as XPath requires
+        n.to_string()
+    }
+}
+
+/// Parses a string as an XPath [`Number`][number].
+///
+/// Only an optional minus sign followed by digits with an optional
+/// decimal point, surrounded by optional whitespace, is accepted.
+/// Anything else, including exponents, a leading plus sign or the
+/// words `Infinity` and `NaN`, becomes NaN.
+///
+/// [number]: https://www.w3.org/TR/xpath/#NT-Number
+pub fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n');
+    let unsigned = if s.starts_with('-') { &s[1..] } else { s };
+
+    let mut parts = unsigned.splitn(2, '.');
+    let integral = parts.next().unwrap_or("");
+    let fractional = parts.next();
+
+    let all_digits = |p: &str| p.bytes().all(|b| b.is_ascii_digit());
+    let valid = all_digits(integral)
+        && fractional.map_or(true, all_digits)
+        && (!integral.is_empty() || fractional.map_or(false, |f| !f.is_empty()));
+
+    if valid {
+        s.parse().unwrap_or(::std::f64::NAN)
+    } else {
+        ::std::f64::NAN
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64;
+
+    use sxd_document::Package;
+
+    use crate::nodeset::Nodeset;
+    use crate::Value;
+
+    use super::{number_to_string, to_boolean, to_number, to_string};
+
+    /// The expected string, number and boolean for a value. A `None`
+    /// number stands for NaN.
+    struct Row<'d> {
+        value: Value<'d>,
+        string: &'static str,
+        number: Option<f64>,
+        boolean: bool,
+    }
+
+    fn row<'d, V>(value: V, string: &'static str, number: Option<f64>, boolean: bool) -> Row<'d>
+    where
+        V: Into<Value<'d>>,
+    {
+        Row {
+            value: value.into(),
+            string,
+            number,
+            boolean,
+        }
+    }
+
+    fn check(rows: &[Row<'_>]) {
+        for row in rows {
+            assert_eq!(
+                row.string,
+                to_string(&row.value),
+                "string of {:?}",
+                row.value
+            );
+            match row.number {
+                Some(n) => assert_eq!(n, to_number(&row.value), "number of {:?}", row.value),
+                None => assert!(to_number(&row.value).is_nan(), "number of {:?}", row.value),
+            }
+            assert_eq!(
+                row.boolean,
+                to_boolean(&row.value),
+                "boolean of {:?}",
+                row.value
+            );
+        }
+    }
+
+    #[test]
+    fn conversions_of_literal_values() {
+        check(&[
+            row(true, "true", Some(1.0), true),
+            row(false, "false", Some(0.0), false),
+            row(1.0, "1", Some(1.0), true),
+            row(-2.5, "-2.5", Some(-2.5), true),
+            row(0.0, "0", Some(0.0), false),
+            row(-0.0, "0", Some(0.0), false),
+            row(f64::NAN, "NaN", None, false),
+            row(f64::INFINITY, "Infinity", Some(f64::INFINITY), true),
+            row(
+                f64::NEG_INFINITY,
+                "-Infinity",
+                Some(f64::NEG_INFINITY),
+                true,
+            ),
+            row("", "", None, false),
+            row(" 42 ", " 42 ", Some(42.0), true),
+            row("-.5", "-.5", Some(-0.5), true),
+            row("1e3", "1e3", None, true),
+            row("+1", "+1", None, true),
+            row("false", "false", None, true),
+            row("0", "0", Some(0.0), true),
+        ]);
+    }
+
+    #[test]
+    fn numbers_are_formatted_without_exponents() {
+        let rows: &[(f64, &str)] = &[
+            (12.0, "12"),
+            (-12.0, "-12"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (-2.0 / 3.0, "-0.6666666666666666"),
+            (1e21, "1000000000000000000000"),
+            (-1.5e22, "-15000000000000000000000"),
+            (1e-7, "0.0000001"),
+            (1.25e-10, "0.000000000125"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (f64::MAX, &format!("17976931348623157{}", "0".repeat(292))),
+            (5e-324, &format!("0.{}5", "0".repeat(323))),
+            (-0.0, "0"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ];
+
+        for &(n, expected) in rows {
+            assert_eq!(expected, number_to_string(n), "formatting {:e}", n);
+            assert!(!number_to_string(n).contains('e'));
+        }
+    }
+
+    #[test]
+    fn conversions_of_nodesets() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let root = doc.create_element("root");
+        let first = doc.create_element("first");
+        let second = doc.create_element("second");
+        let empty = doc.create_element("empty");
+        first.append_child(doc.create_text("12"));
+        second.append_child(doc.create_text("abc"));
+        root.append_child(first);
+        root.append_child(second);
+        root.append_child(empty);
+        doc.root().append_child(root);
+
+        check(&[
+            row(Nodeset::new(), "", None, false),
+            row(nodeset![second, first], "12", Some(12.0), true),
+            row(nodeset![second], "abc", None, true),
+            row(nodeset![empty], "", None, true),
+            row(nodeset![root], "12abc", None, true),
+        ]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Shows where in an XPath something went wrong, as a line of the
+//! XPath with carets underneath.
+
+/// The most columns of the XPath that are shown.
+const WIDTH: usize = 60;
+
+/// How many columns before the marked text are kept when the XPath
+/// has to be shortened.
+const CONTEXT: usize = 20;
+
+const ELLIPSIS: &str = "...";
+
+/// The columns a character takes up in a terminal. Combining marks
+/// take none and East Asian wide characters take two.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn width(chars: &[char]) -> usize {
+    chars.iter().cloned().map(char_width).sum()
+}
+
+/// Returns the line to show and the line of carets that goes beneath
+/// it, marking the `length` bytes at `offset`. An empty span is
+/// marked with a single caret.
+pub fn excerpt(source: &str, offset: usize, length: usize) -> (String, String) {
+    // Whitespace such as tabs would throw the carets out of line
+    let chars: Vec<char> = source
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    let index_of = |byte: usize| source[..byte].chars().count();
+
+    let first = index_of(offset);
+    let last = index_of(offset + length);
+
+    let (start, end) = if width(&chars) <= WIDTH {
+        (0, chars.len())
+    } else {
+        window(&chars, first)
+    };
+
+    let mut line = String::new();
+    if start > 0 {
+        line.push_str(ELLIPSIS);
+    }
+    let indent = line.len() + width(&chars[start..first]);
+    line.extend(&chars[start..end]);
+    if end < chars.len() {
+        line.push_str(ELLIPSIS);
+    }
+
+    let marked = width(&chars[first..last.min(end)]).max(1);
+    let marker = format!("{}{}", " ".repeat(indent), "^".repeat(marked));
+
+    (line, marker)
+}
+
+/// Picks the characters to show around `first` when they do not all
+/// fit.
+fn window(chars: &[char], first: usize) -> (usize, usize) {
+    let mut start = first;
+    while start > 0 && width(&chars[start - 1..first]) <= CONTEXT {
+        start -= 1;
+    }
+
+    let mut end = start;
+    while end < chars.len() && width(&chars[start..=end]) <= WIDTH {
+        end += 1;
+    }
+
+    // Show more of the start when the end has been reached
+    if end == chars.len() {
+        while start > 0 && width(&chars[start - 1..end]) <= WIDTH {
+            start -= 1;
+        }
+    }
+
+    (start, end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::excerpt;
+
+    fn render(source: &str, offset: usize, length: usize) -> String {
+        let (line, marker) = excerpt(source, offset, length);
+        format!("{}\n{}", line, marker)
+    }
+
+    #[test]
+    fn marks_the_span_beneath_the_source() {
+        assert_eq!("1 + * 2\n      ^", render("1 + * 2", 6, 1));
+        assert_eq!("count(a, b)\n^^^^^", render("count(a, b)", 0, 5));
+    }
+
+    #[test]
+    fn marks_the_end_of_the_source_with_one_caret() {
+        assert_eq!("//foo[bar\n         ^", render("//foo[bar", 9, 0));
+    }
+
+    #[test]
+    fn aligns_by_display_width() {
+        assert_eq!("'été' = +\n        ^", render("'été' = +", 10, 1));
+        assert_eq!("'漢字' = +\n         ^", render("'漢字' = +", 11, 1));
+        assert_eq!("'e\u{301}' = +\n      ^", render("'e\u{301}' = +", 8, 1));
+    }
+
+    #[test]
+    fn replaces_whitespace_so_the_carets_line_up() {
+        assert_eq!("a = #\n    ^", render("a\n=\t#", 4, 1));
+    }
+
+    #[test]
+    fn long_sources_are_shortened_around_the_span() {
+        let source = format!("{} + * {}", "a".repeat(100), "b".repeat(100));
+
+        let (line, marker) = excerpt(&source, 103, 1);
+        assert_eq!(
+            format!("...{} + * {}...", "a".repeat(17), "b".repeat(38)),
+            line
+        );
+        assert_eq!(format!("{}^", " ".repeat(23)), marker);
+
+        let (line, marker) = excerpt(&source, source.len(), 0);
+        assert_eq!(format!("...{}", "b".repeat(60)), line);
+        assert_eq!(format!("{}^", " ".repeat(63)), marker);
+    }
+}
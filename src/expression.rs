@@ -1,8 +1,9 @@
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use std::collections::HashSet;
 use std::fmt;
 use sxd_document::QName;
 
+use crate::ast::{self, BinaryOperator};
 use crate::axis::{Axis, AxisLike};
 use crate::context;
 use crate::function;
@@ -22,8 +23,11 @@ pub enum Error {
     UnknownVariable { name: OwnedPrefixedName },
     #[snafu(display("unknown namespace prefix {}", prefix))]
     UnknownNamespace { prefix: String },
-    #[snafu(display("error while evaluating function: {}", source))]
-    FunctionEvaluation { source: function::Error },
+    #[snafu(display("error while evaluating function {:?}: {}", name, source))]
+    FunctionEvaluation {
+        name: OwnedPrefixedName,
+        source: function::Error,
+    },
 }
 
 fn value_into_nodeset(v: Value<'_>) -> Result<Nodeset<'_>, Error> {
@@ -46,8 +50,23 @@ fn value_into_ordered_nodes(v: Value<'_>) -> Result<OrderedNodes<'_>, Error> {
     }
 }
 
+/// The variables and functions that an expression refers to, by the
+/// names written in the expression.
+#[derive(Debug, Default)]
+pub struct References {
+    pub variables: HashSet<OwnedPrefixedName>,
+    pub functions: HashSet<OwnedPrefixedName>,
+}
+
 pub trait Expression: fmt::Debug {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error>;
+
+    /// Adds the variables and functions referred to by this
+    /// expression and all of its subexpressions.
+    fn collect_references(&self, references: &mut References);
+
+    /// Copies the structure of this expression into the public AST.
+    fn to_ast(&self) -> ast::Expr;
 }
 
 impl<T: ?Sized> Expression for Box<T>
@@ -57,15 +76,32 @@ where
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         (**self).evaluate(context)
     }
+
+    fn collect_references(&self, references: &mut References) {
+        (**self).collect_references(references)
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        (**self).to_ast()
+    }
 }
 
 pub type SubExpression = Box<dyn Expression + 'static>;
 
+fn binary_ast(operator: BinaryOperator, left: &SubExpression, right: &SubExpression) -> ast::Expr {
+    ast::Expr::Binary {
+        operator,
+        left: Box::new(left.to_ast()),
+        right: Box::new(right.to_ast()),
+    }
+}
+
 macro_rules! binary_constructor(
     ($t:ident) => (
         impl $t {
+            #[allow(clippy::new_ret_no_self)]
             pub fn new(left: SubExpression, right: SubExpression) -> SubExpression {
-                Box::new($t{left: left, right: right})
+                Box::new($t { left, right })
             }
         }
     );
@@ -85,6 +121,15 @@ impl Expression for And {
         let v = left && self.right.evaluate(context)?.boolean();
         Ok(Boolean(v))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(BinaryOperator::And, &self.left, &self.right)
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -95,6 +140,12 @@ impl Expression for ContextNode {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         Ok(Value::Nodeset(nodeset![context.node]))
     }
+
+    fn collect_references(&self, _: &mut References) {}
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::ContextNode
+    }
 }
 
 #[derive(Debug)]
@@ -106,9 +157,15 @@ pub struct Equal {
 binary_constructor!(Equal);
 
 impl Equal {
-    fn boolean_evaluate<'c, 'd>(
+    /// Compares the two sides following the rules for `=` when `equal`
+    /// is true and for `!=` when it is false. A nodeset is compared
+    /// node by node, so `!=` is not simply the opposite of `=`: both
+    /// are true when a nodeset holds some nodes that match and some
+    /// that do not, and both are false when a nodeset is empty.
+    fn compare<'c, 'd>(
         &self,
         context: &context::Evaluation<'c, 'd>,
+        equal: bool,
     ) -> Result<bool, Error> {
         let left_val = self.left.evaluate(context)?;
         let right_val = self.right.evaluate(context)?;
@@ -126,24 +183,32 @@ impl Equal {
         }
 
         let v = match (&left_val, &right_val) {
-            (&Value::Nodeset(ref left_nodes), &Value::Nodeset(ref right_nodes)) => {
+            (Value::Nodeset(left_nodes), Value::Nodeset(right_nodes)) => {
                 let left_strings = str_vals(left_nodes);
                 let right_strings = str_vals(right_nodes);
-                !left_strings.is_disjoint(&right_strings)
+                if equal {
+                    !left_strings.is_disjoint(&right_strings)
+                } else {
+                    // Some pair differs unless both sides hold the
+                    // same single value.
+                    !left_strings.is_empty()
+                        && !right_strings.is_empty()
+                        && (left_strings.len() > 1 || left_strings != right_strings)
+                }
             }
             (&Value::Nodeset(ref nodes), &Number(val))
             | (&Number(val), &Value::Nodeset(ref nodes)) => {
-                let numbers = num_vals(nodes);
-                numbers.iter().any(|n| *n == val)
+                num_vals(nodes).into_iter().any(|n| (n == val) == equal)
             }
             (&Value::Nodeset(ref nodes), &Value::String(ref val))
             | (&Value::String(ref val), &Value::Nodeset(ref nodes)) => {
-                let strings = str_vals(nodes);
-                strings.contains(val)
+                str_vals(nodes).iter().any(|s| (s == val) == equal)
             }
-            (&Boolean(_), _) | (_, &Boolean(_)) => left_val.boolean() == right_val.boolean(),
-            (&Number(_), _) | (_, &Number(_)) => left_val.number() == right_val.number(),
-            _ => left_val.string() == right_val.string(),
+            (&Boolean(_), _) | (_, &Boolean(_)) => {
+                (left_val.boolean() == right_val.boolean()) == equal
+            }
+            (&Number(_), _) | (_, &Number(_)) => (left_val.number() == right_val.number()) == equal,
+            _ => (left_val.string() == right_val.string()) == equal,
         };
 
         Ok(v)
@@ -152,7 +217,16 @@ impl Equal {
 
 impl Expression for Equal {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.boolean_evaluate(context).map(Boolean)
+        self.compare(context, true).map(Boolean)
+    }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(BinaryOperator::Equal, &self.left, &self.right)
     }
 }
 
@@ -162,6 +236,7 @@ pub struct NotEqual {
 }
 
 impl NotEqual {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(left: SubExpression, right: SubExpression) -> SubExpression {
         Box::new(NotEqual {
             equal: Equal { left, right },
@@ -171,7 +246,20 @@ impl NotEqual {
 
 impl Expression for NotEqual {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
-        self.equal.boolean_evaluate(context).map(|v| Boolean(!v))
+        self.equal.compare(context, false).map(Boolean)
+    }
+
+    fn collect_references(&self, references: &mut References) {
+        self.equal.left.collect_references(references);
+        self.equal.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(
+            BinaryOperator::NotEqual,
+            &self.equal.left,
+            &self.equal.right,
+        )
     }
 }
 
@@ -184,17 +272,40 @@ pub struct Function {
 impl Expression for Function {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         let name = resolve_prefixed_name(context, &self.name)?;
-        context
-            .function_for_name(name)
-            .context(UnknownFunction { name: &self.name })
-            .and_then(|fun| {
-                let args = self
-                    .arguments
-                    .iter()
-                    .map(|arg| arg.evaluate(context))
-                    .collect::<Result<_, _>>()?;
-                fun.evaluate(context, args).context(FunctionEvaluation)
-            })
+        let fun = context.function_for_name(name);
+        if fun.is_none() && !context.has_function_fallback() {
+            return UnknownFunction { name: &self.name }.fail();
+        }
+
+        let args = self
+            .arguments
+            .iter()
+            .map(|arg| arg.evaluate(context))
+            .collect::<Result<_, _>>()?;
+
+        match fun {
+            Some(fun) => fun
+                .evaluate(context, args)
+                .context(FunctionEvaluation { name: &self.name }),
+            None => context
+                .call_function_fallback(name, args)
+                .context(UnknownFunction { name: &self.name })?
+                .context(FunctionEvaluation { name: &self.name }),
+        }
+    }
+
+    fn collect_references(&self, references: &mut References) {
+        references.functions.insert(self.name.clone());
+        for argument in &self.arguments {
+            argument.collect_references(references);
+        }
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Function {
+            name: self.name.clone(),
+            arguments: self.arguments.iter().map(|a| a.to_ast()).collect(),
+        }
     }
 }
 
@@ -213,11 +324,18 @@ impl Expression for Literal {
     fn evaluate<'c, 'd>(&self, _: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         Ok(self.value.clone())
     }
+
+    fn collect_references(&self, _: &mut References) {}
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Literal(self.value.clone())
+    }
 }
 
 pub struct Math {
     left: SubExpression,
     right: SubExpression,
+    operator: BinaryOperator,
     operation: fn(f64, f64) -> f64,
 }
 
@@ -242,6 +360,7 @@ impl Math {
         Box::new(Math {
             left,
             right,
+            operator: BinaryOperator::Addition,
             operation: add,
         })
     }
@@ -250,6 +369,7 @@ impl Math {
         Box::new(Math {
             left,
             right,
+            operator: BinaryOperator::Subtraction,
             operation: subtract,
         })
     }
@@ -258,6 +378,7 @@ impl Math {
         Box::new(Math {
             left,
             right,
+            operator: BinaryOperator::Multiplication,
             operation: multiply,
         })
     }
@@ -266,6 +387,7 @@ impl Math {
         Box::new(Math {
             left,
             right,
+            operator: BinaryOperator::Division,
             operation: divide,
         })
     }
@@ -274,6 +396,7 @@ impl Math {
         Box::new(Math {
             left,
             right,
+            operator: BinaryOperator::Remainder,
             operation: modulus,
         })
     }
@@ -286,6 +409,15 @@ impl Expression for Math {
         let op = self.operation;
         Ok(Number(op(left.number(), right.number())))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(self.operator, &self.left, &self.right)
+    }
 }
 
 impl fmt::Debug for Math {
@@ -309,6 +441,14 @@ impl Expression for Negation {
             .evaluate(context)
             .map(|r| Number(-r.number()))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.expression.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Negation(Box::new(self.expression.to_ast()))
+    }
 }
 
 #[derive(Debug)]
@@ -325,6 +465,15 @@ impl Expression for Or {
         let v = left || self.right.evaluate(context)?.boolean();
         Ok(Boolean(v))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(BinaryOperator::Or, &self.left, &self.right)
+    }
 }
 
 #[derive(Debug)]
@@ -334,6 +483,7 @@ pub struct Path {
 }
 
 impl Path {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(start_point: SubExpression, steps: Vec<Step>) -> SubExpression {
         Box::new(Path { start_point, steps })
     }
@@ -350,6 +500,20 @@ impl Expression for Path {
 
         Ok(Value::Nodeset(result))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.start_point.collect_references(references);
+        for step in &self.steps {
+            step.collect_references(references);
+        }
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Path {
+            start: Box::new(self.start_point.to_ast()),
+            steps: self.steps.iter().map(Step::to_ast).collect(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -359,6 +523,7 @@ pub struct Filter {
 }
 
 impl Filter {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(node_selector: SubExpression, predicate: SubExpression) -> SubExpression {
         let predicate = Predicate {
             expression: predicate,
@@ -378,11 +543,24 @@ impl Expression for Filter {
             .and_then(|nodes| self.predicate.select(context, nodes))
             .map(|nodes| Value::Nodeset(nodes.into()))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.node_selector.collect_references(references);
+        self.predicate.expression.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Filter {
+            expression: Box::new(self.node_selector.to_ast()),
+            predicate: Box::new(self.predicate.expression.to_ast()),
+        }
+    }
 }
 
 pub struct Relational {
     pub left: SubExpression,
     pub right: SubExpression,
+    pub operator: BinaryOperator,
     pub operation: fn(f64, f64) -> bool,
 }
 
@@ -404,6 +582,7 @@ impl Relational {
         Box::new(Relational {
             left,
             right,
+            operator: BinaryOperator::LessThan,
             operation: less_than,
         })
     }
@@ -412,6 +591,7 @@ impl Relational {
         Box::new(Relational {
             left,
             right,
+            operator: BinaryOperator::LessThanOrEqual,
             operation: less_than_or_equal,
         })
     }
@@ -420,6 +600,7 @@ impl Relational {
         Box::new(Relational {
             left,
             right,
+            operator: BinaryOperator::GreaterThan,
             operation: greater_than,
         })
     }
@@ -428,6 +609,7 @@ impl Relational {
         Box::new(Relational {
             left,
             right,
+            operator: BinaryOperator::GreaterThanOrEqual,
             operation: greater_than_or_equal,
         })
     }
@@ -438,7 +620,53 @@ impl Expression for Relational {
         let left_val = self.left.evaluate(context)?;
         let right_val = self.right.evaluate(context)?;
         let op = self.operation;
-        Ok(Boolean(op(left_val.number(), right_val.number())))
+
+        fn num_vals(nodes: &Nodeset<'_>) -> Vec<f64> {
+            nodes
+                .iter()
+                .map(|n| Value::String(n.string_value()).number())
+                .collect()
+        }
+
+        // A comparison with a nodeset is true when it holds for any of
+        // its nodes. Comparing with a boolean converts the nodeset to
+        // a boolean first. Everything else is compared as numbers, so
+        // NaN is never less or greater than anything.
+        let v = match (&left_val, &right_val) {
+            (Value::Nodeset(left_nodes), Value::Nodeset(right_nodes)) => {
+                let right_numbers = num_vals(right_nodes);
+                num_vals(left_nodes)
+                    .into_iter()
+                    .any(|l| right_numbers.iter().any(|&r| op(l, r)))
+            }
+            (&Value::Nodeset(_), &Boolean(_)) | (&Boolean(_), &Value::Nodeset(_)) => {
+                let as_number = |b| if b { 1.0 } else { 0.0 };
+                op(
+                    as_number(left_val.boolean()),
+                    as_number(right_val.boolean()),
+                )
+            }
+            (Value::Nodeset(nodes), other) => {
+                let other = other.number();
+                num_vals(nodes).into_iter().any(|n| op(n, other))
+            }
+            (other, Value::Nodeset(nodes)) => {
+                let other = other.number();
+                num_vals(nodes).into_iter().any(|n| op(other, n))
+            }
+            _ => op(left_val.number(), right_val.number()),
+        };
+
+        Ok(Boolean(v))
+    }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(self.operator, &self.left, &self.right)
     }
 }
 
@@ -460,6 +688,12 @@ impl Expression for RootNode {
     fn evaluate<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
         Ok(Value::Nodeset(nodeset![context.node.document().root()]))
     }
+
+    fn collect_references(&self, _: &mut References) {}
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::RootNode
+    }
 }
 
 #[derive(Debug)]
@@ -486,8 +720,10 @@ impl Predicate {
     fn matches(&self, context: &context::Evaluation<'_, '_>) -> Result<bool, Error> {
         let value = self.expression.evaluate(context)?;
 
+        // A number means `position() = number`, which a fractional or
+        // NaN number never is.
         let v = match value {
-            Number(v) => context.position == v as usize,
+            Number(v) => context.position as f64 == v,
             _ => value.boolean(),
         };
 
@@ -536,6 +772,13 @@ where
         // This seems like a likely place where we could differ from
         // the spec, so thorough testing is key.
 
+        if let Some(prefix) = self.node_test.prefix() {
+            ensure!(
+                context.namespace_for(prefix).is_some(),
+                UnknownNamespace { prefix }
+            );
+        }
+
         let mut unique = Nodeset::new();
 
         for node in starting_nodes.iter() {
@@ -551,8 +794,31 @@ where
 
         Ok(unique)
     }
+
+    fn collect_references(&self, references: &mut References) {
+        for predicate in &self.predicates {
+            predicate.expression.collect_references(references);
+        }
+    }
 }
 
+impl Step {
+    fn to_ast(&self) -> ast::Step {
+        ast::Step {
+            axis: self.axis,
+            node_test: self.node_test.to_ast(),
+            predicates: self
+                .predicates
+                .iter()
+                .map(|p| p.expression.to_ast())
+                .collect(),
+        }
+    }
+}
+
+/// Combines two nodesets. A node selected by both sides appears once,
+/// and, like every nodeset, the result is put in document order by
+/// whatever uses it next, so the order of the operands does not matter.
 #[derive(Debug)]
 pub struct Union {
     pub left: SubExpression,
@@ -571,6 +837,15 @@ impl Expression for Union {
         left_nodes.extend(right_nodes);
         Ok(Value::Nodeset(left_nodes))
     }
+
+    fn collect_references(&self, references: &mut References) {
+        self.left.collect_references(references);
+        self.right.collect_references(references);
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        binary_ast(BinaryOperator::Union, &self.left, &self.right)
+    }
 }
 
 fn resolve_prefixed_name<'a>(
@@ -578,7 +853,7 @@ fn resolve_prefixed_name<'a>(
     name: &'a OwnedPrefixedName,
 ) -> Result<QName<'a>, Error> {
     // What about a "default" namespace?
-    let ns_uri = match name.prefix.as_ref().map(|p| &**p) {
+    let ns_uri = match name.prefix.as_ref().map(String::as_str) {
         None => None,
         Some(prefix) => match context.namespace_for(prefix) {
             None => return UnknownNamespace { prefix }.fail(),
@@ -603,6 +878,14 @@ impl Expression for Variable {
             .cloned()
             .context(UnknownVariable { name: &self.name })
     }
+
+    fn collect_references(&self, references: &mut References) {
+        references.variables.insert(self.name.clone());
+    }
+
+    fn to_ast(&self) -> ast::Expr {
+        ast::Expr::Variable(self.name.clone())
+    }
 }
 
 #[cfg(test)]
@@ -618,7 +901,7 @@ mod test {
     use crate::context::{self, Context};
     use crate::function;
     use crate::node_test::NodeTest;
-    use crate::nodeset::OrderedNodes;
+    use crate::nodeset::{Node, OrderedNodes};
     use crate::Value::{self, Boolean, Number, String};
 
     use super::*;
@@ -629,6 +912,12 @@ mod test {
         fn evaluate<'c, 'd>(&self, _: &context::Evaluation<'c, 'd>) -> Result<Value<'d>, Error> {
             panic!("Should never be called");
         }
+
+        fn collect_references(&self, _: &mut References) {}
+
+        fn to_ast(&self) -> ast::Expr {
+            panic!("Should never be called");
+        }
     }
 
     struct Setup<'d> {
@@ -854,6 +1143,90 @@ mod test {
         assert_eq!(res, Ok(Boolean(true)));
     }
 
+    #[test]
+    fn expression_not_equal_with_a_nodeset_is_not_the_negation_of_equal() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+        let text = |s| setup.doc.create_text(s);
+
+        let one = text("1");
+        let other_one = text("1.0");
+        let two = text("2");
+        let empty = text("");
+        let nan = text("x");
+
+        let ones = nodeset![one, other_one];
+        let mixed = nodeset![one, two];
+        let single = nodeset![one];
+        let none = Nodeset::new();
+
+        // Each row is the left and right values and the results of
+        // `=` and `!=`.
+        let rows: Vec<(Value<'_>, Value<'_>, bool, bool)> = vec![
+            // Nodeset and string
+            (mixed.clone().into(), "1".into(), true, true),
+            (single.clone().into(), "1".into(), true, false),
+            (single.clone().into(), "3".into(), false, true),
+            (ones.clone().into(), "1".into(), true, true),
+            (none.clone().into(), "".into(), false, false),
+            ("1".into(), mixed.clone().into(), true, true),
+            // Nodeset and number
+            (ones.clone().into(), 1.0.into(), true, false),
+            (mixed.clone().into(), 1.0.into(), true, true),
+            (
+                nodeset![nan].into(),
+                Value::Number(::std::f64::NAN),
+                false,
+                true,
+            ),
+            (none.clone().into(), 1.0.into(), false, false),
+            (2.0.into(), mixed.clone().into(), true, true),
+            // Nodeset and boolean
+            (single.clone().into(), true.into(), true, false),
+            (nodeset![empty].into(), true.into(), true, false),
+            (none.clone().into(), false.into(), true, false),
+            (false.into(), mixed.clone().into(), false, true),
+            // Nodeset and nodeset
+            (single.clone().into(), mixed.clone().into(), true, true),
+            (single.clone().into(), single.clone().into(), true, false),
+            (mixed.clone().into(), mixed.clone().into(), true, true),
+            (ones.clone().into(), single.clone().into(), true, true),
+            (nodeset![two].into(), single.clone().into(), false, true),
+            (none.clone().into(), mixed.clone().into(), false, false),
+            // No nodesets
+            (true.into(), "x".into(), true, false),
+            (1.0.into(), " 1 ".into(), true, false),
+            ("1".into(), "1.0".into(), false, true),
+        ];
+
+        for (left, right, equal, not_equal) in rows {
+            let mut context = Context::without_core_functions();
+            context.set_variable("left", left.clone());
+            context.set_variable("right", right.clone());
+            let context = context::Evaluation::new(&context, setup.doc.root().into());
+
+            let variable =
+                |name: &str| -> SubExpression { Box::new(Variable { name: name.into() }) };
+            let eq = Equal::new(variable("left"), variable("right"));
+            let ne = NotEqual::new(variable("left"), variable("right"));
+
+            assert_eq!(
+                Ok(Boolean(equal)),
+                eq.evaluate(&context),
+                "{:?} = {:?}",
+                left,
+                right
+            );
+            assert_eq!(
+                Ok(Boolean(not_equal)),
+                ne.evaluate(&context),
+                "{:?} != {:?}",
+                left,
+                right
+            );
+        }
+    }
+
     struct StubFunction {
         value: &'static str,
     }
@@ -910,6 +1283,83 @@ mod test {
         assert_eq!(res, UnknownFunction { name: "unknown-fn" }.fail());
     }
 
+    #[test]
+    fn expression_function_failure_is_reported_with_the_function_name() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup.context.set_function_fn("failing-fn", |_, _| {
+            Err(function::Error::Other {
+                what: "the function failed".to_owned(),
+            })
+        });
+
+        let expr = Function {
+            name: "failing-fn".into(),
+            arguments: vec![],
+        };
+
+        let context = setup.context();
+        let res = expr.evaluate(&context);
+
+        assert_eq!(
+            res,
+            Err(Error::FunctionEvaluation {
+                name: "failing-fn".into(),
+                source: function::Error::Other {
+                    what: "the function failed".to_owned(),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn expression_function_with_undeclared_prefix_is_reported_as_an_error() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup.context.set_function(
+            ("urn:test", "test-fn"),
+            StubFunction {
+                value: "the function ran",
+            },
+        );
+
+        let expr = Function {
+            name: ("test", "test-fn").into(),
+            arguments: vec![],
+        };
+
+        let context = setup.context();
+        let res = expr.evaluate(&context);
+
+        assert_eq!(res, UnknownNamespace { prefix: "test" }.fail());
+    }
+
+    #[test]
+    fn expression_function_resolves_prefix_to_namespace() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup.context.set_namespace("test", "urn:test");
+        setup.context.set_function(
+            "test-fn",
+            StubFunction {
+                value: "unprefixed",
+            },
+        );
+        setup
+            .context
+            .set_function(("urn:test", "test-fn"), StubFunction { value: "prefixed" });
+
+        let expr = Function {
+            name: ("test", "test-fn").into(),
+            arguments: vec![],
+        };
+
+        let context = setup.context();
+        let res = expr.evaluate(&context);
+
+        assert_eq!(res, Ok(String("prefixed".to_owned())));
+    }
+
     #[test]
     fn expression_math_does_basic_math() {
         let package = Package::new();
@@ -930,6 +1380,103 @@ mod test {
         assert_eq!(res, Ok(Number(50.0)));
     }
 
+    #[test]
+    fn expression_math_follows_ieee_754() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+        let context = setup.context();
+
+        let number = |n| -> SubExpression { Box::new(Literal { value: Number(n) }) };
+        let nan = ::std::f64::NAN;
+        let inf = ::std::f64::INFINITY;
+
+        let evaluate = |expr: SubExpression| match expr.evaluate(&context) {
+            Ok(Number(n)) => n,
+            other => panic!("{:?} did not give a number: {:?}", expr, other),
+        };
+
+        assert_eq!(inf, evaluate(Math::division(number(1.0), number(0.0))));
+        assert_eq!(-inf, evaluate(Math::division(number(-1.0), number(0.0))));
+        assert_eq!(-inf, evaluate(Math::division(number(1.0), number(-0.0))));
+        assert!(evaluate(Math::division(number(0.0), number(0.0))).is_nan());
+        assert!(evaluate(Math::remainder(number(1.0), number(0.0))).is_nan());
+        assert!(evaluate(Math::subtraction(number(inf), number(inf))).is_nan());
+
+        let builders: [fn(SubExpression, SubExpression) -> SubExpression; 5] = [
+            Math::addition,
+            Math::subtraction,
+            Math::multiplication,
+            Math::division,
+            Math::remainder,
+        ];
+        for build in &builders {
+            assert!(evaluate(build(number(nan), number(1.0))).is_nan());
+            assert!(evaluate(build(number(1.0), number(nan))).is_nan());
+        }
+    }
+
+    #[test]
+    fn expression_remainder_takes_the_sign_of_the_dividend() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+        let context = setup.context();
+
+        let remainder = |a, b| {
+            let number = |n| -> SubExpression { Box::new(Literal { value: Number(n) }) };
+            match Math::remainder(number(a), number(b)).evaluate(&context) {
+                Ok(Number(n)) => n,
+                other => panic!("{} mod {} did not give a number: {:?}", a, b, other),
+            }
+        };
+
+        assert_eq!(1.0, remainder(5.0, 2.0));
+        assert_eq!(1.0, remainder(5.0, -2.0));
+        assert_eq!(-1.0, remainder(-5.0, 2.0));
+        assert_eq!(-1.0, remainder(-5.0, -2.0));
+        assert!(remainder(5.0, 0.0).is_nan());
+        assert!(remainder(-5.0, -0.0).is_nan());
+        assert_eq!(5.0, remainder(5.0, ::std::f64::INFINITY));
+    }
+
+    #[test]
+    fn filter_with_a_fractional_or_nan_predicate_selects_nothing() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+
+        let parent = setup.doc.create_element("parent");
+        setup.doc.root().append_child(parent);
+        let children: Vec<_> = (0..3)
+            .map(|_| {
+                let child = setup.doc.create_element("child");
+                parent.append_child(child);
+                child
+            })
+            .collect();
+        setup
+            .context
+            .set_variable("nodes", nodeset![children[0], children[1], children[2]]);
+
+        let context = setup.context();
+        let select = |n| {
+            let selected_nodes = Box::new(Variable {
+                name: "nodes".into(),
+            });
+            let predicate = Box::new(Literal {
+                value: Value::Number(n),
+            });
+            Filter::new(selected_nodes, predicate).evaluate(&context)
+        };
+
+        let nothing = Ok(Value::Nodeset(nodeset![]));
+        assert_eq!(nothing, select(1.5));
+        assert_eq!(nothing, select(2.999_999));
+        assert_eq!(nothing, select(::std::f64::NAN));
+        assert_eq!(nothing, select(::std::f64::INFINITY));
+        assert_eq!(nothing, select(0.0));
+        assert_eq!(nothing, select(-1.0));
+        assert_eq!(Ok(Value::Nodeset(nodeset![children[2]])), select(3.0));
+    }
+
     #[test]
     fn filter_with_numeric_predicate_selects_that_node() {
         let package = Package::new();
@@ -1007,6 +1554,80 @@ mod test {
         assert_eq!(res, Ok(Boolean(false)));
     }
 
+    #[test]
+    fn expression_relational_with_a_nodeset_is_existential() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+        let text = |s| setup.doc.create_text(s);
+
+        let small = nodeset![text("1"), text("2")];
+        let large = nodeset![text("10"), text("20")];
+        let with_nan = nodeset![text("5"), text("five")];
+        let only_nan = nodeset![text("five")];
+        let none = Nodeset::new();
+
+        type Builder = fn(SubExpression, SubExpression) -> SubExpression;
+        let lt: Builder = Relational::less_than;
+        let le: Builder = Relational::less_than_or_equal;
+        let gt: Builder = Relational::greater_than;
+        let ge: Builder = Relational::greater_than_or_equal;
+
+        let rows: Vec<(Value<'_>, Builder, Value<'_>, bool)> = vec![
+            // Nodeset and number
+            (small.clone().into(), lt, 2.0.into(), true),
+            (small.clone().into(), gt, 1.0.into(), true),
+            (small.clone().into(), gt, 2.0.into(), false),
+            (small.clone().into(), ge, 2.0.into(), true),
+            (3.0.into(), gt, small.clone().into(), true),
+            (3.0.into(), lt, small.clone().into(), false),
+            (none.clone().into(), lt, 1.0.into(), false),
+            (none.clone().into(), ge, 1.0.into(), false),
+            // Nodeset and nodeset
+            (small.clone().into(), lt, large.clone().into(), true),
+            (small.clone().into(), gt, large.clone().into(), false),
+            (large.clone().into(), ge, small.clone().into(), true),
+            (small.clone().into(), le, small.clone().into(), true),
+            (small.clone().into(), gt, small.clone().into(), true),
+            (none.clone().into(), le, small.clone().into(), false),
+            // Non-numeric nodes are NaN and never compare
+            (with_nan.clone().into(), lt, 6.0.into(), true),
+            (with_nan.clone().into(), gt, 6.0.into(), false),
+            (only_nan.clone().into(), le, 6.0.into(), false),
+            (only_nan.clone().into(), ge, 6.0.into(), false),
+            (only_nan.clone().into(), le, only_nan.clone().into(), false),
+            // Nodeset and string or boolean
+            (large.clone().into(), gt, "15".into(), true),
+            ("15".into(), ge, large.clone().into(), true),
+            (small.clone().into(), gt, false.into(), true),
+            (none.clone().into(), lt, true.into(), true),
+            // Strings are compared as numbers
+            ("2".into(), lt, "10".into(), true),
+            ("abc".into(), lt, "abd".into(), false),
+            ("abc".into(), ge, "abc".into(), false),
+            (true.into(), gt, "0.5".into(), true),
+        ];
+
+        for (left, build, right, expected) in rows {
+            let mut context = Context::without_core_functions();
+            context.set_variable("left", left.clone());
+            context.set_variable("right", right.clone());
+            let context = context::Evaluation::new(&context, setup.doc.root().into());
+
+            let variable =
+                |name: &str| -> SubExpression { Box::new(Variable { name: name.into() }) };
+            let expr = build(variable("left"), variable("right"));
+
+            assert_eq!(
+                Ok(Boolean(expected)),
+                expr.evaluate(&context),
+                "{:?} {:?} {:?}",
+                left,
+                expr,
+                right
+            );
+        }
+    }
+
     #[test]
     fn expression_root_node_finds_the_root() {
         let package = Package::new();
@@ -1052,6 +1673,10 @@ mod test {
     struct DummyNodeTest;
     impl NodeTest for DummyNodeTest {
         fn test(&self, _context: &context::Evaluation<'_, '_>, _result: &mut OrderedNodes<'_>) {}
+
+        fn to_ast(&self) -> ast::NodeTest {
+            ast::NodeTest::Node
+        }
     }
 
     #[test]
@@ -1070,6 +1695,35 @@ mod test {
         assert_eq!(1, axis.calls());
     }
 
+    #[derive(Debug)]
+    struct PrefixedNodeTest;
+    impl NodeTest for PrefixedNodeTest {
+        fn test(&self, _context: &context::Evaluation<'_, '_>, _result: &mut OrderedNodes<'_>) {}
+
+        fn prefix(&self) -> Option<&str> {
+            Some("unbound")
+        }
+
+        fn to_ast(&self) -> ast::NodeTest {
+            ast::NodeTest::name(Some("unbound"), "name")
+        }
+    }
+
+    #[test]
+    fn step_with_an_unbound_prefix_is_reported_as_an_error() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+
+        let axis = MockAxis::new();
+        let expr = ParameterizedStep::new(axis.clone(), Box::new(PrefixedNodeTest), vec![]);
+
+        let context = setup.context();
+        let res = expr.evaluate(&context, nodeset![context.node]);
+
+        assert_eq!(res, UnknownNamespace { prefix: "unbound" }.fail());
+        assert_eq!(0, axis.calls());
+    }
+
     #[test]
     fn expression_union_combines_nodesets() {
         let package = Package::new();
@@ -1097,6 +1751,39 @@ mod test {
         assert_eq!(res, Ok(Value::Nodeset(nodeset![left_node, right_node])));
     }
 
+    #[test]
+    fn expression_union_keeps_nodes_selected_by_both_sides_once() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+
+        let first = setup.doc.create_element("first");
+        let second = setup.doc.create_element("second");
+        let parent = setup.doc.create_element("parent");
+        setup.doc.root().append_child(parent);
+        parent.append_child(first);
+        parent.append_child(second);
+        setup.context.set_variable("left", nodeset![second, first]);
+        setup.context.set_variable("right", nodeset![first]);
+
+        let expr = Union {
+            left: Box::new(Variable {
+                name: "left".into(),
+            }),
+            right: Box::new(Variable {
+                name: "right".into(),
+            }),
+        };
+
+        let context = setup.context();
+        let res = expr.evaluate(&context);
+
+        let nodes = match res {
+            Ok(Value::Nodeset(nodes)) => nodes.document_order(),
+            other => panic!("union did not produce a nodeset: {:?}", other),
+        };
+        assert_eq!(vec![Node::Element(first), Node::Element(second)], nodes);
+    }
+
     #[test]
     fn expression_variable_looks_up_the_variable() {
         let package = Package::new();
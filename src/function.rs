@@ -4,13 +4,18 @@ use snafu::Snafu;
 use std::borrow::ToOwned;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt;
 use std::iter;
 use std::ops::Index;
+use std::sync::Arc;
 use sxd_document::XmlChar;
 
 use crate::context;
-use crate::nodeset::Nodeset;
-use crate::{str_to_num, Value};
+use crate::convert;
+use crate::nodeset::{Node, Nodeset};
+use crate::{OwnedQName, Value};
+
+pub mod exslt;
 
 /// Types that can be used as XPath functions.
 pub trait Function {
@@ -21,6 +26,81 @@ pub trait Function {
         context: &context::Evaluation<'c, 'd>,
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error>;
+
+    /// The number of arguments this function accepts, if known.
+    ///
+    /// A [`Factory`](../struct.Factory.html) that has been given the
+    /// functions uses this to reject calls with the wrong number of
+    /// arguments while parsing. Functions that return `None` are only
+    /// checked when they are evaluated.
+    fn arity(&self) -> Option<Arity> {
+        None
+    }
+}
+
+/// Closures that accept any context and arguments are functions.
+/// Registering one with
+/// [`Context::set_function_fn`](../context/struct.Context.html#method.set_function_fn)
+/// avoids having to spell out its lifetimes.
+impl<F> Function for F
+where
+    F: for<'c, 'd> Fn(&context::Evaluation<'c, 'd>, Vec<Value<'d>>) -> Result<Value<'d>, Error>,
+{
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        self(context, args)
+    }
+}
+
+/// The number of arguments a function accepts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Arity {
+    /// The fewest arguments the function accepts.
+    pub minimum: usize,
+    /// The most arguments the function accepts, or `None` if there is
+    /// no limit.
+    pub maximum: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Arity {
+        Arity {
+            minimum: count,
+            maximum: Some(count),
+        }
+    }
+
+    pub fn between(minimum: usize, maximum: usize) -> Arity {
+        Arity {
+            minimum,
+            maximum: Some(maximum),
+        }
+    }
+
+    pub fn at_least(minimum: usize) -> Arity {
+        Arity {
+            minimum,
+            maximum: None,
+        }
+    }
+
+    /// Checks if a call with this many arguments is allowed.
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.minimum && self.maximum.map_or(true, |maximum| count <= maximum)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.maximum {
+            Some(maximum) if maximum == self.minimum => write!(f, "exactly {}", maximum),
+            Some(maximum) => write!(f, "between {} and {}", self.minimum, maximum),
+            None => write!(f, "at least {}", self.minimum),
+        }
+    }
 }
 
 /// Represents the kind of an XPath value without carrying a value.
@@ -52,15 +132,49 @@ pub enum Error {
     NotEnoughArguments { expected: usize, actual: usize },
     #[snafu(display("attempted to use an argument that was not present"))]
     ArgumentMissing,
-    #[snafu(display("argument was expected to be a nodeset but was a {:?}", actual))]
-    ArgumentNotANodeset { actual: ArgumentType },
+    #[snafu(display(
+        "argument {} was expected to be a nodeset but was a {:?}",
+        index,
+        actual
+    ))]
+    ArgumentNotANodeset { index: usize, actual: ArgumentType },
+    #[snafu(display("unknown namespace prefix {}", prefix))]
+    UnknownNamespace { prefix: String },
+    #[snafu(display("unknown key {:?}", name))]
+    UnknownKey { name: OwnedQName },
+    #[snafu(display("key {:?} is used by its own match or use expression", name))]
+    RecursiveKey { name: OwnedQName },
+    #[snafu(display("invalid regular expression {:?}: {}", pattern, reason))]
+    InvalidRegularExpression { pattern: String, reason: String },
+    #[snafu(display("no scratch document has been registered to create nodes in"))]
+    NoScratchDocument,
+    #[snafu(display("no document resolver has been registered"))]
+    NoDocumentResolver,
+    #[snafu(display("could not load document {}: {}", uri, what))]
+    DocumentUnavailable { uri: String, what: String },
+    #[snafu(display("could not parse expression {:?}: {}", expression, what))]
+    InvalidExpression { expression: String, what: String },
+    #[snafu(display("could not evaluate expression {:?}: {}", expression, what))]
+    ExpressionFailed { expression: String, what: String },
+    #[snafu(display(
+        "expression {:?} is nested more than {} levels deep",
+        expression,
+        limit
+    ))]
+    NestingTooDeep { expression: String, limit: usize },
+    /// Any other failure, such as an argument that has the right type
+    /// but an unusable value. Custom functions can use this to report
+    /// their own problems; the message reaches the caller of
+    /// [`XPath::evaluate`](../struct.XPath.html#method.evaluate)
+    /// together with the name of the function.
     #[snafu(display("could not evaluate function: {}", what))]
     Other { what: String },
 }
 
 impl Error {
-    fn not_a_nodeset(actual: &Value<'_>) -> Error {
+    fn not_a_nodeset(index: usize, actual: &Value<'_>) -> Error {
         Error::ArgumentNotANodeset {
+            index,
             actual: actual.into(),
         }
     }
@@ -68,6 +182,33 @@ impl Error {
 
 /// Provides common utility functions for dealing with function
 /// argument lists.
+///
+/// This is the recommended way to implement [`Function`](trait.Function.html):
+/// check the number of arguments first, then remove them starting
+/// with the **last** one. Removing from the end means the remaining
+/// arguments keep their positions, so errors can report which
+/// argument (counting from zero) was at fault.
+///
+/// ```
+/// use sxd_xpath::function::{Args, Error, Function};
+/// use sxd_xpath::{context, Value};
+///
+/// struct Repeat;
+///
+/// impl Function for Repeat {
+///     fn evaluate<'c, 'd>(
+///         &self,
+///         _context: &context::Evaluation<'c, 'd>,
+///         args: Vec<Value<'d>>,
+///     ) -> Result<Value<'d>, Error> {
+///         let mut args = Args(args);
+///         args.exactly(2)?;
+///         let count = args.pop_number()?;
+///         let s = args.pop_string()?;
+///         Ok(Value::String(s.repeat(count as usize)))
+///     }
+/// }
+/// ```
 pub struct Args<'d>(pub Vec<Value<'d>>);
 
 impl<'d> Args<'d> {
@@ -104,6 +245,13 @@ impl<'d> Args<'d> {
         }
     }
 
+    /// Ensures that the number of arguments is within the requested
+    /// inclusive range.
+    pub fn between(&self, minimum: usize, maximum: usize) -> Result<(), Error> {
+        self.at_least(minimum)?;
+        self.at_most(maximum)
+    }
+
     /// Ensures that there are exactly the requested number of arguments.
     pub fn exactly(&self, expected: usize) -> Result<(), Error> {
         let actual = self.0.len();
@@ -124,21 +272,21 @@ impl<'d> Args<'d> {
     /// Removes the **last** argument and ensures it is a boolean. If
     /// the argument is not a boolean, it is converted to one.
     pub fn pop_boolean(&mut self) -> Result<bool, Error> {
-        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
-        Ok(v.into_boolean())
+        let v = self.pop_value()?;
+        Ok(convert::to_boolean(&v))
     }
 
     /// Removes the **last** argument and ensures it is a number. If
     /// the argument is not a number, it is converted to one.
     pub fn pop_number(&mut self) -> Result<f64, Error> {
-        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
-        Ok(v.into_number())
+        let v = self.pop_value()?;
+        Ok(convert::to_number(&v))
     }
 
     /// Removes the **last** argument and ensures it is a string. If
     /// the argument is not a string, it is converted to one.
     pub fn pop_string(&mut self) -> Result<String, Error> {
-        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let v = self.pop_value()?;
         Ok(v.into_string())
     }
 
@@ -146,13 +294,19 @@ impl<'d> Args<'d> {
     /// the argument is not a nodeset, a type mismatch error is
     /// returned.
     pub fn pop_nodeset(&mut self) -> Result<Nodeset<'d>, Error> {
-        let v = self.0.pop().ok_or(Error::ArgumentMissing)?;
+        let v = self.pop_value()?;
         match v {
             Value::Nodeset(v) => Ok(v),
-            a => Err(Error::not_a_nodeset(&a)),
+            a => Err(Error::not_a_nodeset(self.0.len(), &a)),
         }
     }
 
+    /// Removes the **last** argument without converting it, for
+    /// functions that behave differently depending on its type.
+    pub fn pop_value(&mut self) -> Result<Value<'d>, Error> {
+        self.0.pop().ok_or(Error::ArgumentMissing)
+    }
+
     /// Removes the **last** argument. If no argument is present, the
     /// context node is returned as a nodeset.
     fn pop_value_or_context_node<'c>(
@@ -175,7 +329,7 @@ impl<'d> Args<'d> {
         self.0
             .pop()
             .map(Value::into_string)
-            .unwrap_or_else(|| context.node.string_value())
+            .unwrap_or_else(|| context.string_value())
     }
 
     /// Removes the **last** argument if it is a nodeset. If no
@@ -188,12 +342,27 @@ impl<'d> Args<'d> {
     ) -> Result<Nodeset<'d>, Error> {
         match self.0.pop() {
             Some(Value::Nodeset(ns)) => Ok(ns),
-            Some(arg) => Err(Error::not_a_nodeset(&arg)),
+            Some(arg) => Err(Error::not_a_nodeset(self.0.len(), &arg)),
             None => Ok(nodeset![context.node]),
         }
     }
 }
 
+/// Resolves a qualified name given as a string, such as the first
+/// argument to `key`, using the namespaces of the context.
+fn qname_from_str(context: &context::Evaluation<'_, '_>, name: &str) -> Result<OwnedQName, Error> {
+    let mut parts = name.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(prefix), Some(local_part)) => match context.namespace_for(prefix) {
+            Some(uri) => Ok((uri, local_part).into()),
+            None => Err(Error::UnknownNamespace {
+                prefix: prefix.into(),
+            }),
+        },
+        _ => Ok(name.into()),
+    }
+}
+
 impl<'d> Index<usize> for Args<'d> {
     type Output = Value<'d>;
 
@@ -214,6 +383,10 @@ impl Function for Last {
         args.exactly(0)?;
         Ok(Value::Number(context.size as f64))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(0))
+    }
 }
 
 struct Position;
@@ -228,6 +401,28 @@ impl Function for Position {
         args.exactly(0)?;
         Ok(Value::Number(context.position as f64))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(0))
+    }
+}
+
+struct Current;
+
+impl Function for Current {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        Ok(Value::Nodeset(nodeset![context.current]))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(0))
+    }
 }
 
 struct Count;
@@ -243,6 +438,10 @@ impl Function for Count {
         let arg = args.pop_nodeset()?;
         Ok(Value::Number(arg.size() as f64))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
 }
 
 struct LocalName;
@@ -254,7 +453,7 @@ impl Function for LocalName {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_nodeset_or_context_node(context)?;
         let name = arg
             .document_order_first()
@@ -263,6 +462,10 @@ impl Function for LocalName {
             .unwrap_or("");
         Ok(Value::String(name.to_owned()))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
 }
 
 struct NamespaceUri;
@@ -274,7 +477,7 @@ impl Function for NamespaceUri {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_nodeset_or_context_node(context)?;
         let name = arg
             .document_order_first()
@@ -283,6 +486,10 @@ impl Function for NamespaceUri {
             .unwrap_or("");
         Ok(Value::String(name.to_owned()))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
 }
 
 struct Name;
@@ -294,7 +501,7 @@ impl Function for Name {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_nodeset_or_context_node(context)?;
         let name = arg
             .document_order_first()
@@ -302,6 +509,10 @@ impl Function for Name {
             .unwrap_or_else(String::new);
         Ok(Value::String(name))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
 }
 
 struct StringFn;
@@ -313,9 +524,13 @@ impl Function for StringFn {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_value_or_context_node(context);
-        Ok(Value::String(arg.string()))
+        Ok(Value::String(convert::to_string(&arg)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
     }
 }
 
@@ -332,6 +547,10 @@ impl Function for Concat {
         let args = args.into_strings();
         Ok(Value::String(args.concat()))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::at_least(2))
+    }
 }
 
 struct TwoStringPredicate(fn(&str, &str) -> bool);
@@ -348,18 +567,28 @@ impl Function for TwoStringPredicate {
         let v = self.0(&args[0], &args[1]);
         Ok(Value::Boolean(v))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
 }
 
 fn starts_with() -> TwoStringPredicate {
     fn imp(a: &str, b: &str) -> bool {
         str::starts_with(a, b)
-    };
+    }
+    TwoStringPredicate(imp)
+}
+fn ends_with() -> TwoStringPredicate {
+    fn imp(a: &str, b: &str) -> bool {
+        str::ends_with(a, b)
+    }
     TwoStringPredicate(imp)
 }
 fn contains() -> TwoStringPredicate {
     fn imp(a: &str, b: &str) -> bool {
         str::contains(a, b)
-    };
+    }
     TwoStringPredicate(imp)
 }
 
@@ -377,6 +606,10 @@ impl Function for SubstringCommon {
         let s = self.0(&args[0], &args[1]);
         Ok(Value::String(s.to_owned()))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
 }
 
 fn substring_before() -> SubstringCommon {
@@ -408,8 +641,7 @@ impl Function for Substring {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_least(2)?;
-        args.at_most(3)?;
+        args.between(2, 3)?;
 
         let len = if args.len() == 3 {
             let len = args.pop_number()?;
@@ -436,6 +668,10 @@ impl Function for Substring {
 
         Ok(Value::String(selected_chars))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(2, 3))
+    }
 }
 
 struct StringLength;
@@ -447,10 +683,14 @@ impl Function for StringLength {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_string_value_or_context_node(context);
         Ok(Value::Number(arg.chars().count() as f64))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
 }
 
 struct NormalizeSpace;
@@ -462,7 +702,7 @@ impl Function for NormalizeSpace {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_string_value_or_context_node(context);
         // TODO: research itertools or another pure-iterator solution
         let s: Vec<_> = arg
@@ -472,6 +712,10 @@ impl Function for NormalizeSpace {
         let s = s.join(" ");
         Ok(Value::String(s))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
 }
 
 struct Translate;
@@ -501,11 +745,15 @@ impl Function for Translate {
 
         let s = s
             .chars()
-            .filter_map(|c| replacements.get(&c).cloned().unwrap_or_else(|| Some(c)))
+            .filter_map(|c| replacements.get(&c).cloned().unwrap_or(Some(c)))
             .collect();
 
         Ok(Value::String(s))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(3))
+    }
 }
 
 struct BooleanFn;
@@ -518,7 +766,11 @@ impl Function for BooleanFn {
     ) -> Result<Value<'d>, Error> {
         let args = Args(args);
         args.exactly(1)?;
-        Ok(Value::Boolean(args[0].boolean()))
+        Ok(Value::Boolean(convert::to_boolean(&args[0])))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
     }
 }
 
@@ -535,6 +787,10 @@ impl Function for Not {
         let arg = args.pop_boolean()?;
         Ok(Value::Boolean(!arg))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
 }
 
 struct BooleanLiteral(bool);
@@ -549,6 +805,45 @@ impl Function for BooleanLiteral {
         args.exactly(0)?;
         Ok(Value::Boolean(self.0))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(0))
+    }
+}
+
+struct Lang;
+
+const XML_NAMESPACE_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+impl Function for Lang {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let wanted = args.pop_string()?.to_lowercase();
+
+        let mut node = Some(context.node);
+        while let Some(n) = node {
+            if let Node::Element(e) = n {
+                if let Some(lang) = e.attribute_value((XML_NAMESPACE_URI, "lang")) {
+                    let lang = lang.to_lowercase();
+                    let matches = lang.starts_with(&wanted)
+                        && (lang.len() == wanted.len() || lang[wanted.len()..].starts_with('-'));
+                    return Ok(Value::Boolean(matches));
+                }
+            }
+            node = n.parent();
+        }
+
+        Ok(Value::Boolean(false))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
 }
 
 fn true_fn() -> BooleanLiteral {
@@ -567,12 +862,24 @@ impl Function for NumberFn {
         args: Vec<Value<'d>>,
     ) -> Result<Value<'d>, Error> {
         let mut args = Args(args);
-        args.at_most(1)?;
+        args.between(0, 1)?;
         let arg = args.pop_value_or_context_node(context);
-        Ok(Value::Number(arg.number()))
+        Ok(Value::Number(convert::to_number(&arg)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
     }
 }
 
+/// Converts the string-value of each node to a number, as `sum` and
+/// similar aggregate functions do.
+pub(crate) fn node_numbers<'a, 'd>(nodes: &'a Nodeset<'d>) -> impl Iterator<Item = f64> + 'a {
+    nodes
+        .iter()
+        .map(|n| convert::string_to_number(&n.string_value()))
+}
+
 struct Sum;
 
 impl Function for Sum {
@@ -584,15 +891,16 @@ impl Function for Sum {
         let mut args = Args(args);
         args.exactly(1)?;
         let arg = args.pop_nodeset()?;
-        let r = arg
-            .iter()
-            .map(|n| str_to_num(&n.string_value()))
-            .fold(0.0, |acc, i| acc + i);
+        let r = node_numbers(&arg).fold(0.0, |acc, i| acc + i);
         Ok(Value::Number(r))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
 }
 
-struct NumberConvert(fn(f64) -> f64);
+pub(crate) struct NumberConvert(pub(crate) fn(f64) -> f64);
 
 impl Function for NumberConvert {
     fn evaluate<'c, 'd>(
@@ -605,6 +913,10 @@ impl Function for NumberConvert {
         let arg = args.pop_number()?;
         Ok(Value::Number(self.0(arg)))
     }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
 }
 
 fn floor() -> NumberConvert {
@@ -634,121 +946,813 @@ fn round() -> NumberConvert {
     NumberConvert(round_ties_to_positive_infinity)
 }
 
-/// Adds the [XPath 1.0 core function library][corelib].
+/// Identifies a node by the number of its tree, followed by its path
+/// from the top of that tree: the position among its parent's
+/// children, the position among its element's attributes (`a`) or the
+/// prefix of a namespace node (`n`).
 ///
-/// [corelib]: https://www.w3.org/TR/xpath/#corelib
-pub fn register_core_functions(context: &mut context::Context<'_>) {
-    context.set_function("last", Last);
-    context.set_function("position", Position);
-    context.set_function("count", Count);
-    context.set_function("local-name", LocalName);
-    context.set_function("namespace-uri", NamespaceUri);
-    context.set_function("name", Name);
-    context.set_function("string", StringFn);
-    context.set_function("concat", Concat);
-    context.set_function("starts-with", starts_with());
-    context.set_function("contains", contains());
-    context.set_function("substring-before", substring_before());
-    context.set_function("substring-after", substring_after());
-    context.set_function("substring", Substring);
-    context.set_function("string-length", StringLength);
-    context.set_function("normalize-space", NormalizeSpace);
-    context.set_function("translate", Translate);
-    context.set_function("boolean", BooleanFn);
-    context.set_function("not", Not);
-    context.set_function("true", true_fn());
-    context.set_function("false", false_fn());
-    context.set_function("number", NumberFn);
-    context.set_function("sum", Sum);
-    context.set_function("floor", floor());
-    context.set_function("ceiling", ceiling());
-    context.set_function("round", round());
+/// Trees are numbered by the context, so nodes of different documents,
+/// or nodes that have not been added to their document, are told
+/// apart. Within a context the identifiers do not change. The tree
+/// of the first node a context evaluates against is numbered 0, so
+/// a document evaluated first gets the same identifiers in any
+/// context; other trees are numbered in the order they are first
+/// asked about.
+fn generated_id<'d>(context: &context::Evaluation<'_, 'd>, node: Node<'d>) -> String {
+    let mut steps = Vec::new();
+    let mut node = node;
+
+    while let Some(parent) = node.parent() {
+        let step = match (node, parent) {
+            (Node::Attribute(a), Node::Element(e)) => {
+                let idx = e.attributes().iter().position(|&o| o == a).unwrap_or(0);
+                format!("a{}", idx)
+            }
+            (Node::Namespace(ns), _) => format!("n{}", ns.prefix()),
+            _ => {
+                let idx = parent
+                    .children()
+                    .iter()
+                    .position(|&o| o == node)
+                    .unwrap_or(0);
+                idx.to_string()
+            }
+        };
+        steps.push(step);
+        node = parent;
+    }
+
+    let mut id = format!("id{}", context.tree_number(node));
+    for step in steps.iter().rev() {
+        id.push('-');
+        id.push_str(step);
+    }
+    id
 }
 
-#[cfg(test)]
-mod test {
-    use std::borrow::ToOwned;
-    use std::{f64, fmt};
+struct GenerateId;
 
-    use sxd_document::Package;
+impl Function for GenerateId {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(0, 1)?;
+        let arg = args.pop_nodeset_or_context_node(context)?;
+        let id = arg
+            .document_order_first()
+            .map(|node| generated_id(context, node))
+            .unwrap_or_else(String::new);
+        Ok(Value::String(id))
+    }
 
-    use crate::context;
-    use crate::nodeset::Node;
-    use crate::{LiteralValue, Value};
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
+}
 
-    use super::{
-        ceiling, contains, floor, round, starts_with, substring_after, substring_before, BooleanFn,
-        Concat, Count, Error, Function, Last, LocalName, Name, NamespaceUri, NormalizeSpace,
-        NumberFn, Position, StringFn, StringLength, Substring, Sum, Translate,
-    };
+struct FunctionAvailable;
 
-    /// Converts each argument into a `Value` and packs them into a
-    /// vector.
-    macro_rules! args {
-        ( $($val:expr,)* ) => {
-            vec![
-                $( Value::from($val), )*
-            ]
-        };
-        ( $($val:expr),* ) => {
-            args![$($val, )*]
-        };
+impl Function for FunctionAvailable {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let name = qname_from_str(context, &args.pop_string()?)?;
+        Ok(Value::Boolean(context.has_function(&name)))
     }
 
-    struct Setup<'d> {
-        context: context::Context<'d>,
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
     }
+}
 
-    impl<'d> Setup<'d> {
-        fn new() -> Setup<'d> {
-            Setup {
-                context: context::Context::without_core_functions(),
-            }
-        }
+/// We are not an XSLT processor, so no instruction elements are ever
+/// available.
+struct ElementAvailable;
 
-        fn evaluate<N, F>(&self, node: N, f: F, args: Vec<Value<'d>>) -> Result<Value<'d>, Error>
-        where
-            N: Into<Node<'d>>,
-            F: Function,
-        {
-            let context = context::Evaluation::new(&self.context, node.into());
-            f.evaluate(&context, args)
-        }
+impl Function for ElementAvailable {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        qname_from_str(context, &args.pop_string()?)?;
+        Ok(Value::Boolean(false))
     }
 
-    fn evaluate_literal<F, F2, T>(f: F, args: Vec<LiteralValue>, rf: F2) -> T
-    where
-        F: Function,
-        F2: FnOnce(Result<Value<'_>, Error>) -> T,
-    {
-        let package = Package::new();
-        let doc = package.as_document();
-        let setup = Setup::new();
-
-        rf(setup.evaluate(doc.root(), f, args))
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
     }
+}
 
-    #[test]
-    fn last_returns_context_size() {
-        evaluate_literal(Last, args![], |r| {
-            assert_eq!(Ok(Value::Number(1.0)), r);
-        });
-    }
+struct SystemProperty;
 
-    #[test]
-    fn position_returns_context_position() {
-        evaluate_literal(Position, args![], |r| {
-            assert_eq!(Ok(Value::Number(1.0)), r);
-        });
+impl Function for SystemProperty {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let name = qname_from_str(context, &args.pop_string()?)?;
+        let value = context
+            .system_property(&name)
+            .cloned()
+            .unwrap_or_else(|| Value::String(String::new()));
+        Ok(value)
     }
 
-    #[test]
-    fn count_counts_nodes_in_nodeset() {
-        let package = Package::new();
-        let doc = package.as_document();
-        let setup = Setup::new();
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
 
-        let r = setup.evaluate(doc.root(), Count, args![nodeset![doc.root()]]);
+struct UnparsedEntityUri;
+
+impl Function for UnparsedEntityUri {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let name = args.pop_string()?;
+        let uri = context.unparsed_entity_uri(&name).unwrap_or("");
+        Ok(Value::String(uri.to_owned()))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+const XSLT_NAMESPACE_URI: &str = "http://www.w3.org/1999/XSL/Transform";
+
+struct KeyFn;
+
+impl Function for KeyFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let values: Vec<String> = match args.pop_value()? {
+            Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+            other => vec![other.into_string()],
+        };
+        let name = qname_from_str(context, &args.pop_string()?)?;
+
+        let mut result = Nodeset::new();
+        for value in values {
+            result.extend(context.nodes_with_key(&name, &value)?);
+        }
+        Ok(Value::Nodeset(result))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+struct DocumentFn;
+
+impl Function for DocumentFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let uris: Vec<String> = match args.pop_value()? {
+            Value::Nodeset(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+            other => vec![other.into_string()],
+        };
+
+        let mut documents = Nodeset::new();
+        for uri in uris {
+            documents.add(context.load_document(&uri)?.root());
+        }
+        Ok(Value::Nodeset(documents))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+struct StringConvert(fn(&str) -> String);
+
+impl Function for StringConvert {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let arg = args.pop_string()?;
+        Ok(Value::String(self.0(&arg)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+fn upper_case() -> StringConvert {
+    StringConvert(str::to_uppercase)
+}
+fn lower_case() -> StringConvert {
+    StringConvert(str::to_lowercase)
+}
+
+struct StringJoin;
+
+impl Function for StringJoin {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let separator = args.pop_string()?;
+        let joined = match args.pop_value()? {
+            Value::Nodeset(nodes) => {
+                let values: Vec<_> = nodes
+                    .document_order()
+                    .iter()
+                    .map(Node::string_value)
+                    .collect();
+                values.join(&separator)
+            }
+            // A single value has nothing to be joined with
+            other => other.into_string(),
+        };
+        Ok(Value::String(joined))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// Adds the [XPath 1.0 core function library][corelib], along with
+/// the commonly provided `ends-with` extension.
+///
+/// [corelib]: https://www.w3.org/TR/xpath/#corelib
+pub fn register_core_functions(context: &mut context::Context<'_>) {
+    context.set_function("last", Last);
+    context.set_function("position", Position);
+    context.set_function("count", Count);
+    context.set_function("local-name", LocalName);
+    context.set_function("namespace-uri", NamespaceUri);
+    context.set_function("name", Name);
+    context.set_function("string", StringFn);
+    context.set_function("concat", Concat);
+    context.set_function("starts-with", starts_with());
+    context.set_function("ends-with", ends_with());
+    context.set_function("contains", contains());
+    context.set_function("substring-before", substring_before());
+    context.set_function("substring-after", substring_after());
+    context.set_function("substring", Substring);
+    context.set_function("string-length", StringLength);
+    context.set_function("normalize-space", NormalizeSpace);
+    context.set_function("translate", Translate);
+    context.set_function("boolean", BooleanFn);
+    context.set_function("not", Not);
+    context.set_function("lang", Lang);
+    context.set_function("true", true_fn());
+    context.set_function("false", false_fn());
+    context.set_function("number", NumberFn);
+    context.set_function("sum", Sum);
+    context.set_function("floor", floor());
+    context.set_function("ceiling", ceiling());
+    context.set_function("round", round());
+}
+
+/// Adds the functions that [XSLT 1.0][xslt] adds to the core
+/// library: `current`, `key`, `generate-id`, `function-available`,
+/// `element-available`, `system-property`, `unparsed-entity-uri` and
+/// `document`, along with the `xsl:version`, `xsl:vendor` and
+/// `xsl:vendor-url` system properties.
+///
+/// `key` relies on keys registered with
+/// [`Context::set_key`](../context/struct.Context.html#method.set_key)
+/// and `document` fails unless a resolver has been provided with
+/// [`Context::set_document_resolver`][resolver].
+///
+/// [xslt]: https://www.w3.org/TR/xslt#add-func
+/// [resolver]: ../context/struct.Context.html#method.set_document_resolver
+pub fn register_xslt_functions(context: &mut context::Context<'_>) {
+    context.set_function("current", Current);
+    context.set_function("key", KeyFn);
+    context.set_function("generate-id", GenerateId);
+    context.set_function("function-available", FunctionAvailable);
+    context.set_function("element-available", ElementAvailable);
+    context.set_function("system-property", SystemProperty);
+    context.set_function("unparsed-entity-uri", UnparsedEntityUri);
+    context.set_function("document", DocumentFn);
+    context.set_system_property((XSLT_NAMESPACE_URI, "version"), 1.0);
+    context.set_system_property((XSLT_NAMESPACE_URI, "vendor"), "sxd-xpath");
+    context.set_system_property(
+        (XSLT_NAMESPACE_URI, "vendor-url"),
+        "https://github.com/shepmaster/sxd-xpath",
+    );
+}
+
+/// Adds string functions from XPath 2.0 that are useful with XPath
+/// 1.0 but not part of it: `upper-case`, `lower-case` and
+/// `string-join`. Case conversion uses the full Unicode mappings, so
+/// the result may be longer than the input.
+pub fn register_extended_string_functions(context: &mut context::Context<'_>) {
+    context.set_function("upper-case", upper_case());
+    context.set_function("lower-case", lower_case());
+    context.set_function("string-join", StringJoin);
+}
+
+/// Collects functions into a table that can be given to a context
+/// with [`Context::set_functions`][set_functions], so that groups of
+/// functions can be combined before any context exists.
+///
+/// ```
+/// use sxd_xpath::function::FunctionLibrary;
+/// use sxd_xpath::{Context, Value};
+///
+/// let functions = FunctionLibrary::new()
+///     .with_core()
+///     .with_exslt_strings()
+///     .with_fn("answer", |_, _| Ok(Value::Number(42.0)))
+///     .build();
+///
+/// let mut context = Context::without_core_functions();
+/// context.set_functions(functions);
+/// ```
+///
+/// Only functions are collected. Libraries that also bind a prefix,
+/// such as the EXSLT ones, still need that prefix set on the context.
+///
+/// [set_functions]: ../context/struct.Context.html#method.set_functions
+#[derive(Clone, Default)]
+pub struct FunctionLibrary {
+    functions: context::Functions,
+}
+
+impl FunctionLibrary {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the core XPath 1.0 functions.
+    pub fn with_core(self) -> Self {
+        self.with_registered(register_core_functions)
+    }
+
+    /// Adds the functions of
+    /// [`register_xslt_functions`](fn.register_xslt_functions.html).
+    pub fn with_xslt(self) -> Self {
+        self.with_registered(register_xslt_functions)
+    }
+
+    /// Adds the functions of
+    /// [`register_extended_string_functions`](fn.register_extended_string_functions.html).
+    pub fn with_extended_strings(self) -> Self {
+        self.with_registered(register_extended_string_functions)
+    }
+
+    /// Adds the EXSLT strings functions.
+    pub fn with_exslt_strings(self) -> Self {
+        self.with_registered(exslt::register_exslt_strings)
+    }
+
+    /// Adds the functions that a registration function, such as
+    /// [`exslt::register_exslt_math`](exslt/fn.register_exslt_math.html),
+    /// adds to a context. Anything else it sets on the context is
+    /// discarded.
+    pub fn with_registered(mut self, register: fn(&mut context::Context<'_>)) -> Self {
+        let mut context = context::Context::without_core_functions();
+        register(&mut context);
+        self.functions.extend(
+            context
+                .functions()
+                .iter()
+                .map(|(name, function)| (name.clone(), function.clone())),
+        );
+        self
+    }
+
+    /// Adds a function, replacing any function with the same name.
+    pub fn with<N, F>(mut self, name: N, function: F) -> Self
+    where
+        N: Into<OwnedQName>,
+        F: Function + Send + Sync + 'static,
+    {
+        self.functions.insert(name.into(), Arc::new(function));
+        self
+    }
+
+    /// Adds a closure as a function, replacing any function with the
+    /// same name.
+    pub fn with_fn<N, F>(self, name: N, function: F) -> Self
+    where
+        N: Into<OwnedQName>,
+        F: for<'a, 'b> Fn(&context::Evaluation<'a, 'b>, Vec<Value<'b>>) -> Result<Value<'b>, Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.with(name, function)
+    }
+
+    /// Adds every function of another library. Functions whose name
+    /// is already taken are not added; their names are returned in
+    /// the error.
+    pub fn merge(&mut self, other: FunctionLibrary) -> Result<(), Vec<OwnedQName>> {
+        let mut collisions = Vec::new();
+
+        for (name, function) in other.functions {
+            match self.functions.entry(name) {
+                Entry::Occupied(entry) => collisions.push(entry.key().clone()),
+                Entry::Vacant(entry) => {
+                    entry.insert(function);
+                }
+            }
+        }
+
+        if collisions.is_empty() {
+            Ok(())
+        } else {
+            Err(collisions)
+        }
+    }
+
+    /// The collected functions.
+    pub fn build(self) -> context::Functions {
+        self.functions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use std::{f64, fmt};
+
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::nodeset::Node;
+    use crate::{LiteralValue, OwnedQName, Value};
+
+    use super::{
+        ceiling, contains, ends_with, floor, lower_case, round, starts_with, substring_after,
+        substring_before, upper_case, Args, ArgumentType, Arity, BooleanFn, Concat, Count, Current,
+        ElementAvailable, Error, Function, FunctionAvailable, FunctionLibrary, GenerateId, KeyFn,
+        Lang, Last, LocalName, Name, NamespaceUri, NormalizeSpace, NumberFn, Position,
+        StringConvert, StringFn, StringJoin, StringLength, Substring, Sum, SystemProperty,
+        Translate, UnparsedEntityUri,
+    };
+
+    /// Converts each argument into a `Value` and packs them into a
+    /// vector.
+    macro_rules! args {
+        ( $($val:expr,)* ) => {
+            vec![
+                $( Value::from($val), )*
+            ]
+        };
+        ( $($val:expr),* ) => {
+            args![$($val, )*]
+        };
+    }
+
+    struct Setup<'d> {
+        context: context::Context<'d>,
+    }
+
+    impl<'d> Setup<'d> {
+        fn new() -> Setup<'d> {
+            Setup {
+                context: context::Context::without_core_functions(),
+            }
+        }
+
+        fn evaluate<N, F>(&self, node: N, f: F, args: Vec<Value<'d>>) -> Result<Value<'d>, Error>
+        where
+            N: Into<Node<'d>>,
+            F: Function,
+        {
+            let context = context::Evaluation::new(&self.context, node.into());
+            f.evaluate(&context, args)
+        }
+    }
+
+    fn evaluate_literal<F, F2, T>(f: F, args: Vec<LiteralValue>, rf: F2) -> T
+    where
+        F: Function,
+        F2: FnOnce(Result<Value<'_>, Error>) -> T,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        rf(setup.evaluate(doc.root(), f, args))
+    }
+
+    #[test]
+    fn between_accepts_the_inclusive_range() {
+        assert_eq!(Ok(()), Args(args![]).between(0, 1));
+        assert_eq!(Ok(()), Args(args![1.0]).between(0, 1));
+        assert_eq!(
+            Err(Error::TooManyArguments {
+                expected: 1,
+                actual: 2
+            }),
+            Args(args![1.0, 2.0]).between(0, 1)
+        );
+        assert_eq!(
+            Err(Error::NotEnoughArguments {
+                expected: 2,
+                actual: 1
+            }),
+            Args(args![1.0]).between(2, 3)
+        );
+    }
+
+    fn generate_id_test<'d, N>(setup: &Setup<'d>, node: N) -> String
+    where
+        N: Into<Node<'d>>,
+    {
+        match setup.evaluate(node, GenerateId, args![]) {
+            Ok(Value::String(s)) => s,
+            r => panic!("generate-id failed: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn generate_id_is_unique_per_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        let c = doc.create_element("c");
+        doc.root().append_child(a);
+        a.append_child(b);
+        a.append_child(c);
+        let attr_1 = c.set_attribute_value("one", "1");
+        let attr_2 = c.set_attribute_value("two", "2");
+        let ns = crate::nodeset::Namespace {
+            parent: c,
+            prefix: "p",
+            uri: "uri",
+        };
+
+        let ids: Vec<_> = vec![
+            generate_id_test(&setup, doc.root()),
+            generate_id_test(&setup, a),
+            generate_id_test(&setup, b),
+            generate_id_test(&setup, c),
+            generate_id_test(&setup, attr_1),
+            generate_id_test(&setup, attr_2),
+            generate_id_test(&setup, Node::Namespace(ns)),
+        ];
+
+        for (i, id) in ids.iter().enumerate() {
+            assert!(id.starts_with("id"), "{} is not a valid name", id);
+            assert!(!ids[i + 1..].contains(id), "{} is not unique", id);
+        }
+    }
+
+    #[test]
+    fn generate_id_is_unique_across_trees() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let other_package = Package::new();
+        let other_doc = other_package.as_document();
+        let setup = Setup::new();
+
+        let a = doc.create_element("a");
+        doc.root().append_child(a);
+        let other_a = other_doc.create_element("a");
+        other_doc.root().append_child(other_a);
+        let detached = doc.create_element("detached");
+        let also_detached = doc.create_element("detached");
+
+        let ids: Vec<_> = vec![
+            generate_id_test(&setup, doc.root()),
+            generate_id_test(&setup, a),
+            generate_id_test(&setup, other_doc.root()),
+            generate_id_test(&setup, other_a),
+            generate_id_test(&setup, detached),
+            generate_id_test(&setup, also_detached),
+        ];
+
+        for (i, id) in ids.iter().enumerate() {
+            assert!(!ids[i + 1..].contains(id), "{} is not unique", id);
+        }
+        assert_eq!(ids[1], generate_id_test(&setup, a));
+        assert_eq!(ids[3], generate_id_test(&setup, other_a));
+    }
+
+    #[test]
+    fn generate_id_is_stable() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        doc.root().append_child(a);
+        a.append_child(b);
+
+        let first = generate_id_test(&setup, b);
+        let second = generate_id_test(&setup, b);
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn generate_id_is_empty_for_empty_nodeset() {
+        evaluate_literal(GenerateId, args![nodeset![]], |r| {
+            assert_eq!(Ok(Value::String("".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn function_available_finds_registered_functions() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_function("exists", Count);
+        setup.context.set_function(("uri:ext", "exists"), Count);
+        setup.context.set_namespace("ext", "uri:ext");
+
+        let builtin = setup.evaluate(doc.root(), FunctionAvailable, args!["exists"]);
+        let prefixed = setup.evaluate(doc.root(), FunctionAvailable, args!["ext:exists"]);
+        let unknown = setup.evaluate(doc.root(), FunctionAvailable, args!["ext:missing"]);
+
+        assert_eq!(Ok(Value::Boolean(true)), builtin);
+        assert_eq!(Ok(Value::Boolean(true)), prefixed);
+        assert_eq!(Ok(Value::Boolean(false)), unknown);
+    }
+
+    #[test]
+    fn element_available_is_always_false() {
+        evaluate_literal(ElementAvailable, args!["template"], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+    }
+
+    #[test]
+    fn system_property_looks_up_prefixed_names() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_system_property(("uri:props", "answer"), 42.0);
+        setup.context.set_namespace("p", "uri:props");
+
+        let r = setup.evaluate(doc.root(), SystemProperty, args!["p:answer"]);
+
+        assert_eq!(Ok(Value::Number(42.0)), r);
+    }
+
+    #[test]
+    fn system_property_that_is_unknown_is_empty() {
+        evaluate_literal(SystemProperty, args!["unknown"], |r| {
+            assert_eq!(Ok(Value::String("".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn system_property_with_undeclared_prefix_is_an_error() {
+        evaluate_literal(SystemProperty, args!["xsl:version"], |r| {
+            assert_eq!(
+                Err(Error::UnknownNamespace {
+                    prefix: "xsl".to_owned()
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn unparsed_entity_uri_finds_registered_entities() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_unparsed_entity("logo", "http://example.com/logo.png");
+
+        let r = setup.evaluate(doc.root(), UnparsedEntityUri, args!["logo"]);
+
+        assert_eq!(
+            Ok(Value::String("http://example.com/logo.png".to_owned())),
+            r
+        );
+    }
+
+    #[test]
+    fn unparsed_entity_uri_is_empty_for_unknown_entities() {
+        evaluate_literal(UnparsedEntityUri, args!["logo"], |r| {
+            assert_eq!(Ok(Value::String("".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn unparsed_entity_uri_requires_one_argument() {
+        evaluate_literal(UnparsedEntityUri, args![], |r| {
+            assert_eq!(
+                Err(Error::NotEnoughArguments {
+                    expected: 1,
+                    actual: 0
+                }),
+                r
+            );
+        });
+        evaluate_literal(UnparsedEntityUri, args!["a", "b"], |r| {
+            assert_eq!(
+                Err(Error::TooManyArguments {
+                    expected: 1,
+                    actual: 2
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn key_with_undeclared_prefix_is_an_error() {
+        evaluate_literal(KeyFn, args!["nope:key", "value"], |r| {
+            assert_eq!(
+                Err(Error::UnknownNamespace {
+                    prefix: "nope".to_owned()
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn key_that_is_not_registered_is_an_error() {
+        evaluate_literal(KeyFn, args!["key", "value"], |r| {
+            assert_eq!(Err(Error::UnknownKey { name: "key".into() }), r);
+        });
+    }
+
+    #[test]
+    fn last_returns_context_size() {
+        evaluate_literal(Last, args![], |r| {
+            assert_eq!(Ok(Value::Number(1.0)), r);
+        });
+    }
+
+    #[test]
+    fn position_returns_context_position() {
+        evaluate_literal(Position, args![], |r| {
+            assert_eq!(Ok(Value::Number(1.0)), r);
+        });
+    }
+
+    #[test]
+    fn current_returns_the_starting_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let start = doc.create_element("start");
+        let other = doc.create_element("other");
+        let context = context::Evaluation::new(&setup.context, start.into());
+        let context = context.new_context_for(other);
+
+        let r = Current.evaluate(&context, args![]);
+
+        assert_eq!(Ok(Value::Nodeset(nodeset![start])), r);
+    }
+
+    #[test]
+    fn count_counts_nodes_in_nodeset() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), Count, args![nodeset![doc.root()]]);
 
         assert_eq!(Ok(Value::Number(1.0)), r);
     }
@@ -774,6 +1778,46 @@ mod test {
         });
     }
 
+    #[test]
+    fn local_name_gets_name_of_attribute() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("wow");
+        let a = e.set_attribute_value(("uri", "attr"), "value");
+
+        let r = setup.evaluate(doc.root(), LocalName, args![nodeset![a]]);
+
+        assert_eq!(Ok(Value::String("attr".to_owned())), r);
+    }
+
+    #[test]
+    fn local_name_of_processing_instruction_is_target() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let pi = doc.create_processing_instruction("target", Some("data"));
+
+        let r = setup.evaluate(doc.root(), LocalName, args![nodeset![pi]]);
+
+        assert_eq!(Ok(Value::String("target".to_owned())), r);
+    }
+
+    #[test]
+    fn local_name_defaults_to_the_context_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element(("uri", "wow"));
+
+        let r = setup.evaluate(e, LocalName, args![]);
+
+        assert_eq!(Ok(Value::String("wow".to_owned())), r);
+    }
+
     #[test]
     fn namespace_uri_gets_uri_of_element() {
         let package = Package::new();
@@ -788,6 +1832,19 @@ mod test {
         assert_eq!(Ok(Value::String("uri".to_owned())), r);
     }
 
+    #[test]
+    fn namespace_uri_is_empty_without_namespace() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("wow");
+
+        let r = setup.evaluate(e, NamespaceUri, args![]);
+
+        assert_eq!(Ok(Value::String("".to_owned())), r);
+    }
+
     #[test]
     fn name_uses_declared_prefix() {
         let package = Package::new();
@@ -800,26 +1857,83 @@ mod test {
 
         let r = setup.evaluate(doc.root(), Name, args![nodeset![e]]);
 
-        assert_eq!(Ok(Value::String("prefix:wow".to_owned())), r);
+        assert_eq!(Ok(Value::String("prefix:wow".to_owned())), r);
+    }
+
+    #[test]
+    fn name_uses_prefix_declared_on_ancestor() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let parent = doc.create_element("parent");
+        parent.register_prefix("prefix", "uri");
+        let e = doc.create_element(("uri", "wow"));
+        parent.append_child(e);
+
+        let r = setup.evaluate(e, Name, args![]);
+
+        assert_eq!(Ok(Value::String("prefix:wow".to_owned())), r);
+    }
+
+    #[test]
+    fn name_without_prefix_in_scope_is_local_name() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element(("uri", "wow"));
+
+        let r = setup.evaluate(e, Name, args![]);
+
+        assert_eq!(Ok(Value::String("wow".to_owned())), r);
+    }
+
+    #[test]
+    fn string_converts_to_string() {
+        evaluate_literal(StringFn, args![true], |r| {
+            assert_eq!(Ok(Value::String("true".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn concat_combines_strings() {
+        evaluate_literal(Concat, args!["hello", " ", "world"], |r| {
+            assert_eq!(Ok(Value::String("hello world".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn starts_with_checks_prefixes() {
+        evaluate_literal(starts_with(), args!["hello", "he"], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn ends_with_checks_suffixes() {
+        evaluate_literal(ends_with(), args!["hello", "llo"], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
     }
 
     #[test]
-    fn string_converts_to_string() {
-        evaluate_literal(StringFn, args![true], |r| {
-            assert_eq!(Ok(Value::String("true".to_owned())), r);
+    fn ends_with_rejects_other_suffixes() {
+        evaluate_literal(ends_with(), args!["hello", "he"], |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
         });
     }
 
     #[test]
-    fn concat_combines_strings() {
-        evaluate_literal(Concat, args!["hello", " ", "world"], |r| {
-            assert_eq!(Ok(Value::String("hello world".to_owned())), r);
+    fn ends_with_accepts_the_empty_suffix() {
+        evaluate_literal(ends_with(), args!["hello", ""], |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
         });
     }
 
     #[test]
-    fn starts_with_checks_prefixes() {
-        evaluate_literal(starts_with(), args!["hello", "he"], |r| {
+    fn ends_with_coerces_its_arguments() {
+        evaluate_literal(ends_with(), args![10.0, 0.0], |r| {
             assert_eq!(Ok(Value::Boolean(true)), r);
         });
     }
@@ -902,6 +2016,92 @@ mod test {
         );
     }
 
+    #[test]
+    fn substring_matches_the_spec_examples() {
+        assert_eq!("234", substring_test("12345", 2.0, 3.0));
+        assert_eq!("234", substring_test("12345", 1.5, 2.6));
+        assert_eq!("12", substring_test("12345", 0.0, 3.0));
+        assert_eq!("", substring_test("12345", f64::NAN, 3.0));
+        assert_eq!("", substring_test("12345", 1.0, f64::NAN));
+        assert_eq!("12345", substring_test("12345", -42.0, f64::INFINITY));
+        assert_eq!(
+            "",
+            substring_test("12345", f64::NEG_INFINITY, f64::INFINITY)
+        );
+
+        evaluate_literal(Substring, args!["12345", 2.0], |r| {
+            assert_eq!(Ok(Value::String("2345".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn substring_requires_two_or_three_arguments() {
+        evaluate_literal(Substring, args!["12345"], |r| {
+            assert_eq!(
+                Err(Error::NotEnoughArguments {
+                    expected: 2,
+                    actual: 1
+                }),
+                r
+            );
+        });
+    }
+
+    #[test]
+    fn string_defaults_to_the_context_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("hello");
+        e.append_child(doc.create_text("world"));
+
+        let r = setup.evaluate(e, StringFn, args![]);
+
+        assert_eq!(Ok(Value::String("world".to_owned())), r);
+    }
+
+    #[test]
+    fn string_of_empty_nodeset_is_empty() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), StringFn, args![nodeset![]]);
+
+        assert_eq!(Ok(Value::String("".to_owned())), r);
+    }
+
+    #[test]
+    fn string_formats_special_numbers() {
+        let cases = [
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+            (-0.0, "0"),
+            (1.5, "1.5"),
+        ];
+
+        for &(n, expected) in &cases {
+            evaluate_literal(StringFn, args![n], |r| {
+                assert_eq!(Ok(Value::String(expected.to_owned())), r);
+            });
+        }
+    }
+
+    #[test]
+    fn string_rejects_extra_arguments() {
+        evaluate_literal(StringFn, args!["a", "b"], |r| {
+            assert_eq!(
+                Err(Error::TooManyArguments {
+                    expected: 1,
+                    actual: 2
+                }),
+                r
+            );
+        });
+    }
+
     #[test]
     fn string_length_counts_characters() {
         evaluate_literal(StringLength, args!["日本語"], |r| {
@@ -909,6 +2109,20 @@ mod test {
         });
     }
 
+    #[test]
+    fn string_length_defaults_to_the_context_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("word");
+        e.append_child(doc.create_text("café"));
+
+        let r = setup.evaluate(e, StringLength, args![]);
+
+        assert_eq!(Ok(Value::Number(4.0)), r);
+    }
+
     #[test]
     fn normalize_space_removes_leading_space() {
         evaluate_literal(NormalizeSpace, args!["\t hello"], |r| {
@@ -930,6 +2144,42 @@ mod test {
         });
     }
 
+    #[test]
+    fn normalize_space_collapses_embedded_newlines() {
+        evaluate_literal(NormalizeSpace, args!["one\ntwo\n\nthree"], |r| {
+            assert_eq!(Ok(Value::String("one two three".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn normalize_space_of_only_whitespace_is_empty() {
+        evaluate_literal(NormalizeSpace, args![" \t\r\n "], |r| {
+            assert_eq!(Ok(Value::String("".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn normalize_space_preserves_non_xml_whitespace() {
+        evaluate_literal(NormalizeSpace, args!["\u{A0}a\u{2003}b "], |r| {
+            assert_eq!(Ok(Value::String("\u{A0}a\u{2003}b".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn normalize_space_defaults_to_the_context_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("messy");
+        e.append_child(doc.create_text("  hello \n"));
+        e.append_child(doc.create_text("\tworld  "));
+
+        let r = setup.evaluate(e, NormalizeSpace, args![]);
+
+        assert_eq!(Ok(Value::String("hello world".to_owned())), r);
+    }
+
     fn translate_test(s: &str, from: &str, to: &str) -> String {
         evaluate_literal(Translate, args![s, from, to], |r| match r {
             Ok(Value::String(s)) => s,
@@ -962,6 +2212,104 @@ mod test {
         assert_eq!("b", translate_test("a", "a", "bc"));
     }
 
+    #[test]
+    fn translate_matches_the_spec_examples() {
+        assert_eq!("BAr", translate_test("bar", "abc", "ABC"));
+        assert_eq!("AAA", translate_test("--aaa--", "abc-", "ABC"));
+    }
+
+    #[test]
+    fn translate_replaces_multi_byte_characters() {
+        assert_eq!("cafe", translate_test("café", "é", "e"));
+        assert_eq!("naïve", translate_test("naive", "i", "ï"));
+    }
+
+    #[test]
+    fn translate_uses_first_occurrence_even_when_it_deletes() {
+        assert_eq!("", translate_test("a", "aa", ""));
+    }
+
+    fn case_test(f: StringConvert, s: &str) -> String {
+        evaluate_literal(f, args![s], |r| match r {
+            Ok(Value::String(s)) => s,
+            r => panic!("case conversion failed: {:?}", r),
+        })
+    }
+
+    #[test]
+    fn upper_case_converts_letters() {
+        assert_eq!("HELLO, WORLD", case_test(upper_case(), "Hello, World"));
+        assert_eq!("ÉCOLE", case_test(upper_case(), "école"));
+    }
+
+    #[test]
+    fn upper_case_of_sharp_s_is_longer() {
+        assert_eq!("STRASSE", case_test(upper_case(), "straße"));
+    }
+
+    #[test]
+    fn lower_case_converts_letters() {
+        assert_eq!("hello, world", case_test(lower_case(), "Hello, World"));
+        assert_eq!("ß", case_test(lower_case(), "ß"));
+    }
+
+    #[test]
+    fn lower_case_of_dotted_capital_i_keeps_the_dot() {
+        assert_eq!("i\u{307}stanbul", case_test(lower_case(), "İSTANBUL"));
+    }
+
+    #[test]
+    fn case_conversion_coerces_its_argument() {
+        evaluate_literal(upper_case(), args![true], |r| {
+            assert_eq!(Ok(Value::String("TRUE".to_owned())), r);
+        });
+    }
+
+    #[test]
+    fn string_join_joins_in_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let list = doc.create_element("list");
+        let items: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|&t| {
+                let e = doc.create_element("item");
+                e.append_child(doc.create_text(t));
+                list.append_child(e);
+                e
+            })
+            .collect();
+        doc.root().append_child(list);
+
+        let r = setup.evaluate(
+            doc.root(),
+            StringJoin,
+            args![nodeset![items[2], items[0], items[1]], "-"],
+        );
+
+        assert_eq!(Ok(Value::String("a-b-c".to_owned())), r);
+    }
+
+    #[test]
+    fn string_join_of_empty_nodeset_is_empty() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), StringJoin, args![nodeset![], ", "]);
+
+        assert_eq!(Ok(Value::String("".to_owned())), r);
+    }
+
+    #[test]
+    fn string_join_returns_a_string_unchanged() {
+        evaluate_literal(StringJoin, args!["alone", ", "], |r| {
+            assert_eq!(Ok(Value::String("alone".to_owned())), r);
+        });
+    }
+
     #[test]
     fn boolean_converts_to_boolean() {
         evaluate_literal(BooleanFn, args!["false"], |r| {
@@ -969,6 +2317,73 @@ mod test {
         });
     }
 
+    fn lang_test(lang: &str, wanted: &str) -> bool {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let grandparent = doc.create_element("grandparent");
+        grandparent.set_attribute_value(("http://www.w3.org/XML/1998/namespace", "lang"), lang);
+        let parent = doc.create_element("parent");
+        grandparent.append_child(parent);
+        let text = doc.create_text("hello");
+        parent.append_child(text);
+
+        match setup.evaluate(text, Lang, args![wanted]) {
+            Ok(Value::Boolean(b)) => b,
+            r => panic!("lang failed: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn lang_is_inherited_from_ancestors() {
+        assert!(lang_test("en", "en"));
+    }
+
+    #[test]
+    fn lang_matches_sublanguages() {
+        assert!(lang_test("en-US", "en"));
+        assert!(!lang_test("en-US", "e"));
+        assert!(!lang_test("en", "en-US"));
+        assert!(!lang_test("english", "en"));
+    }
+
+    #[test]
+    fn lang_is_case_insensitive() {
+        assert!(lang_test("EN-us", "en-US"));
+    }
+
+    #[test]
+    fn lang_is_false_without_any_language() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("hello");
+
+        let r = setup.evaluate(e, Lang, args!["en"]);
+
+        assert_eq!(Ok(Value::Boolean(false)), r);
+    }
+
+    #[test]
+    fn lang_uses_the_nearest_declaration() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let xml_lang = ("http://www.w3.org/XML/1998/namespace", "lang");
+        let outer = doc.create_element("outer");
+        outer.set_attribute_value(xml_lang, "en");
+        let inner = doc.create_element("inner");
+        inner.set_attribute_value(xml_lang, "de");
+        outer.append_child(inner);
+
+        let r = setup.evaluate(inner, Lang, args!["en"]);
+
+        assert_eq!(Ok(Value::Boolean(false)), r);
+    }
+
     #[test]
     fn number_converts_to_number() {
         evaluate_literal(NumberFn, args![" -1.2 "], |r| {
@@ -976,6 +2391,49 @@ mod test {
         });
     }
 
+    #[test]
+    fn number_trims_surrounding_whitespace() {
+        evaluate_literal(NumberFn, args![" 12.5 "], |r| assert_number(12.5, r));
+    }
+
+    #[test]
+    fn number_of_garbage_is_nan() {
+        evaluate_literal(NumberFn, args!["abc"], |r| assert_number(f64::NAN, r));
+    }
+
+    #[test]
+    fn number_converts_booleans() {
+        evaluate_literal(NumberFn, args![true], |r| assert_number(1.0, r));
+        evaluate_literal(NumberFn, args![false], |r| assert_number(0.0, r));
+    }
+
+    #[test]
+    fn number_uses_string_value_of_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("count");
+        e.append_child(doc.create_text("4"));
+        e.append_child(doc.create_text("2"));
+
+        let r = setup.evaluate(doc.root(), NumberFn, args![nodeset![e]]);
+        assert_number(42.0, r);
+    }
+
+    #[test]
+    fn number_defaults_to_the_context_node() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let e = doc.create_element("count");
+        e.append_child(doc.create_text("7"));
+
+        let r = setup.evaluate(e, NumberFn, args![]);
+        assert_number(7.0, r);
+    }
+
     #[test]
     fn number_fails_with_nan() {
         evaluate_literal(NumberFn, args![" nope "], |r| assert_number(f64::NAN, r));
@@ -995,6 +2453,120 @@ mod test {
         assert_eq!(Ok(Value::Number(66.7)), r);
     }
 
+    #[test]
+    fn sum_is_nan_when_any_node_is_not_numeric() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let a = doc.create_text("1");
+        let b = doc.create_text("2");
+        let c = doc.create_text("garbage");
+
+        let r = setup.evaluate(doc.root(), Sum, args![nodeset![a, b, c]]);
+
+        assert_number(f64::NAN, r);
+    }
+
+    #[test]
+    fn sum_of_empty_nodeset_is_zero() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let setup = Setup::new();
+
+        let r = setup.evaluate(doc.root(), Sum, args![nodeset![]]);
+
+        assert_number(0.0, r);
+    }
+
+    #[test]
+    fn arity_accepts_counts_within_its_bounds() {
+        assert!(Arity::exactly(2).accepts(2));
+        assert!(!Arity::exactly(2).accepts(3));
+        assert!(!Arity::between(1, 2).accepts(0));
+        assert!(Arity::between(1, 2).accepts(1));
+        assert!(Arity::at_least(2).accepts(100));
+        assert!(!Arity::at_least(2).accepts(1));
+    }
+
+    #[test]
+    fn arity_describes_its_bounds() {
+        assert_eq!("exactly 1", Arity::exactly(1).to_string());
+        assert_eq!("between 2 and 3", Arity::between(2, 3).to_string());
+        assert_eq!("at least 2", Arity::at_least(2).to_string());
+    }
+
+    #[test]
+    fn function_library_collects_core_and_custom_functions() {
+        let functions = FunctionLibrary::new()
+            .with_core()
+            .with("custom", Last)
+            .build();
+
+        assert!(functions.contains_key(&"count".into()));
+        assert!(functions.contains_key(&"custom".into()));
+        assert!(!functions.contains_key(&"upper-case".into()));
+    }
+
+    #[test]
+    fn function_library_merge_reports_clashing_names() {
+        let mut library = FunctionLibrary::new().with_core();
+        let other = FunctionLibrary::new()
+            .with("count", Last)
+            .with("custom", Last);
+
+        let r = library.merge(other);
+
+        assert_eq!(Err(vec![OwnedQName::from("count")]), r);
+        let functions = library.build();
+        assert!(functions.contains_key(&"custom".into()));
+    }
+
+    #[test]
+    fn function_library_merge_without_clashes_succeeds() {
+        let mut library = FunctionLibrary::new().with_core();
+
+        let r = library.merge(FunctionLibrary::new().with_extended_strings());
+
+        assert_eq!(Ok(()), r);
+        assert!(library.build().contains_key(&"upper-case".into()));
+    }
+
+    #[test]
+    fn pop_nodeset_reports_the_index_of_the_argument() {
+        let mut args = Args(args![nodeset![], "a", 1.0]);
+
+        assert_eq!(Ok(1.0), args.pop_number());
+        assert_eq!(
+            Err(Error::ArgumentNotANodeset {
+                index: 1,
+                actual: ArgumentType::String
+            }),
+            args.pop_nodeset()
+        );
+    }
+
+    #[test]
+    fn pop_value_keeps_the_type_of_the_argument() {
+        let mut args = Args(args![true]);
+
+        assert_eq!(Ok(Value::Boolean(true)), args.pop_value());
+        assert_eq!(Err(Error::ArgumentMissing), args.pop_value());
+    }
+
+    #[test]
+    fn sum_requires_a_nodeset() {
+        evaluate_literal(Sum, args![1.0], |r| {
+            assert_eq!(
+                Err(Error::ArgumentNotANodeset {
+                    index: 0,
+                    actual: ArgumentType::Number
+                }),
+                r
+            );
+        });
+    }
+
     /// By default, NaN != NaN and -0.0 == 0.0. We don't want either
     /// of those to be true.
     struct PedanticNumber(f64);
@@ -1031,7 +2603,7 @@ mod test {
     fn assert_number(expected: f64, actual: Result<Value<'_>, Error>) {
         match actual {
             Ok(Value::Number(n)) => assert_eq!(PedanticNumber(n), PedanticNumber(expected)),
-            _ => assert!(false, "{:?} did not evaluate correctly", actual),
+            _ => panic!("{:?} did not evaluate correctly", actual),
         }
     }
 
@@ -1088,4 +2660,22 @@ mod test {
     fn round_pos_zero_point_five_to_pos_one() {
         evaluate_literal(round(), args![0.5], |r| assert_number(1.0, r));
     }
+
+    #[test]
+    fn round_ties_toward_positive_infinity() {
+        evaluate_literal(round(), args![2.5], |r| assert_number(3.0, r));
+        evaluate_literal(round(), args![-2.5], |r| assert_number(-2.0, r));
+    }
+
+    #[test]
+    fn round_small_negative_to_neg_zero() {
+        evaluate_literal(round(), args![-0.2], |r| assert_number(-0.0, r));
+    }
+
+    #[test]
+    fn round_just_below_half_rounds_down() {
+        evaluate_literal(round(), args![0.499_999_999_999_999_94], |r| {
+            assert_number(0.0, r)
+        });
+    }
 }
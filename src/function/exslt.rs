@@ -0,0 +1,142 @@
+//! Functions from the [EXSLT][] extension libraries.
+//!
+//! None of these functions are part of XPath 1.0, so they are not
+//! registered by [`Context::new`](../../context/struct.Context.html#method.new).
+//! Each library has a registration function that adds its functions
+//! to a context and binds the library's conventional namespace prefix.
+//! [`register_all`](fn.register_all.html) adds every library at once.
+//!
+//! The regular expression library is only built with the `regexp`
+//! feature.
+//!
+//! Functions that return new nodes, such as `str:tokenize`, create
+//! them in the context's
+//! [scratch document](../../context/struct.Context.html#method.set_scratch_document).
+//!
+//! [EXSLT]: http://exslt.org/
+
+use std::fmt;
+
+use crate::context;
+use crate::function::Error;
+use crate::nodeset::Nodeset;
+
+mod common;
+mod dates;
+mod dynamic;
+mod math;
+#[cfg(feature = "regexp")]
+mod regexp;
+mod sets;
+mod strings;
+
+/// Creates an element with the given name for each string, containing
+/// that string as text, in the scratch document.
+fn text_elements<'d, I>(
+    context: &context::Evaluation<'_, 'd>,
+    name: &str,
+    texts: I,
+) -> Result<Nodeset<'d>, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let doc = context.scratch_document()?;
+    let container = context.scratch_container()?;
+    let mut nodes = Nodeset::new();
+
+    for text in texts {
+        let element = doc.create_element(name);
+        if !text.is_empty() {
+            element.append_child(doc.create_text(&text));
+        }
+        container.append_child(element);
+        nodes.add(element);
+    }
+
+    Ok(nodes)
+}
+
+pub use self::common::{register_exslt_common, COMMON_NAMESPACE_URI, COMMON_PREFIX};
+pub use self::dates::{register_exslt_dates, DATES_NAMESPACE_URI, DATES_PREFIX};
+pub use self::dynamic::{register_exslt_dynamic, DYNAMIC_NAMESPACE_URI, DYNAMIC_PREFIX};
+pub use self::math::{register_exslt_math, MATH_NAMESPACE_URI, MATH_PREFIX};
+#[cfg(feature = "regexp")]
+pub use self::regexp::{register_exslt_regexp, REGEXP_NAMESPACE_URI, REGEXP_PREFIX};
+pub use self::sets::{register_exslt_sets, SETS_NAMESPACE_URI, SETS_PREFIX};
+pub use self::strings::{register_exslt_strings, STRINGS_NAMESPACE_URI, STRINGS_PREFIX};
+
+/// A conventional EXSLT prefix that was already bound to a different
+/// namespace when registering every library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixCollision {
+    /// The conventional prefix, such as `str`
+    pub prefix: String,
+    /// The namespace the prefix was already bound to, and still is
+    pub existing_uri: String,
+    /// The namespace of the EXSLT library
+    pub library_uri: String,
+}
+
+impl fmt::Display for PrefixCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "prefix {} is bound to {}, not {}",
+            self.prefix, self.existing_uri, self.library_uri
+        )
+    }
+}
+
+type Registration = fn(&mut context::Context<'_>);
+
+/// Adds the functions of every EXSLT library and binds their
+/// conventional prefixes: `exsl`, `date`, `dyn`, `math`, `set`, `str`
+/// and, with the `regexp` feature, `regexp`.
+///
+/// A prefix already bound to another namespace is left as it is, and
+/// reported in the returned error; the library's functions are still
+/// added and can be called through any prefix bound to its namespace.
+pub fn register_all(context: &mut context::Context<'_>) -> Result<(), Vec<PrefixCollision>> {
+    #[allow(unused_mut)]
+    let mut libraries: Vec<(&str, &str, Registration)> = vec![
+        (COMMON_PREFIX, COMMON_NAMESPACE_URI, register_exslt_common),
+        (DATES_PREFIX, DATES_NAMESPACE_URI, register_exslt_dates),
+        (
+            DYNAMIC_PREFIX,
+            DYNAMIC_NAMESPACE_URI,
+            register_exslt_dynamic,
+        ),
+        (MATH_PREFIX, MATH_NAMESPACE_URI, register_exslt_math),
+        (SETS_PREFIX, SETS_NAMESPACE_URI, register_exslt_sets),
+        (
+            STRINGS_PREFIX,
+            STRINGS_NAMESPACE_URI,
+            register_exslt_strings,
+        ),
+    ];
+    #[cfg(feature = "regexp")]
+    libraries.push((REGEXP_PREFIX, REGEXP_NAMESPACE_URI, register_exslt_regexp));
+    let mut collisions = Vec::new();
+
+    for &(prefix, uri, register) in &libraries {
+        let existing = context.namespace_for(prefix).map(str::to_owned);
+        register(context);
+
+        if let Some(existing) = existing {
+            if existing != uri {
+                context.set_namespace(prefix, &existing);
+                collisions.push(PrefixCollision {
+                    prefix: prefix.to_owned(),
+                    existing_uri: existing,
+                    library_uri: uri.to_owned(),
+                });
+            }
+        }
+    }
+
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(collisions)
+    }
+}
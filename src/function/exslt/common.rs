@@ -0,0 +1,180 @@
+//! The [EXSLT common][common] library.
+//!
+//! [common]: http://exslt.org/exsl/index.html
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::Value;
+
+/// The namespace URI of the common library.
+pub const COMMON_NAMESPACE_URI: &str = "http://exslt.org/common";
+/// The prefix conventionally bound to the common library.
+pub const COMMON_PREFIX: &str = "exsl";
+
+/// Returns nodesets unchanged. Any other value is converted to a
+/// string and wrapped in a text node in the scratch document.
+struct NodeSet;
+
+impl Function for NodeSet {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+
+        match args.pop_value()? {
+            Value::Nodeset(nodes) => Ok(Value::Nodeset(nodes)),
+            other => {
+                let doc = context.scratch_document()?;
+                let text = doc.create_text(&other.into_string());
+                context.scratch_container()?.append_child(text);
+                Ok(Value::Nodeset(nodeset![text]))
+            }
+        }
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+/// Names the type of its argument. Result tree fragments are not a
+/// separate kind of value here, so `"RTF"` is never returned; created
+/// nodes are reported as `"node-set"`.
+struct ObjectType;
+
+impl Function for ObjectType {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+
+        let name = match args.pop_value()? {
+            Value::String(_) => "string",
+            Value::Number(_) => "number",
+            Value::Boolean(_) => "boolean",
+            Value::Nodeset(_) => "node-set",
+        };
+        Ok(Value::String(name.to_owned()))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+/// Adds the EXSLT common functions and binds the `exsl` prefix to
+/// their namespace.
+pub fn register_exslt_common(context: &mut context::Context<'_>) {
+    context.set_function((COMMON_NAMESPACE_URI, "node-set"), NodeSet);
+    context.set_function((COMMON_NAMESPACE_URI, "object-type"), ObjectType);
+    context.set_namespace(COMMON_PREFIX, COMMON_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::function::{Error, Function};
+    use crate::nodeset::Node;
+    use crate::{LiteralValue, Value};
+
+    use super::{NodeSet, ObjectType};
+
+    fn evaluate_node_set(arg: LiteralValue) -> Result<Vec<String>, Error> {
+        let package = Package::new();
+        let doc = package.as_document();
+        let scratch = Package::new();
+        let mut context = context::Context::without_core_functions();
+        context.set_scratch_document(scratch.as_document());
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        NodeSet.evaluate(&evaluation, vec![arg]).map(|r| match r {
+            Value::Nodeset(nodes) => nodes
+                .iter()
+                .map(|n| match n {
+                    Node::Text(t) => t.text().to_owned(),
+                    n => panic!("expected a text node, got {:?}", n),
+                })
+                .collect(),
+            r => panic!("expected a nodeset, got {:?}", r),
+        })
+    }
+
+    #[test]
+    fn node_set_wraps_strings_in_text_nodes() {
+        assert_eq!(
+            Ok(vec!["hello".to_owned()]),
+            evaluate_node_set(Value::String("hello".into()))
+        );
+    }
+
+    #[test]
+    fn node_set_converts_numbers_and_booleans_to_strings() {
+        assert_eq!(
+            Ok(vec!["1.5".to_owned()]),
+            evaluate_node_set(Value::Number(1.5))
+        );
+        assert_eq!(
+            Ok(vec!["true".to_owned()]),
+            evaluate_node_set(Value::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn node_set_returns_nodesets_unchanged() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let e = doc.create_element("e");
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let r = NodeSet.evaluate(&evaluation, vec![Value::Nodeset(nodeset![e])]);
+
+        assert_eq!(Ok(Value::Nodeset(nodeset![e])), r);
+    }
+
+    #[test]
+    fn node_set_of_a_string_needs_a_scratch_document() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let r = NodeSet.evaluate(&evaluation, vec![Value::String("x".into())]);
+
+        assert_eq!(Err(Error::NoScratchDocument), r);
+    }
+
+    #[test]
+    fn object_type_requires_exactly_one_argument() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let none = ObjectType.evaluate(&evaluation, vec![]);
+        let two = ObjectType.evaluate(&evaluation, vec![Value::Number(1.0), Value::Number(2.0)]);
+
+        assert_eq!(
+            Err(Error::NotEnoughArguments {
+                expected: 1,
+                actual: 0
+            }),
+            none
+        );
+        assert_eq!(
+            Err(Error::TooManyArguments {
+                expected: 1,
+                actual: 2
+            }),
+            two
+        );
+    }
+}
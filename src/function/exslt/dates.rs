@@ -0,0 +1,1570 @@
+//! The [EXSLT dates and times][dates] library.
+//!
+//! Dates are the ISO 8601 forms used by XML Schema: `dateTime`,
+//! `date`, `time`, `gYearMonth`, `gYear`, `gMonthDay`, `gMonth` and
+//! `gDay`. Years are proleptic Gregorian and the present is read from
+//! the system clock in UTC, unless fixed with
+//! [`Context::set_current_date_time`](../../../context/struct.Context.html#method.set_current_date_time).
+//!
+//! [dates]: http://exslt.org/date/index.html
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::Value;
+
+/// The namespace URI of the dates and times library.
+pub const DATES_NAMESPACE_URI: &str = "http://exslt.org/dates-and-times";
+/// The prefix conventionally bound to the dates and times library.
+pub const DATES_PREFIX: &str = "date";
+
+const SECONDS_PER_DAY: i64 = 86_400;
+/// The largest year, positive or negative, that dates may have.
+const MAX_YEAR: i64 = 999_999_999;
+
+/// Which of the XML Schema date and time types a value is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    DateTime,
+    Date,
+    Time,
+    YearMonth,
+    Year,
+    MonthDay,
+    Month,
+    Day,
+}
+
+/// A parsed date or time. Fields that the kind does not have are left
+/// at their lowest value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct DateTime {
+    kind: Kind,
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    /// The offset from UTC in minutes, if there is a timezone
+    timezone: Option<i32>,
+}
+
+impl DateTime {
+    fn new(kind: Kind) -> DateTime {
+        DateTime {
+            kind,
+            year: 1,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            nanos: 0,
+            timezone: None,
+        }
+    }
+
+    fn parse(s: &str) -> Option<DateTime> {
+        let mut cursor = Cursor::new(s.trim());
+
+        let mut dt = if cursor.eat_str("---") {
+            let mut dt = DateTime::new(Kind::Day);
+            dt.day = cursor.number(2)? as u32;
+            dt
+        } else if cursor.eat_str("--") {
+            let mut dt = DateTime::new(Kind::Month);
+            dt.month = cursor.number(2)? as u32;
+            if cursor.eat(b'-') {
+                dt.kind = Kind::MonthDay;
+                dt.day = cursor.number(2)? as u32;
+            }
+            dt
+        } else if cursor.peek_at(2) == Some(b':') {
+            let mut dt = DateTime::new(Kind::Time);
+            cursor.time(&mut dt)?;
+            dt
+        } else {
+            let mut dt = DateTime::new(Kind::Year);
+            dt.year = cursor.year()?;
+            if cursor.eat(b'-') {
+                dt.kind = Kind::YearMonth;
+                dt.month = cursor.number(2)? as u32;
+                if cursor.eat(b'-') {
+                    dt.kind = Kind::Date;
+                    dt.day = cursor.number(2)? as u32;
+                    if cursor.eat(b'T') {
+                        dt.kind = Kind::DateTime;
+                        cursor.time(&mut dt)?;
+                    }
+                }
+            }
+            dt
+        };
+
+        dt.timezone = cursor.timezone()?;
+        if !cursor.is_done() || !dt.is_valid() {
+            return None;
+        }
+        Some(dt)
+    }
+
+    fn is_valid(&self) -> bool {
+        let days = match self.kind {
+            // Without a year, February may always have 29 days
+            Kind::MonthDay | Kind::Month | Kind::Day => days_in_month(2000, self.month),
+            _ => days_in_month(self.year, self.month),
+        };
+
+        self.year != 0
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    fn has_date(&self) -> bool {
+        match self.kind {
+            Kind::DateTime | Kind::Date => true,
+            _ => false,
+        }
+    }
+
+    fn has_time(&self) -> bool {
+        match self.kind {
+            Kind::DateTime | Kind::Time => true,
+            _ => false,
+        }
+    }
+
+    fn year(&self) -> Option<f64> {
+        match self.kind {
+            Kind::DateTime | Kind::Date | Kind::YearMonth | Kind::Year => Some(self.year as f64),
+            _ => None,
+        }
+    }
+
+    fn month(&self) -> Option<f64> {
+        match self.kind {
+            Kind::Time | Kind::Year | Kind::Day => None,
+            _ => Some(f64::from(self.month)),
+        }
+    }
+
+    fn day(&self) -> Option<f64> {
+        match self.kind {
+            Kind::DateTime | Kind::Date | Kind::MonthDay | Kind::Day => Some(f64::from(self.day)),
+            _ => None,
+        }
+    }
+
+    fn hour(&self) -> Option<f64> {
+        if self.has_time() {
+            Some(f64::from(self.hour))
+        } else {
+            None
+        }
+    }
+
+    fn minute(&self) -> Option<f64> {
+        if self.has_time() {
+            Some(f64::from(self.minute))
+        } else {
+            None
+        }
+    }
+
+    fn second(&self) -> Option<f64> {
+        if self.has_time() {
+            Some(Secs::new(i64::from(self.second), self.nanos).to_f64())
+        } else {
+            None
+        }
+    }
+
+    /// The moment `seconds` after 1970-01-01T00:00:00Z, in UTC, or
+    /// `None` if that is too far away to be represented.
+    fn from_unix_seconds(seconds: Secs) -> Option<DateTime> {
+        let days = floor_div(seconds.whole, SECONDS_PER_DAY);
+        let time = seconds.whole - days * SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days)?;
+
+        Some(DateTime {
+            kind: Kind::DateTime,
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u32,
+            minute: (time % 3600 / 60) as u32,
+            second: (time % 60) as u32,
+            nanos: seconds.nanos,
+            timezone: Some(0),
+        })
+    }
+
+    /// The number of seconds since 1970-01-01T00:00:00Z. Dates
+    /// without a timezone are taken to be in UTC, and missing
+    /// months and days are the first of the year or month.
+    fn unix_seconds(&self) -> Option<Secs> {
+        match self.kind {
+            Kind::DateTime | Kind::Date | Kind::YearMonth | Kind::Year => {}
+            _ => return None,
+        }
+        let days = days_from_civil(self.year, self.month, self.day)?;
+        let offset = i64::from(self.timezone.unwrap_or(0)) * 60;
+        let seconds = days
+            .checked_mul(SECONDS_PER_DAY)?
+            .checked_add(self.seconds_of_day() - offset)?;
+        Some(Secs::new(seconds, self.nanos))
+    }
+
+    fn seconds_of_day(&self) -> i64 {
+        i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+
+    /// Adds a duration following [XML Schema][add]: the months are
+    /// added first, clamping the day to the end of the month, and
+    /// then the seconds. The result has the same kind and timezone.
+    ///
+    /// [add]: https://www.w3.org/TR/xmlschema-2/#adding-durations-to-dateTimes
+    fn add(&self, duration: &Duration) -> Option<DateTime> {
+        match self.kind {
+            Kind::DateTime | Kind::Date | Kind::YearMonth | Kind::Year => {}
+            _ => return None,
+        }
+        let sign = if duration.negative { -1 } else { 1 };
+
+        let months = self
+            .year
+            .checked_mul(12)?
+            .checked_add(i64::from(self.month) - 1)?
+            .checked_add(sign * duration.months)?;
+        let year = floor_div(months, 12);
+        let month = (months - year * 12 + 1) as u32;
+        let day = self.day.min(days_in_month(year, month));
+
+        let days = days_from_civil(year, month, day)?.checked_add(sign * duration.days)?;
+        let start = Secs::new(
+            days.checked_mul(SECONDS_PER_DAY)?
+                .checked_add(self.seconds_of_day())?,
+            self.nanos,
+        );
+        let offset = if duration.negative {
+            duration.seconds.checked_neg()?
+        } else {
+            duration.seconds
+        };
+        let moved = DateTime::from_unix_seconds(start.checked_add(offset)?)?;
+
+        let mut result = DateTime {
+            kind: self.kind,
+            timezone: self.timezone,
+            ..moved
+        };
+        if !self.has_date() {
+            result.day = 1;
+        }
+        if self.kind == Kind::Year {
+            result.month = 1;
+        }
+        // Year zero cannot be written, so it cannot be a result either
+        if result.year == 0 || result.year.abs() > MAX_YEAR {
+            return None;
+        }
+        Some(result)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let year = |f: &mut fmt::Formatter<'_>| {
+            if self.year < 0 {
+                write!(f, "-{:04}", -self.year)
+            } else {
+                write!(f, "{:04}", self.year)
+            }
+        };
+
+        match self.kind {
+            Kind::DateTime | Kind::Date | Kind::YearMonth | Kind::Year => {
+                year(f)?;
+                if self.kind != Kind::Year {
+                    write!(f, "-{:02}", self.month)?;
+                }
+                if self.has_date() {
+                    write!(f, "-{:02}", self.day)?;
+                }
+                if self.kind == Kind::DateTime {
+                    f.write_str("T")?;
+                }
+            }
+            Kind::MonthDay => write!(f, "--{:02}-{:02}", self.month, self.day)?,
+            Kind::Month => write!(f, "--{:02}", self.month)?,
+            Kind::Day => write!(f, "---{:02}", self.day)?,
+            Kind::Time => {}
+        }
+
+        if self.has_time() {
+            write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)?;
+            write_fraction(f, self.nanos)?;
+        }
+
+        match self.timezone {
+            None => Ok(()),
+            Some(0) => f.write_str("Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+/// A number of seconds, kept as whole seconds and nanoseconds so that
+/// decimal fractions such as `0.1` stay exact. The nanoseconds are
+/// never negative: -0.25 seconds is -1 second and 750,000,000
+/// nanoseconds.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct Secs {
+    whole: i64,
+    nanos: u32,
+}
+
+const NANOS_PER_SECOND: u32 = 1_000_000_000;
+
+impl Secs {
+    fn new(whole: i64, nanos: u32) -> Secs {
+        Secs { whole, nanos }
+    }
+
+    fn is_negative(&self) -> bool {
+        self.whole < 0
+    }
+
+    fn checked_add(self, other: Secs) -> Option<Secs> {
+        let nanos = self.nanos + other.nanos;
+        let carry = if nanos >= NANOS_PER_SECOND { 1 } else { 0 };
+        let whole = self.whole.checked_add(other.whole)?.checked_add(carry)?;
+        Some(Secs::new(whole, nanos - carry as u32 * NANOS_PER_SECOND))
+    }
+
+    fn checked_neg(self) -> Option<Secs> {
+        if self.nanos == 0 {
+            Some(Secs::new(self.whole.checked_neg()?, 0))
+        } else {
+            let whole = self.whole.checked_add(1)?.checked_neg()?;
+            Some(Secs::new(whole, NANOS_PER_SECOND - self.nanos))
+        }
+    }
+
+    fn checked_sub(self, other: Secs) -> Option<Secs> {
+        self.checked_add(other.checked_neg()?)
+    }
+
+    /// The nearest number to the exact decimal value.
+    fn to_f64(self) -> f64 {
+        match self.checked_neg() {
+            Some(positive) if self.is_negative() => -positive.to_f64(),
+            _ => format!("{}.{:09}", self.whole, self.nanos)
+                .parse()
+                .unwrap_or(::std::f64::NAN),
+        }
+    }
+}
+
+/// Reads the digits after a decimal point as nanoseconds. Digits
+/// beyond the ninth are dropped.
+fn to_nanos(digits: &[u8]) -> u32 {
+    (0..9).fold(0, |acc, i| {
+        let digit = digits.get(i).map_or(0, |&d| u32::from(d - b'0'));
+        acc * 10 + digit
+    })
+}
+
+/// Writes the nanoseconds as a decimal fraction, such as `.25`,
+/// without trailing zeros. Nothing is written for zero.
+fn write_fraction(f: &mut fmt::Formatter<'_>, nanos: u32) -> fmt::Result {
+    if nanos == 0 {
+        return Ok(());
+    }
+    let digits = format!("{:09}", nanos);
+    write!(f, ".{}", digits.trim_end_matches('0'))
+}
+
+/// An ISO 8601 duration such as `-P1Y2M3DT4H5M6.5S`. Years and
+/// months have no fixed length, so they are kept apart from the
+/// rest of the duration, as are days so that even the longest
+/// durations that can be written fit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Duration {
+    negative: bool,
+    months: i64,
+    days: i64,
+    seconds: Secs,
+}
+
+impl Duration {
+    fn parse(s: &str) -> Option<Duration> {
+        let mut cursor = Cursor::new(s.trim());
+        let negative = cursor.eat(b'-');
+        if !cursor.eat(b'P') {
+            return None;
+        }
+
+        let mut duration = Duration {
+            negative,
+            months: 0,
+            days: 0,
+            seconds: Secs::default(),
+        };
+        let mut any = false;
+        let mut in_time = false;
+        // Each designator may appear once, in this order
+        let mut allowed: &[u8] = b"YMD";
+
+        while !cursor.is_done() {
+            if !in_time && cursor.eat(b'T') {
+                in_time = true;
+                allowed = b"HMS";
+                // A T must be followed by at least one time component
+                if cursor.is_done() {
+                    return None;
+                }
+                continue;
+            }
+
+            let digits = cursor.digits();
+            if digits.is_empty() || digits.len() > 15 {
+                return None;
+            }
+            let value = to_number(digits);
+            let mut nanos = 0;
+            if in_time && cursor.eat(b'.') {
+                let fraction = cursor.digits();
+                if fraction.is_empty() {
+                    return None;
+                }
+                nanos = to_nanos(fraction);
+                if cursor.peek() != Some(b'S') {
+                    return None;
+                }
+            }
+
+            let designator = cursor.peek()?;
+            let position = allowed.iter().position(|&d| d == designator)?;
+            allowed = &allowed[position + 1..];
+            cursor.pos += 1;
+            any = true;
+
+            let seconds = match (in_time, designator) {
+                (false, b'Y') => {
+                    duration.months += value * 12;
+                    continue;
+                }
+                (false, b'M') => {
+                    duration.months += value;
+                    continue;
+                }
+                (false, b'D') => {
+                    duration.days = value;
+                    continue;
+                }
+                (true, b'H') => Secs::new(value * 3600, 0),
+                (true, b'M') => Secs::new(value * 60, 0),
+                _ => Secs::new(value, nanos),
+            };
+            duration.seconds = duration.seconds.checked_add(seconds)?;
+        }
+
+        if any {
+            Some(duration)
+        } else {
+            None
+        }
+    }
+
+    /// The length of the duration in seconds, counting a year as 365
+    /// days and a month as 30 days.
+    fn total_seconds(&self) -> f64 {
+        let days = self.months / 12 * 365 + self.months % 12 * 30 + self.days;
+        let seconds = days as f64 * SECONDS_PER_DAY as f64 + self.seconds.to_f64();
+        if self.negative {
+            -seconds
+        } else {
+            seconds
+        }
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        f.write_str("P")?;
+
+        let (years, months) = (self.months / 12, self.months % 12);
+        if years != 0 {
+            write!(f, "{}Y", years)?;
+        }
+        if months != 0 {
+            write!(f, "{}M", months)?;
+        }
+
+        let whole = self.seconds.whole;
+        let nanos = self.seconds.nanos;
+        let days = self.days + whole / SECONDS_PER_DAY;
+        let hours = whole % SECONDS_PER_DAY / 3600;
+        let minutes = whole % 3600 / 60;
+        let seconds = whole % 60;
+
+        if days != 0 {
+            write!(f, "{}D", days)?;
+        }
+        if hours != 0 || minutes != 0 || seconds != 0 || nanos != 0 {
+            f.write_str("T")?;
+            if hours != 0 {
+                write!(f, "{}H", hours)?;
+            }
+            if minutes != 0 {
+                write!(f, "{}M", minutes)?;
+            }
+            if seconds != 0 || nanos != 0 {
+                write!(f, "{}", seconds)?;
+                write_fraction(f, nanos)?;
+                f.write_str("S")?;
+            }
+        } else if self.months == 0 && days == 0 {
+            f.write_str("T0S")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the pieces of an ISO 8601 string.
+struct Cursor<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(s: &'a str) -> Self {
+        Cursor {
+            s: s.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.s.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.s.get(self.pos + offset).cloned()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        if self.s[self.pos..].starts_with(s.as_bytes()) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads a run of digits, returning the digits.
+    fn digits(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().map_or(false, |b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        &self.s[start..self.pos]
+    }
+
+    /// Reads exactly `width` digits.
+    fn number(&mut self, width: usize) -> Option<i64> {
+        let digits = self.digits();
+        if digits.len() != width {
+            return None;
+        }
+        Some(to_number(digits))
+    }
+
+    /// Reads a year of at least four digits, without superfluous
+    /// leading zeros, and an optional sign. Years beyond
+    /// `MAX_YEAR` are rejected.
+    fn year(&mut self) -> Option<i64> {
+        let negative = self.eat(b'-');
+        let digits = self.digits();
+        if digits.len() < 4 || (digits.len() > 4 && digits[0] == b'0') || digits.len() > 9 {
+            return None;
+        }
+        let year = to_number(digits);
+        Some(if negative { -year } else { year })
+    }
+
+    fn time(&mut self, dt: &mut DateTime) -> Option<()> {
+        dt.hour = self.number(2)? as u32;
+        if !self.eat(b':') {
+            return None;
+        }
+        dt.minute = self.number(2)? as u32;
+        if !self.eat(b':') {
+            return None;
+        }
+        dt.second = self.number(2)? as u32;
+        if self.eat(b'.') {
+            let digits = self.digits();
+            if digits.is_empty() {
+                return None;
+            }
+            dt.nanos = to_nanos(digits);
+        }
+        Some(())
+    }
+
+    /// Reads an optional timezone. The outer `None` means the
+    /// timezone is malformed.
+    fn timezone(&mut self) -> Option<Option<i32>> {
+        if self.eat(b'Z') {
+            return Some(Some(0));
+        }
+        let sign = if self.eat(b'+') {
+            1
+        } else if self.eat(b'-') {
+            -1
+        } else {
+            return Some(None);
+        };
+        let hours = self.number(2)?;
+        if !self.eat(b':') {
+            return None;
+        }
+        let minutes = self.number(2)?;
+        if minutes >= 60 || hours * 60 + minutes > 14 * 60 {
+            return None;
+        }
+        Some(Some(sign * (hours * 60 + minutes) as i32))
+    }
+}
+
+fn to_number(digits: &[u8]) -> i64 {
+    digits
+        .iter()
+        .fold(0, |acc, &d| acc * 10 + i64::from(d - b'0'))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Converts a day count since 1970-01-01 to a year, month and day,
+/// or `None` if the day count is too large.
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> Option<(i64, u32, u32)> {
+    let z = days.checked_add(719_468)?;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some((year, month, day))
+}
+
+/// Converts a year, month and day to a day count since 1970-01-01,
+/// or `None` if the year is too large.
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = if year >= 0 {
+        year
+    } else {
+        year.checked_sub(399)?
+    } / 400;
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era.checked_mul(146_097)?.checked_add(doe - 719_468)
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+/// The present, either as fixed by the context or from the system
+/// clock.
+fn now(context: &context::Evaluation<'_, '_>) -> Option<DateTime> {
+    match context.fixed_date_time() {
+        Some(fixed) => DateTime::parse(fixed),
+        None => {
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            let seconds = Secs::new(since_epoch.as_secs() as i64, 0);
+            DateTime::from_unix_seconds(seconds)
+        }
+    }
+}
+
+struct DateTimeFn;
+
+impl Function for DateTimeFn {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let args = Args(args);
+        args.exactly(0)?;
+        let now = now(context).map_or_else(String::new, |now| now.to_string());
+        Ok(Value::String(now))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(0))
+    }
+}
+
+/// Extracts one component of a date, defaulting to the present. The
+/// result is NaN if the date cannot be parsed or lacks the component.
+struct Component(fn(&DateTime) -> Option<f64>);
+
+impl Function for Component {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+        let date = if args.is_empty() {
+            now(context)
+        } else {
+            DateTime::parse(&args.pop_string()?)
+        };
+
+        let r = date.and_then(|d| self.0(&d)).unwrap_or(::std::f64::NAN);
+        Ok(Value::Number(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
+}
+
+/// The difference between two dates as a duration. Dates with only
+/// years and months differ by years and months; any others differ
+/// by days and time. Unparseable dates produce the empty string.
+struct Difference;
+
+impl Difference {
+    fn difference(start: &str, end: &str) -> Option<Duration> {
+        let start = DateTime::parse(start)?;
+        let end = DateTime::parse(end)?;
+        let coarse = |d: &DateTime| d.kind == Kind::YearMonth || d.kind == Kind::Year;
+
+        let (months, seconds) = if coarse(&start) || coarse(&end) {
+            let months = |d: &DateTime| d.year * 12 + i64::from(d.month);
+            (months(&end) - months(&start), Secs::default())
+        } else {
+            let seconds = end.unix_seconds()?.checked_sub(start.unix_seconds()?)?;
+            (0, seconds)
+        };
+        let negative = months < 0 || seconds.is_negative();
+
+        Some(Duration {
+            negative,
+            months: months.abs(),
+            days: 0,
+            seconds: if negative {
+                seconds.checked_neg()?
+            } else {
+                seconds
+            },
+        })
+    }
+}
+
+impl Function for Difference {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let end = args.pop_string()?;
+        let start = args.pop_string()?;
+
+        let r = Difference::difference(&start, &end).map_or_else(String::new, |d| d.to_string());
+        Ok(Value::String(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// Adds a duration to a date. Unparseable arguments produce the empty
+/// string.
+struct Add;
+
+impl Function for Add {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let duration = args.pop_string()?;
+        let date = args.pop_string()?;
+
+        let r = DateTime::parse(&date)
+            .and_then(|date| date.add(&Duration::parse(&duration)?))
+            .map_or_else(String::new, |d| d.to_string());
+        Ok(Value::String(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// The seconds since 1970-01-01T00:00:00Z of a date, or the seconds
+/// in a duration, defaulting to the present. Unparseable arguments
+/// produce NaN.
+struct Seconds;
+
+impl Function for Seconds {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.at_most(1)?;
+
+        let r = if args.is_empty() {
+            now(context)
+                .and_then(|d| d.unix_seconds())
+                .map(Secs::to_f64)
+        } else {
+            let s = args.pop_string()?;
+            match DateTime::parse(&s) {
+                Some(date) => date.unix_seconds().map(Secs::to_f64),
+                None => Duration::parse(&s).map(|d| d.total_seconds()),
+            }
+        };
+        Ok(Value::Number(r.unwrap_or(::std::f64::NAN)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(0, 1))
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Formats a date with a `java.text.SimpleDateFormat` pattern. Fields
+/// that the date does not have, and unsupported pattern letters, are
+/// left blank.
+fn format_date(date: &DateTime, pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // Two quotes are a literal quote, inside or outside quoting
+            if chars.peek() == Some(&'\'') {
+                chars.next();
+                result.push('\'');
+                continue;
+            }
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                        result.push('\'');
+                    } else {
+                        break;
+                    }
+                } else {
+                    result.push(c);
+                }
+            }
+            continue;
+        }
+
+        if !c.is_ascii_alphabetic() {
+            result.push(c);
+            continue;
+        }
+
+        let mut width = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            width += 1;
+        }
+        if let Some(field) = format_field(date, c, width) {
+            result.push_str(&field);
+        }
+    }
+
+    result
+}
+
+fn format_field(date: &DateTime, letter: char, width: usize) -> Option<String> {
+    let number = |n: i64| {
+        let digits = format!("{:0width$}", n.abs(), width = width);
+        if n < 0 {
+            format!("-{}", digits)
+        } else {
+            digits
+        }
+    };
+    let text = |full: &str| {
+        if width >= 4 {
+            full.to_owned()
+        } else {
+            full[..3].to_owned()
+        }
+    };
+    let has_year = date.year().is_some();
+    let has_month = date.month().is_some();
+    let has_day = date.day().is_some();
+    let has_time = date.has_time();
+
+    let field = match letter {
+        'G' if has_year => (if date.year > 0 { "AD" } else { "BC" }).to_owned(),
+        'y' if has_year && width == 2 => format!("{:02}", date.year.abs() % 100),
+        'y' if has_year => number(date.year),
+        'M' if has_month && width >= 3 => text(MONTH_NAMES[date.month as usize - 1]),
+        'M' if has_month => number(i64::from(date.month)),
+        'd' if has_day => number(i64::from(date.day)),
+        'D' if date.has_date() => {
+            let first = days_from_civil(date.year, 1, 1)?;
+            number(days_from_civil(date.year, date.month, date.day)? - first + 1)
+        }
+        'E' if date.has_date() => {
+            let days = days_from_civil(date.year, date.month, date.day)?;
+            // 1970-01-01 was a Thursday
+            let weekday = ((days % 7 + 7 + 4) % 7) as usize;
+            text(DAY_NAMES[weekday])
+        }
+        'H' if has_time => number(i64::from(date.hour)),
+        'k' if has_time => number(if date.hour == 0 {
+            24
+        } else {
+            i64::from(date.hour)
+        }),
+        'K' if has_time => number(i64::from(date.hour % 12)),
+        'h' if has_time => number(match date.hour % 12 {
+            0 => 12,
+            h => i64::from(h),
+        }),
+        'a' if has_time => (if date.hour < 12 { "AM" } else { "PM" }).to_owned(),
+        'm' if has_time => number(i64::from(date.minute)),
+        's' if has_time => number(i64::from(date.second)),
+        'S' if has_time => number(i64::from(date.nanos / 1_000_000)),
+        'z' | 'Z' => match date.timezone {
+            Some(0) if letter == 'z' => "GMT".to_owned(),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let (hours, minutes) = (offset.abs() / 60, offset.abs() % 60);
+                if letter == 'z' {
+                    format!("GMT{}{:02}:{:02}", sign, hours, minutes)
+                } else {
+                    format!("{}{:02}{:02}", sign, hours, minutes)
+                }
+            }
+            None => return None,
+        },
+        _ => return None,
+    };
+    Some(field)
+}
+
+/// Formats a date with a pattern. Unparseable dates produce the
+/// empty string.
+struct FormatDate;
+
+impl Function for FormatDate {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let pattern = args.pop_string()?;
+        let date = args.pop_string()?;
+
+        let r = DateTime::parse(&date).map_or_else(String::new, |d| format_date(&d, &pattern));
+        Ok(Value::String(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// Adds the EXSLT date and time functions and binds the `date` prefix
+/// to their namespace.
+pub fn register_exslt_dates(context: &mut context::Context<'_>) {
+    context.set_function((DATES_NAMESPACE_URI, "date-time"), DateTimeFn);
+    context.set_function((DATES_NAMESPACE_URI, "year"), Component(DateTime::year));
+    context.set_function(
+        (DATES_NAMESPACE_URI, "month-in-year"),
+        Component(DateTime::month),
+    );
+    context.set_function(
+        (DATES_NAMESPACE_URI, "day-in-month"),
+        Component(DateTime::day),
+    );
+    context.set_function(
+        (DATES_NAMESPACE_URI, "hour-in-day"),
+        Component(DateTime::hour),
+    );
+    context.set_function(
+        (DATES_NAMESPACE_URI, "minute-in-hour"),
+        Component(DateTime::minute),
+    );
+    context.set_function(
+        (DATES_NAMESPACE_URI, "second-in-minute"),
+        Component(DateTime::second),
+    );
+    context.set_function((DATES_NAMESPACE_URI, "difference"), Difference);
+    context.set_function((DATES_NAMESPACE_URI, "add"), Add);
+    context.set_function((DATES_NAMESPACE_URI, "seconds"), Seconds);
+    context.set_function((DATES_NAMESPACE_URI, "format-date"), FormatDate);
+    context.set_namespace(DATES_PREFIX, DATES_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context::{self, Context};
+    use crate::function::Function;
+    use crate::{LiteralValue, Value};
+
+    use super::{
+        civil_from_days, days_from_civil, Add, Component, DateTime, DateTimeFn, Difference,
+        Duration, FormatDate, Kind, Seconds, Secs,
+    };
+
+    fn evaluate<F>(f: F, now: Option<&str>, args: Vec<LiteralValue>) -> LiteralValue
+    where
+        F: Function,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut context = Context::without_core_functions();
+        if let Some(now) = now {
+            context.set_current_date_time(now);
+        }
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        match f.evaluate(&evaluation, args) {
+            Ok(Value::Number(n)) => Value::Number(n),
+            Ok(Value::String(s)) => Value::String(s),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    fn component(f: fn(&DateTime) -> Option<f64>, date: &str) -> f64 {
+        evaluate(Component(f), None, vec![Value::String(date.into())]).number()
+    }
+
+    #[test]
+    fn parses_each_kind() {
+        let kinds = [
+            ("2001-02-03T04:05:06", Kind::DateTime),
+            ("2001-02-03", Kind::Date),
+            ("04:05:06", Kind::Time),
+            ("2001-02", Kind::YearMonth),
+            ("2001", Kind::Year),
+            ("--02-03", Kind::MonthDay),
+            ("--02", Kind::Month),
+            ("---03", Kind::Day),
+        ];
+        for &(s, kind) in &kinds {
+            assert_eq!(Some(kind), DateTime::parse(s).map(|d| d.kind), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_timezones_and_fractional_seconds() {
+        let dt = DateTime::parse("2001-02-03T04:05:06.25-05:30").expect("did not parse");
+
+        assert_eq!((6, 250_000_000), (dt.second, dt.nanos));
+        assert_eq!(Some(-330), dt.timezone);
+        assert_eq!(Some(0), DateTime::parse("2001-02-03Z").unwrap().timezone);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for s in &[
+            "",
+            "2001-13-01",
+            "2001-02-29",
+            "2001-2-03",
+            "01-02-03",
+            "2001-02-03T24:00:00",
+            "2001-02-03T04:05",
+            "2001-02-03+15:00",
+            "2001-02-03 junk",
+            "0000-01-01",
+        ] {
+            assert_eq!(None, DateTime::parse(s), "{:?}", s);
+        }
+        assert!(DateTime::parse("2000-02-29").is_some());
+        assert!(DateTime::parse("--02-29").is_some());
+    }
+
+    #[test]
+    fn years_beyond_the_limit_are_rejected() {
+        assert!(DateTime::parse("999999999-12-31").is_some());
+        assert!(DateTime::parse("-999999999-01-01").is_some());
+
+        for s in &[
+            "1000000000-01-01",
+            "-1000000000",
+            "100000000000000000-01-01",
+        ] {
+            assert_eq!(None, DateTime::parse(s), "{:?}", s);
+        }
+
+        let huge = "100000000000000000-01-01";
+        assert!(seconds(huge).is_nan());
+        assert!(component(DateTime::year, huge).is_nan());
+        assert_eq!("", format(huge, "yyyy D E"));
+        assert_eq!("", strings(Difference, huge, "2000-01-01"));
+        assert_eq!("", strings(Difference, "2000-01-01", huge));
+        assert_eq!("", strings(Add, huge, "P1D"));
+    }
+
+    #[test]
+    fn extreme_years_can_still_be_formatted() {
+        assert_eq!("999999999 365 Fri", format("999999999-12-31", "yyyy D EEE"));
+        assert!(seconds("-999999999-01-01").is_finite());
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for s in &[
+            "2001-02-03T04:05:06Z",
+            "-0044-03-15",
+            "12345-01",
+            "04:05:06.5+01:00",
+            "2001-02-03T04:05:06.1Z",
+            "04:05:06.3",
+            "04:05:06.123456789",
+            "---09",
+        ] {
+            assert_eq!(
+                Some(s.to_string()),
+                DateTime::parse(s).map(|d| d.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn converts_day_counts_to_dates() {
+        assert_eq!(Some((1970, 1, 1)), civil_from_days(0));
+        assert_eq!(Some((2000, 2, 29)), civil_from_days(11_016));
+        assert_eq!(Some((1969, 12, 31)), civil_from_days(-1));
+        assert_eq!(None, civil_from_days(i64::max_value()));
+    }
+
+    #[test]
+    fn date_time_uses_the_fixed_present() {
+        let r = evaluate(DateTimeFn, Some("2001-02-03T04:05:06Z"), vec![]);
+
+        assert_eq!(Value::String("2001-02-03T04:05:06Z".into()), r);
+    }
+
+    #[test]
+    fn date_time_of_the_system_clock_has_a_timezone() {
+        let r = evaluate(DateTimeFn, None, vec![]).string();
+
+        assert!(DateTime::parse(&r).map_or(false, |d| d.kind == Kind::DateTime));
+        assert!(r.ends_with('Z'), "{}", r);
+    }
+
+    #[test]
+    fn components_default_to_the_present() {
+        let now = Some("2001-02-03T04:05:06Z");
+
+        assert_eq!(
+            2001.0,
+            evaluate(Component(DateTime::year), now, vec![]).number()
+        );
+        assert_eq!(
+            4.0,
+            evaluate(Component(DateTime::hour), now, vec![]).number()
+        );
+    }
+
+    #[test]
+    fn components_of_each_kind() {
+        assert_eq!(2001.0, component(DateTime::year, "2001-02"));
+        assert_eq!(2.0, component(DateTime::month, "--02-03"));
+        assert_eq!(3.0, component(DateTime::day, "---03"));
+        assert_eq!(4.0, component(DateTime::hour, "04:05:06"));
+        assert_eq!(5.0, component(DateTime::minute, "2001-02-03T04:05:06"));
+        assert_eq!(6.5, component(DateTime::second, "04:05:06.5"));
+        assert_eq!(6.1, component(DateTime::second, "04:05:06.1"));
+    }
+
+    #[test]
+    fn missing_components_are_nan() {
+        assert!(component(DateTime::year, "--02-03").is_nan());
+        assert!(component(DateTime::hour, "2001-02-03").is_nan());
+        assert!(component(DateTime::day, "2001-02").is_nan());
+    }
+
+    #[test]
+    fn unparseable_dates_are_nan() {
+        assert!(component(DateTime::year, "yesterday").is_nan());
+        assert!(component(DateTime::month, "2001-02-30").is_nan());
+    }
+
+    #[test]
+    fn converts_dates_to_day_counts() {
+        for &days in &[-800_000, -1, 0, 11_016, 20_000] {
+            let (y, m, d) = civil_from_days(days).expect("day count out of range");
+            assert_eq!(Some(days), days_from_civil(y, m, d));
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        let d = Duration::parse("P1Y2M3DT4H5M6.5S").expect("did not parse");
+
+        assert!(!d.negative);
+        assert_eq!(14, d.months);
+        assert_eq!(3, d.days);
+        assert_eq!(Secs::new(4 * 3600 + 5 * 60 + 6, 500_000_000), d.seconds);
+    }
+
+    #[test]
+    fn parses_negative_and_partial_durations() {
+        let d = Duration::parse("-P1DT2H").expect("did not parse");
+
+        assert!(d.negative);
+        assert_eq!(0, d.months);
+        assert_eq!(1, d.days);
+        assert_eq!(Secs::new(7200, 0), d.seconds);
+        assert_eq!(
+            Some(Secs::new(60, 0)),
+            Duration::parse("PT1M").map(|d| d.seconds)
+        );
+        assert_eq!(Some(1), Duration::parse("P1M").map(|d| d.months));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in &[
+            "", "P", "PT", "1D", "P1H", "PT1D", "P1D1Y", "P1.5D", "P1DT", "P-1D", "PT1.S",
+        ] {
+            assert_eq!(None, Duration::parse(s), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn durations_round_trip_through_display() {
+        for s in &[
+            "P1Y2M3DT4H5M6.5S",
+            "-P1DT2H",
+            "PT1M",
+            "P1M",
+            "PT0S",
+            "P2Y",
+            "PT0.1S",
+            "-PT1.3S",
+        ] {
+            assert_eq!(
+                Some(s.to_string()),
+                Duration::parse(s).map(|d| d.to_string())
+            );
+        }
+    }
+
+    fn strings(f: impl Function, a: &str, b: &str) -> String {
+        evaluate(
+            f,
+            None,
+            vec![Value::String(a.into()), Value::String(b.into())],
+        )
+        .string()
+    }
+
+    #[test]
+    fn difference_of_date_times() {
+        assert_eq!(
+            "P1DT1H",
+            strings(Difference, "2001-02-03T04:00:00Z", "2001-02-04T05:00:00Z")
+        );
+        assert_eq!(
+            "-PT30M",
+            strings(
+                Difference,
+                "2001-02-03T04:30:00Z",
+                "2001-02-03T05:00:00+01:00"
+            )
+        );
+        assert_eq!("P29D", strings(Difference, "2000-02-01", "2000-03-01"));
+    }
+
+    #[test]
+    fn difference_keeps_decimal_fractions() {
+        assert_eq!(
+            "PT0.3S",
+            strings(Difference, "2001-02-03T04:05:06Z", "2001-02-03T04:05:06.3Z")
+        );
+        assert_eq!(
+            "-PT1.1S",
+            strings(
+                Difference,
+                "2001-02-03T04:05:07.2Z",
+                "2001-02-03T04:05:06.1Z"
+            )
+        );
+    }
+
+    #[test]
+    fn difference_of_years_and_months() {
+        assert_eq!("P1Y1M", strings(Difference, "2000-01", "2001-02"));
+        assert_eq!("-P2Y", strings(Difference, "2002", "2000-01-15"));
+    }
+
+    #[test]
+    fn difference_of_invalid_dates_is_empty() {
+        assert_eq!("", strings(Difference, "2000-01-01", "soon"));
+        assert_eq!("", strings(Difference, "04:00:00", "05:00:00"));
+    }
+
+    #[test]
+    fn add_carries_into_larger_units() {
+        assert_eq!(
+            "2001-03-01T01:00:00Z",
+            strings(Add, "2001-02-28T23:00:00Z", "PT2H")
+        );
+        assert_eq!("2000-12-31", strings(Add, "2001-01-01", "-P1D"));
+    }
+
+    #[test]
+    fn add_clamps_the_day_when_adding_months() {
+        assert_eq!("2001-02-28", strings(Add, "2001-01-31", "P1M"));
+        assert_eq!("2001-02-28", strings(Add, "2000-02-29", "P1Y"));
+        assert_eq!("1999-11", strings(Add, "2000-01", "-P2M"));
+    }
+
+    #[test]
+    fn add_of_huge_durations_is_empty() {
+        for duration in &[
+            "P999999999999999Y",
+            "-P999999999999999Y",
+            "P999999999999999D",
+            "-P999999999999999D",
+            "PT999999999999999H",
+            "P999999999999999Y999999999999999M",
+        ] {
+            assert_eq!("", strings(Add, "2000-01-01", duration), "{}", duration);
+            assert_eq!("", strings(Add, "2000-01-01T00:00:00Z", duration));
+        }
+        assert_eq!("", strings(Add, "999999999-12-31", "P1D"));
+    }
+
+    #[test]
+    fn add_keeps_decimal_fractions() {
+        assert_eq!(
+            "2001-02-03T04:05:07.1Z",
+            strings(Add, "2001-02-03T04:05:06.1Z", "PT1S")
+        );
+        assert_eq!(
+            "2001-02-03T04:05:05.9Z",
+            strings(Add, "2001-02-03T04:05:06.3Z", "-PT0.4S")
+        );
+        assert_eq!(
+            "2001-02-03T04:05:06Z",
+            strings(Add, "2001-02-03T04:05:05.7Z", "PT0.3S")
+        );
+    }
+
+    #[test]
+    fn add_keeps_the_timezone() {
+        assert_eq!(
+            "2001-02-04T00:30:00-05:00",
+            strings(Add, "2001-02-03T23:00:00-05:00", "PT1H30M")
+        );
+    }
+
+    #[test]
+    fn add_of_invalid_arguments_is_empty() {
+        assert_eq!("", strings(Add, "2001-02-03", "1 day"));
+        assert_eq!("", strings(Add, "04:00:00", "PT1H"));
+    }
+
+    fn seconds(s: &str) -> f64 {
+        evaluate(Seconds, None, vec![Value::String(s.into())]).number()
+    }
+
+    #[test]
+    fn seconds_of_dates() {
+        assert_eq!(0.0, seconds("1970-01-01T00:00:00Z"));
+        assert_eq!(86_400.0, seconds("1970-01-02"));
+        assert_eq!(-3600.0, seconds("1970-01-01T00:00:00+01:00"));
+        assert_eq!(0.1, seconds("1970-01-01T00:00:00.1Z"));
+        assert_eq!(-0.3, seconds("1969-12-31T23:59:59.7Z"));
+    }
+
+    #[test]
+    fn seconds_of_durations() {
+        assert_eq!(93_600.0, seconds("P1DT2H"));
+        assert_eq!(-90.5, seconds("-PT1M30.5S"));
+        assert_eq!(-0.3, seconds("-PT0.3S"));
+    }
+
+    #[test]
+    fn seconds_of_invalid_arguments_is_nan() {
+        assert!(seconds("tomorrow").is_nan());
+        assert!(seconds("12:00:00").is_nan());
+    }
+
+    #[test]
+    fn seconds_default_to_the_present() {
+        let r = evaluate(Seconds, Some("1970-01-01T00:01:00Z"), vec![]);
+
+        assert_eq!(Value::Number(60.0), r);
+    }
+
+    fn format(date: &str, pattern: &str) -> String {
+        strings(FormatDate, date, pattern)
+    }
+
+    #[test]
+    fn format_date_of_a_date_time() {
+        assert_eq!(
+            "2001-02-03 04:05:06",
+            format("2001-02-03T04:05:06Z", "yyyy-MM-dd HH:mm:ss")
+        );
+    }
+
+    #[test]
+    fn format_date_pads_to_the_pattern_width() {
+        assert_eq!("2/3/01", format("2001-02-03", "M/d/yy"));
+        assert_eq!("02/03/2001", format("2001-02-03", "MM/dd/yyyy"));
+        assert_eq!("Feb", format("2001-02-03", "MMM"));
+        assert_eq!("0034", format("2001-02-03", "DDDD"));
+    }
+
+    #[test]
+    fn format_date_names_months_and_days() {
+        assert_eq!("Sat, 3 Feb 2001", format("2001-02-03", "EEE, d MMM yyyy"));
+        assert_eq!("Saturday, February 3", format("2001-02-03", "EEEE, MMMM d"));
+        assert_eq!("Thu", format("1970-01-01", "EEE"));
+        assert_eq!("Wed", format("1969-12-31", "EEE"));
+    }
+
+    #[test]
+    fn format_date_of_twelve_hour_times() {
+        assert_eq!("12:30 AM", format("00:30:00", "hh:mm a"));
+        assert_eq!("1:05 PM", format("13:05:00", "h:mm a"));
+        assert_eq!("24 0", format("00:00:00", "k K"));
+    }
+
+    #[test]
+    fn format_date_quotes_literal_text() {
+        assert_eq!(
+            "2001-02-03T04:05",
+            format("2001-02-03T04:05:06Z", "yyyy-MM-dd'T'HH:mm")
+        );
+        assert_eq!("at 4 o'clock", format("04:05:06", "'at' H 'o''clock'"));
+        assert_eq!("'4'", format("04:05:06", "''H''"));
+    }
+
+    #[test]
+    fn format_date_leaves_missing_fields_blank() {
+        assert_eq!(" 04:05", format("04:05:06", "yyyy HH:mm"));
+        assert_eq!("2001-02 :", format("2001-02-03", "yyyy-MM HH:mm"));
+        assert_eq!("2001-", format("2001", "yyyy-MM"));
+    }
+
+    #[test]
+    fn format_date_of_timezones() {
+        assert_eq!("GMT", format("04:05:06Z", "z"));
+        assert_eq!("-0530", format("04:05:06-05:30", "Z"));
+        assert_eq!("", format("04:05:06", "z"));
+    }
+
+    #[test]
+    fn format_date_of_invalid_date_is_empty() {
+        assert_eq!("", format("someday", "yyyy"));
+    }
+}
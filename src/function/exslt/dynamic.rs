@@ -0,0 +1,197 @@
+//! The [EXSLT dynamic][dynamic] library.
+//!
+//! [dynamic]: http://exslt.org/dyn/index.html
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::Value;
+
+/// The namespace URI of the dynamic library.
+pub const DYNAMIC_NAMESPACE_URI: &str = "http://exslt.org/dynamic";
+/// The prefix conventionally bound to the dynamic library.
+pub const DYNAMIC_PREFIX: &str = "dyn";
+
+/// Parses its argument as an XPath expression and evaluates it with
+/// the functions, variables, namespaces and context node of the
+/// calling expression. The expression is built by the factory set with
+/// [`Context::set_factory`](../../context/struct.Context.html#method.set_factory).
+struct Evaluate;
+
+impl Function for Evaluate {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let expression = args.pop_string()?;
+
+        let nested = match context.nested() {
+            Some(nested) => nested,
+            None => {
+                return Err(Error::NestingTooDeep {
+                    expression,
+                    limit: context::MAX_NESTING_DEPTH,
+                })
+            }
+        };
+
+        let xpath = match context.factory().build(&expression) {
+            Ok(xpath) => xpath,
+            Err(e) => {
+                return Err(Error::InvalidExpression {
+                    expression,
+                    what: e.to_string(),
+                })
+            }
+        };
+
+        xpath
+            .0
+            .evaluate(&nested)
+            .map_err(|e| Error::ExpressionFailed {
+                expression,
+                what: e.to_string(),
+            })
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+/// Adds the EXSLT dynamic functions and binds the `dyn` prefix to
+/// their namespace.
+pub fn register_exslt_dynamic(context: &mut context::Context<'_>) {
+    context.set_function((DYNAMIC_NAMESPACE_URI, "evaluate"), Evaluate);
+    context.set_namespace(DYNAMIC_PREFIX, DYNAMIC_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context::{self, Context};
+    use crate::Factory;
+
+    use super::register_exslt_dynamic;
+
+    /// Evaluates the XPath against `<top price="12"/>`, returning the
+    /// string of the result or the error message.
+    fn evaluate<F>(setup: F, xpath: &str) -> Result<String, String>
+    where
+        F: FnOnce(&mut Context<'_>),
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        top.set_attribute_value("price", "12");
+        doc.root().append_child(top);
+
+        let mut context = Context::new();
+        register_exslt_dynamic(&mut context);
+        setup(&mut context);
+
+        let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+        xpath
+            .evaluate(&context, doc.root())
+            .map(|v| v.string())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn evaluate_uses_the_context_node_and_variables() {
+        let set_limit = |c: &mut Context<'_>| c.set_variable("limit", 10.0);
+
+        assert_eq!(
+            Ok("false".to_owned()),
+            evaluate(set_limit, "dyn:evaluate('@price > $limit')")
+        );
+        assert_eq!(
+            Ok("12".to_owned()),
+            evaluate(
+                set_limit,
+                "string(/top[dyn:evaluate('@price > $limit')]/@price)"
+            )
+        );
+    }
+
+    #[test]
+    fn evaluate_uses_the_context_position_and_size() {
+        assert_eq!(
+            Ok("12".to_owned()),
+            evaluate(
+                |_| {},
+                "string(/top[dyn:evaluate('position() = last()')]/@price)"
+            )
+        );
+        assert_eq!(
+            Ok("".to_owned()),
+            evaluate(
+                |_| {},
+                "string(/top[dyn:evaluate('position() = 2')]/@price)"
+            )
+        );
+    }
+
+    #[test]
+    fn evaluate_reports_parse_errors_with_the_expression() {
+        let message = evaluate(|_| {}, "dyn:evaluate('1 +')").expect_err("should not parse");
+
+        assert!(message.contains("\"1 +\""), "{}", message);
+    }
+
+    #[test]
+    fn evaluate_builds_with_the_factory_of_the_context() {
+        let default = evaluate(|_| {}, "dyn:evaluate('count(//*:top)')");
+        assert!(default.is_err());
+        let deep = evaluate(|_| {}, "dyn:evaluate('((((1))))')");
+        assert_eq!(Ok("1".to_owned()), deep);
+        let unknown = evaluate(|_| {}, "dyn:evaluate('string(unknown(1))')");
+        assert!(unknown.is_err());
+
+        let with_factory = |c: &mut Context<'_>| {
+            let mut factory = Factory::new();
+            factory.set_local_name_wildcards(true);
+            factory.set_max_depth(2);
+            factory.set_functions(c.functions().clone());
+            factory.set_strict_functions(true);
+            c.set_factory(factory);
+        };
+
+        assert_eq!(
+            Ok("1".to_owned()),
+            evaluate(with_factory, "dyn:evaluate('count(//*:top)')")
+        );
+        let message = evaluate(with_factory, "dyn:evaluate('((((1))))')").expect_err("too deep");
+        assert!(message.contains("nests"), "{}", message);
+        let message = evaluate(with_factory, "dyn:evaluate('concat(1)')").expect_err("wrong arity");
+        assert!(message.contains("concat"), "{}", message);
+    }
+
+    #[test]
+    fn evaluate_limits_recursion() {
+        let message = evaluate(
+            |c| c.set_variable("self", "dyn:evaluate($self)"),
+            "dyn:evaluate($self)",
+        )
+        .expect_err("should not recurse forever");
+
+        assert!(message.contains("levels deep"), "{}", message);
+    }
+
+    #[test]
+    fn nesting_stops_at_the_maximum_depth() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::without_core_functions();
+        let mut evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        for _ in 0..context::MAX_NESTING_DEPTH {
+            evaluation = evaluation.nested().expect("nested too early");
+        }
+
+        assert!(evaluation.nested().is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! The [EXSLT math][math] library.
+//!
+//! [math]: http://exslt.org/math/index.html
+
+use crate::context;
+use crate::convert::string_to_number;
+use crate::function::{node_numbers, Args, Arity, Error, Function, NumberConvert};
+use crate::Value;
+
+/// The namespace URI of the math library.
+pub const MATH_NAMESPACE_URI: &str = "http://exslt.org/math";
+/// The prefix conventionally bound to the math library.
+pub const MATH_PREFIX: &str = "math";
+
+/// Reduces the numeric values of a nodeset to the one that is
+/// preferred over all the others. The result is NaN if the nodeset
+/// is empty or any node is not a number.
+struct Extremum(fn(f64, f64) -> bool);
+
+impl Function for Extremum {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+
+        let prefer = self.0;
+        let r = node_numbers(&nodes).fold(None, |acc: Option<f64>, n| match acc {
+            Some(a) if a.is_nan() => Some(a),
+            Some(a) if !n.is_nan() && !prefer(n, a) => Some(a),
+            _ => Some(n),
+        });
+        Ok(Value::Number(r.unwrap_or(::std::f64::NAN)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+fn min() -> Extremum {
+    Extremum(|candidate, current| candidate < current)
+}
+fn max() -> Extremum {
+    Extremum(|candidate, current| candidate > current)
+}
+fn abs() -> NumberConvert {
+    NumberConvert(f64::abs)
+}
+fn sqrt() -> NumberConvert {
+    NumberConvert(f64::sqrt)
+}
+fn log() -> NumberConvert {
+    NumberConvert(f64::ln)
+}
+fn exp() -> NumberConvert {
+    NumberConvert(f64::exp)
+}
+
+struct Power;
+
+impl Function for Power {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let exponent = args.pop_number()?;
+        let base = args.pop_number()?;
+        Ok(Value::Number(base.powf(exponent)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// The digits of each named constant, as many as an `f64` can use.
+fn constant_digits(name: &str) -> Option<&'static str> {
+    let digits = match name {
+        "PI" => "3.14159265358979323846264338327950288",
+        "E" => "2.71828182845904523536028747135266250",
+        // EXSLT spells this one SQRRT2; accept the obvious name too
+        "SQRRT2" | "SQRT2" => "1.41421356237309504880168872420969808",
+        "LN2" => "0.693147180559945309417232121458176568",
+        "LN10" => "2.30258509299404568401799145468436421",
+        "LOG2E" => "1.44269504088896340735992468100189214",
+        "SQRT1_2" => "0.707106781186547524400844362104849039",
+        _ => return None,
+    };
+    Some(digits)
+}
+
+/// Returns a named constant truncated to the requested number of
+/// characters. Unknown names produce NaN rather than an error.
+struct Constant;
+
+impl Function for Constant {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let precision = args.pop_number()?;
+        let name = args.pop_string()?;
+
+        let r = constant_digits(&name).map_or(::std::f64::NAN, |digits| {
+            if precision.is_nan() || precision >= digits.len() as f64 {
+                string_to_number(digits)
+            } else if precision < 1.0 {
+                ::std::f64::NAN
+            } else {
+                string_to_number(&digits[..precision as usize])
+            }
+        });
+        Ok(Value::Number(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// Adds the EXSLT math functions and binds the `math` prefix to their
+/// namespace.
+pub fn register_exslt_math(context: &mut context::Context<'_>) {
+    context.set_function((MATH_NAMESPACE_URI, "min"), min());
+    context.set_function((MATH_NAMESPACE_URI, "max"), max());
+    context.set_function((MATH_NAMESPACE_URI, "abs"), abs());
+    context.set_function((MATH_NAMESPACE_URI, "power"), Power);
+    context.set_function((MATH_NAMESPACE_URI, "sqrt"), sqrt());
+    context.set_function((MATH_NAMESPACE_URI, "log"), log());
+    context.set_function((MATH_NAMESPACE_URI, "exp"), exp());
+    context.set_function((MATH_NAMESPACE_URI, "constant"), Constant);
+    context.set_namespace(MATH_PREFIX, MATH_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::function::{Error, Function};
+    use crate::nodeset::Nodeset;
+    use crate::{LiteralValue, Value};
+
+    use super::{abs, exp, log, max, min, sqrt, Constant, Power};
+
+    fn evaluate_on_texts<F>(f: F, texts: &[&str]) -> f64
+    where
+        F: Function,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let mut nodes = Nodeset::new();
+        for &t in texts {
+            nodes.add(doc.create_text(t));
+        }
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        match f.evaluate(&evaluation, vec![Value::Nodeset(nodes)]) {
+            Ok(Value::Number(n)) => n,
+            r => panic!("expected a number, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn min_and_max_of_numbers() {
+        assert_eq!(-2.5, evaluate_on_texts(min(), &["3", "-2.5", " 10 "]));
+        assert_eq!(10.0, evaluate_on_texts(max(), &["3", "-2.5", " 10 "]));
+    }
+
+    #[test]
+    fn min_and_max_are_nan_when_any_node_is_not_numeric() {
+        assert!(evaluate_on_texts(min(), &["1", "seven", "3"]).is_nan());
+        assert!(evaluate_on_texts(max(), &["1", "seven", "3"]).is_nan());
+        assert!(evaluate_on_texts(max(), &["seven", "1"]).is_nan());
+    }
+
+    #[test]
+    fn min_and_max_of_empty_nodeset_are_nan() {
+        assert!(evaluate_on_texts(min(), &[]).is_nan());
+        assert!(evaluate_on_texts(max(), &[]).is_nan());
+    }
+
+    fn evaluate_numbers<F>(f: F, args: Vec<LiteralValue>) -> Result<f64, Error>
+    where
+        F: Function,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        f.evaluate(&evaluation, args).map(|r| r.number())
+    }
+
+    #[test]
+    fn abs_of_negative_number() {
+        let r = evaluate_numbers(abs(), vec![Value::String("-4".into())]);
+
+        assert_eq!(Ok(4.0), r);
+    }
+
+    #[test]
+    fn power_raises_base_to_exponent() {
+        assert_eq!(
+            Ok(1024.0),
+            evaluate_numbers(Power, vec![Value::Number(2.0), Value::Number(10.0)])
+        );
+        assert_eq!(
+            Ok(0.5),
+            evaluate_numbers(
+                Power,
+                vec![Value::Number(4.0), Value::String("-0.5".into())]
+            )
+        );
+    }
+
+    #[test]
+    fn power_requires_two_arguments() {
+        assert_eq!(
+            Err(Error::NotEnoughArguments {
+                expected: 2,
+                actual: 1
+            }),
+            evaluate_numbers(Power, vec![Value::Number(2.0)])
+        );
+    }
+
+    #[test]
+    fn sqrt_of_negative_number_is_nan() {
+        assert_eq!(Ok(3.0), evaluate_numbers(sqrt(), vec![Value::Number(9.0)]));
+        assert!(evaluate_numbers(sqrt(), vec![Value::Number(-1.0)])
+            .unwrap()
+            .is_nan());
+    }
+
+    #[test]
+    fn log_and_exp_are_inverses() {
+        assert_eq!(Ok(0.0), evaluate_numbers(log(), vec![Value::Number(1.0)]));
+        assert_eq!(Ok(1.0), evaluate_numbers(exp(), vec![Value::Number(0.0)]));
+        let e = evaluate_numbers(exp(), vec![Value::Number(2.0)]).unwrap();
+        let r = evaluate_numbers(log(), vec![Value::Number(e)]).unwrap();
+        assert!((r - 2.0).abs() < 1e-12);
+    }
+
+    fn constant(name: &str, precision: f64) -> f64 {
+        evaluate_numbers(
+            Constant,
+            vec![Value::String(name.into()), Value::Number(precision)],
+        )
+        .expect("constant failed")
+    }
+
+    #[test]
+    fn constant_is_truncated_to_precision() {
+        assert_eq!(1.414, constant("SQRRT2", 5.0));
+        assert_eq!(::std::f64::consts::PI, constant("PI", 100.0));
+        assert_eq!(::std::f64::consts::LN_2, constant("LN2", 50.0));
+    }
+
+    #[test]
+    fn constant_supports_the_exslt_names() {
+        for &name in &["PI", "E", "SQRRT2", "LN2", "LN10", "LOG2E", "SQRT1_2"] {
+            assert!(!constant(name, 10.0).is_nan(), "{} is NaN", name);
+        }
+    }
+
+    #[test]
+    fn unknown_constant_is_nan() {
+        assert!(constant("TAU", 10.0).is_nan());
+    }
+}
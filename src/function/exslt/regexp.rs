@@ -0,0 +1,336 @@
+//! The [EXSLT regular expressions][regexp] library.
+//!
+//! Patterns use the syntax of the [`regex`][regex] crate, which
+//! covers the commonly used parts of JavaScript regular expressions
+//! but not backreferences or lookaround.
+//!
+//! [regexp]: http://exslt.org/regexp/index.html
+//! [regex]: https://docs.rs/regex/1/regex/#syntax
+
+use regex::{Captures, Regex, RegexBuilder};
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::Value;
+
+use super::text_elements;
+
+/// The namespace URI of the regular expressions library.
+pub const REGEXP_NAMESPACE_URI: &str = "http://exslt.org/regular-expressions";
+/// The prefix conventionally bound to the regular expressions library.
+pub const REGEXP_PREFIX: &str = "regexp";
+
+/// The flags accepted by the regular expression functions: `g` for
+/// global matching and `i` for case-insensitive matching.
+#[derive(Debug, Default)]
+struct Flags {
+    global: bool,
+    case_insensitive: bool,
+}
+
+impl Flags {
+    fn parse(flags: &str) -> Result<Flags, Error> {
+        let mut parsed = Flags::default();
+        for flag in flags.chars() {
+            match flag {
+                'g' => parsed.global = true,
+                'i' => parsed.case_insensitive = true,
+                other => {
+                    return Err(Error::Other {
+                        what: format!("unknown regular expression flag {:?}", other),
+                    })
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn compile(pattern: &str, flags: &Flags) -> Result<Regex, Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(flags.case_insensitive)
+        .build()
+        .map_err(|e| Error::InvalidRegularExpression {
+            pattern: pattern.into(),
+            reason: e.to_string(),
+        })
+}
+
+struct Test;
+
+impl Function for Test {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(2, 3)?;
+        let flags = if args.len() == 3 {
+            Flags::parse(&args.pop_string()?)?
+        } else {
+            Flags::default()
+        };
+        let pattern = args.pop_string()?;
+        let s = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+        Ok(Value::Boolean(regex.is_match(&s)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(2, 3))
+    }
+}
+
+struct Replace;
+
+impl Function for Replace {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(4)?;
+        let replacement = args.pop_string()?;
+        let flags = Flags::parse(&args.pop_string()?)?;
+        let pattern = args.pop_string()?;
+        let s = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+        let matches: Vec<Captures<'_>> = if flags.global {
+            regex.captures_iter(&s).collect()
+        } else {
+            regex.captures(&s).into_iter().collect()
+        };
+
+        // References such as `$1` are replaced with the text of the
+        // group, or with nothing if the group did not participate.
+        let mut result = String::new();
+        let mut copied = 0;
+        for captures in matches {
+            let whole = captures.get(0).expect("whole match is always captured");
+            result.push_str(&s[copied..whole.start()]);
+            captures.expand(&replacement, &mut result);
+            copied = whole.end();
+        }
+        result.push_str(&s[copied..]);
+
+        Ok(Value::String(result))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(4))
+    }
+}
+
+struct Match;
+
+impl Function for Match {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(2, 3)?;
+        let flags = if args.len() == 3 {
+            Flags::parse(&args.pop_string()?)?
+        } else {
+            Flags::default()
+        };
+        let pattern = args.pop_string()?;
+        let s = args.pop_string()?;
+
+        let regex = compile(&pattern, &flags)?;
+
+        // Globally, each match is returned; otherwise the first match
+        // is followed by each of its groups.
+        let strings: Vec<String> = if flags.global {
+            regex.find_iter(&s).map(|m| m.as_str().to_owned()).collect()
+        } else {
+            regex
+                .captures(&s)
+                .map(|captures| {
+                    captures
+                        .iter()
+                        .map(|m| m.map_or("", |m| m.as_str()).to_owned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        text_elements(context, "match", strings).map(Value::Nodeset)
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(2, 3))
+    }
+}
+
+/// Adds the EXSLT regular expression functions and binds the
+/// `regexp` prefix to their namespace.
+pub fn register_exslt_regexp(context: &mut context::Context<'_>) {
+    context.set_function((REGEXP_NAMESPACE_URI, "test"), Test);
+    context.set_function((REGEXP_NAMESPACE_URI, "replace"), Replace);
+    context.set_function((REGEXP_NAMESPACE_URI, "match"), Match);
+    context.set_namespace(REGEXP_PREFIX, REGEXP_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::function::{Error, Function};
+    use crate::{LiteralValue, Value};
+
+    use super::{Match, Replace, Test};
+
+    fn evaluate<F, F2, T>(f: F, args: Vec<LiteralValue>, rf: F2) -> T
+    where
+        F: Function,
+        F2: FnOnce(Result<Value<'_>, Error>) -> T,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let scratch = Package::new();
+        let mut context = context::Context::without_core_functions();
+        context.set_scratch_document(scratch.as_document());
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+        rf(f.evaluate(&evaluation, args))
+    }
+
+    fn strings(args: &[&str]) -> Vec<LiteralValue> {
+        args.iter().map(|&s| Value::String(s.into())).collect()
+    }
+
+    #[test]
+    fn test_finds_a_match() {
+        evaluate(Test, strings(&["hello world", "o w"]), |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn test_is_case_sensitive_by_default() {
+        evaluate(Test, strings(&["HELLO", "^hel+o$"]), |r| {
+            assert_eq!(Ok(Value::Boolean(false)), r);
+        });
+    }
+
+    #[test]
+    fn test_accepts_the_case_insensitive_flag() {
+        evaluate(Test, strings(&["HELLO", "^hel+o$", "gi"]), |r| {
+            assert_eq!(Ok(Value::Boolean(true)), r);
+        });
+    }
+
+    #[test]
+    fn test_reports_invalid_patterns() {
+        evaluate(Test, strings(&["hello", "(unclosed"]), |r| match r {
+            Err(Error::InvalidRegularExpression { pattern, .. }) => {
+                assert_eq!("(unclosed", pattern)
+            }
+            r => panic!("pattern should be invalid: {:?}", r),
+        });
+    }
+
+    #[test]
+    fn test_reports_unknown_flags() {
+        evaluate(Test, strings(&["hello", "h", "x"]), |r| assert!(r.is_err()));
+    }
+
+    fn replace_test(args: &[&str]) -> String {
+        evaluate(Replace, strings(args), |r| match r {
+            Ok(Value::String(s)) => s,
+            r => panic!("replace failed: {:?}", r),
+        })
+    }
+
+    #[test]
+    fn replace_only_replaces_the_first_match_by_default() {
+        assert_eq!("b-a-a", replace_test(&["a-a-a", "a", "", "b"]));
+    }
+
+    #[test]
+    fn replace_replaces_all_matches_when_global() {
+        assert_eq!("b-b-b", replace_test(&["a-a-a", "a", "g", "b"]));
+    }
+
+    #[test]
+    fn replace_honors_case_insensitivity() {
+        assert_eq!("x-x-x", replace_test(&["A-a-A", "a", "gi", "x"]));
+    }
+
+    #[test]
+    fn replace_substitutes_group_references() {
+        assert_eq!(
+            "Doe, John",
+            replace_test(&["John Doe", "(\\w+) (\\w+)", "", "$2, $1"])
+        );
+    }
+
+    #[test]
+    fn replace_with_unmatched_group_inserts_nothing() {
+        assert_eq!("[]b", replace_test(&["ab", "(x)?a", "", "[$1]"]));
+    }
+
+    #[test]
+    fn replace_reports_the_invalid_pattern() {
+        evaluate(Replace, strings(&["abc", "[a-", "", "x"]), |r| {
+            let message = r.expect_err("pattern should be invalid").to_string();
+            assert!(message.contains("[a-"), "{}", message);
+        });
+    }
+
+    fn match_test(args: &[&str]) -> Vec<String> {
+        evaluate(Match, strings(args), |r| match r {
+            Ok(Value::Nodeset(nodes)) => nodes
+                .document_order()
+                .iter()
+                .map(|n| n.string_value())
+                .collect(),
+            r => panic!("match failed: {:?}", r),
+        })
+    }
+
+    #[test]
+    fn match_returns_the_match_and_its_groups() {
+        assert_eq!(
+            vec!["user@example.org", "user", "example.org"],
+            match_test(&["mail user@example.org", "(\\w+)@([\\w.]+)"])
+        );
+    }
+
+    #[test]
+    fn match_returns_empty_strings_for_unmatched_groups() {
+        assert_eq!(vec!["b", "", "b"], match_test(&["b", "(a)?(b)"]));
+    }
+
+    #[test]
+    fn match_returns_every_match_when_global() {
+        assert_eq!(
+            vec!["12", "3", "456"],
+            match_test(&["12 apples, 3 pears, 456 plums", "\\d+", "g"])
+        );
+    }
+
+    #[test]
+    fn match_without_a_match_is_empty() {
+        assert_eq!(Vec::<String>::new(), match_test(&["abc", "\\d"]));
+    }
+
+    #[test]
+    fn match_requires_a_scratch_document() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let r = Match.evaluate(&evaluation, strings(&["a", "a"]));
+
+        assert_eq!(Err(Error::NoScratchDocument), r);
+    }
+}
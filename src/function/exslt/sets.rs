@@ -0,0 +1,369 @@
+//! The [EXSLT sets][sets] library.
+//!
+//! [sets]: http://exslt.org/set/index.html
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::nodeset::{DocOrder, Nodeset};
+use crate::Value;
+
+/// The namespace URI of the sets library.
+pub const SETS_NAMESPACE_URI: &str = "http://exslt.org/sets";
+/// The prefix conventionally bound to the sets library.
+pub const SETS_PREFIX: &str = "set";
+
+/// Keeps the nodes of the first nodeset whose presence in the second
+/// nodeset matches the wrapped value.
+struct Membership(bool);
+
+impl Function for Membership {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let second = args.pop_nodeset()?;
+        let first = args.pop_nodeset()?;
+
+        let r = first
+            .into_iter()
+            .filter(|&n| second.contains(n) == self.0)
+            .collect();
+        Ok(Value::Nodeset(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+fn difference() -> Membership {
+    Membership(false)
+}
+fn intersection() -> Membership {
+    Membership(true)
+}
+
+/// Keeps the first node, in document order, with each string-value.
+struct Distinct;
+
+impl Function for Distinct {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+
+        let mut seen = HashSet::new();
+        let r = nodes
+            .document_order()
+            .into_iter()
+            .filter(|n| seen.insert(n.string_value()))
+            .collect();
+        Ok(Value::Nodeset(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+struct HasSameNode;
+
+impl Function for HasSameNode {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let second = args.pop_nodeset()?;
+        let first = args.pop_nodeset()?;
+
+        Ok(Value::Boolean(first.iter().any(|n| second.contains(n))))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+/// Keeps the nodes of the first nodeset that are on the wrapped side,
+/// in document order, of the first node of the second nodeset. The
+/// result is empty when the second nodeset is. Nodes outside the tree
+/// of that first node's document, such as nodes of other documents,
+/// have no order relative to it and are never kept.
+struct Relative(Ordering);
+
+impl Function for Relative {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(2)?;
+        let second = args.pop_nodeset()?;
+        let first = args.pop_nodeset()?;
+
+        let boundary = match second.document_order_first() {
+            Some(node) => node,
+            None => return Ok(Value::Nodeset(Nodeset::new())),
+        };
+
+        let order = DocOrder::new(boundary.document());
+        if !order.contains(boundary) {
+            return Ok(Value::Nodeset(Nodeset::new()));
+        }
+        let r = first
+            .into_iter()
+            .filter(|&n| order.contains(n) && order.compare(n, boundary) == self.0)
+            .collect();
+        Ok(Value::Nodeset(r))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(2))
+    }
+}
+
+fn leading() -> Relative {
+    Relative(Ordering::Less)
+}
+fn trailing() -> Relative {
+    Relative(Ordering::Greater)
+}
+
+/// Adds the EXSLT set functions and binds the `set` prefix to their
+/// namespace.
+pub fn register_exslt_sets(context: &mut context::Context<'_>) {
+    context.set_function((SETS_NAMESPACE_URI, "difference"), difference());
+    context.set_function((SETS_NAMESPACE_URI, "intersection"), intersection());
+    context.set_function((SETS_NAMESPACE_URI, "distinct"), Distinct);
+    context.set_function((SETS_NAMESPACE_URI, "has-same-node"), HasSameNode);
+    context.set_function((SETS_NAMESPACE_URI, "leading"), leading());
+    context.set_function((SETS_NAMESPACE_URI, "trailing"), trailing());
+    context.set_namespace(SETS_PREFIX, SETS_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::function::Function;
+    use crate::nodeset::Node;
+    use crate::Value;
+
+    use super::{difference, intersection, leading, trailing, Distinct, HasSameNode};
+
+    fn evaluate<'d, F>(
+        doc: &sxd_document::dom::Document<'d>,
+        f: F,
+        args: Vec<Value<'d>>,
+    ) -> Vec<Node<'d>>
+    where
+        F: Function,
+    {
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        match f.evaluate(&evaluation, args) {
+            Ok(Value::Nodeset(nodes)) => nodes.document_order(),
+            r => panic!("expected a nodeset, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn difference_keeps_nodes_only_in_the_first_set() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        let c = doc.create_element("c");
+        doc.root().append_child(top);
+        top.append_child(a);
+        top.append_child(b);
+        top.append_child(c);
+
+        let r = evaluate(
+            &doc,
+            difference(),
+            vec![
+                Value::Nodeset(nodeset![c, b, a]),
+                Value::Nodeset(nodeset![b]),
+            ],
+        );
+
+        assert_eq!(vec![Node::from(a), Node::from(c)], r);
+    }
+
+    #[test]
+    fn intersection_keeps_nodes_in_both_sets() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        let a = doc.create_element("a");
+        let b = doc.create_element("b");
+        doc.root().append_child(top);
+        top.append_child(a);
+        top.append_child(b);
+
+        let r = evaluate(
+            &doc,
+            intersection(),
+            vec![
+                Value::Nodeset(nodeset![a, b]),
+                Value::Nodeset(nodeset![top, b]),
+            ],
+        );
+
+        assert_eq!(vec![Node::from(b)], r);
+    }
+
+    #[test]
+    fn distinct_keeps_the_first_node_with_each_value() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        let a1 = doc.create_element("a");
+        let b = doc.create_element("b");
+        let a2 = doc.create_element("a");
+        doc.root().append_child(top);
+        for &(e, t) in &[(a1, "x"), (b, "y"), (a2, "x")] {
+            top.append_child(e);
+            e.append_child(doc.create_text(t));
+        }
+
+        let r = evaluate(&doc, Distinct, vec![Value::Nodeset(nodeset![a2, b, a1])]);
+
+        assert_eq!(vec![Node::from(a1), Node::from(b)], r);
+    }
+
+    #[test]
+    fn has_same_node_checks_identity() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let a = doc.create_element("a");
+        let also_a = doc.create_element("a");
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let same = HasSameNode.evaluate(
+            &evaluation,
+            vec![
+                Value::Nodeset(nodeset![a]),
+                Value::Nodeset(nodeset![also_a, a]),
+            ],
+        );
+        let different = HasSameNode.evaluate(
+            &evaluation,
+            vec![
+                Value::Nodeset(nodeset![a]),
+                Value::Nodeset(nodeset![also_a]),
+            ],
+        );
+
+        assert_eq!(Ok(Value::Boolean(true)), same);
+        assert_eq!(Ok(Value::Boolean(false)), different);
+    }
+
+    #[test]
+    fn leading_and_trailing_across_sibling_subtrees() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        let left = doc.create_element("left");
+        let l1 = doc.create_element("l1");
+        let middle = doc.create_element("middle");
+        let right = doc.create_element("right");
+        let r1 = doc.create_element("r1");
+        doc.root().append_child(top);
+        top.append_child(left);
+        left.append_child(l1);
+        top.append_child(middle);
+        top.append_child(right);
+        right.append_child(r1);
+
+        let all = || Value::Nodeset(nodeset![r1, right, l1, left]);
+        let boundary = || Value::Nodeset(nodeset![r1, middle]);
+
+        assert_eq!(
+            vec![Node::from(left), Node::from(l1)],
+            evaluate(&doc, leading(), vec![all(), boundary()])
+        );
+        assert_eq!(
+            vec![Node::from(right), Node::from(r1)],
+            evaluate(&doc, trailing(), vec![all(), boundary()])
+        );
+    }
+
+    #[test]
+    fn leading_and_trailing_ignore_nodes_outside_the_boundary_tree() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        let before = doc.create_element("before");
+        let middle = doc.create_element("middle");
+        let after = doc.create_element("after");
+        doc.root().append_child(top);
+        top.append_child(before);
+        top.append_child(middle);
+        top.append_child(after);
+        let detached = doc.create_element("detached");
+
+        let other_package = Package::new();
+        let other_doc = other_package.as_document();
+        let other_top = other_doc.create_element("other");
+        other_doc.root().append_child(other_top);
+
+        let all = || Value::Nodeset(nodeset![after, other_top, before, detached]);
+        let boundary = || Value::Nodeset(nodeset![middle]);
+
+        assert_eq!(
+            vec![Node::from(before)],
+            evaluate(&doc, leading(), vec![all(), boundary()])
+        );
+        assert_eq!(
+            vec![Node::from(after)],
+            evaluate(&doc, trailing(), vec![all(), boundary()])
+        );
+
+        let unordered = || Value::Nodeset(nodeset![detached]);
+        assert!(evaluate(&doc, trailing(), vec![all(), unordered()]).is_empty());
+        assert!(evaluate(&doc, leading(), vec![all(), unordered()]).is_empty());
+    }
+
+    #[test]
+    fn leading_and_trailing_of_empty_second_set_are_empty() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let top = doc.create_element("top");
+        doc.root().append_child(top);
+
+        let empty = || Value::Nodeset(nodeset![]);
+
+        assert!(evaluate(
+            &doc,
+            leading(),
+            vec![Value::Nodeset(nodeset![top]), empty()]
+        )
+        .is_empty());
+        assert!(evaluate(
+            &doc,
+            trailing(),
+            vec![Value::Nodeset(nodeset![top]), empty()]
+        )
+        .is_empty());
+    }
+}
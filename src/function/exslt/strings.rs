@@ -0,0 +1,775 @@
+//! The [EXSLT strings][strings] library.
+//!
+//! [strings]: http://exslt.org/str/index.html
+
+use std::cmp::Reverse;
+
+use crate::context;
+use crate::function::{Args, Arity, Error, Function};
+use crate::Value;
+
+use super::text_elements;
+
+/// The namespace URI of the strings library.
+pub const STRINGS_NAMESPACE_URI: &str = "http://exslt.org/strings";
+/// The prefix conventionally bound to the strings library.
+pub const STRINGS_PREFIX: &str = "str";
+
+struct Tokenize;
+
+impl Function for Tokenize {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(1, 2)?;
+        let delimiters = if args.len() == 2 {
+            Some(args.pop_string()?)
+        } else {
+            None
+        };
+        let s = args.pop_string()?;
+
+        let tokens: Vec<String> = if s.is_empty() {
+            Vec::new()
+        } else {
+            match delimiters {
+                // An empty delimiter set splits into single characters
+                Some(ref d) if d.is_empty() => s.chars().map(String::from).collect(),
+                // Explicit delimiters keep the empty tokens between them
+                Some(ref d) => s.split(|c| d.contains(c)).map(String::from).collect(),
+                None => s
+                    .split(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n')
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect(),
+            }
+        };
+
+        text_elements(context, "token", tokens).map(Value::Nodeset)
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(1, 2))
+    }
+}
+
+struct Split;
+
+impl Function for Split {
+    fn evaluate<'c, 'd>(
+        &self,
+        context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(1, 2)?;
+        let pattern = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            " ".to_owned()
+        };
+        let s = args.pop_string()?;
+
+        let tokens: Vec<String> = if s.is_empty() {
+            Vec::new()
+        } else if pattern.is_empty() {
+            s.chars().map(String::from).collect()
+        } else {
+            s.split(pattern.as_str()).map(String::from).collect()
+        };
+
+        text_elements(context, "token", tokens).map(Value::Nodeset)
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(1, 2))
+    }
+}
+
+/// Treats a nodeset as the list of its nodes' string-values, in
+/// document order, and any other value as a single string.
+fn string_list(value: Value<'_>) -> Vec<String> {
+    match value {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .iter()
+            .map(|n| n.string_value())
+            .collect(),
+        other => vec![other.into_string()],
+    }
+}
+
+/// Replaces every occurrence of each search string with the
+/// replacement at the same position, or with nothing if there is no
+/// such replacement. Longer search strings take precedence, and
+/// replaced text is never searched again.
+fn replace_all(s: &str, searches: &[String], replacements: &[String]) -> String {
+    let mut pairs: Vec<(&str, &str)> = Vec::new();
+    for (i, search) in searches.iter().enumerate() {
+        if search.is_empty() || pairs.iter().any(|&(seen, _)| seen == search) {
+            continue;
+        }
+        let replacement = replacements.get(i).map_or("", String::as_str);
+        pairs.push((search, replacement));
+    }
+    pairs.sort_by_key(|&(search, _)| Reverse(search.len()));
+
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    'outer: while let Some(c) = rest.chars().next() {
+        for &(search, replacement) in &pairs {
+            if rest.starts_with(search) {
+                result.push_str(replacement);
+                rest = &rest[search.len()..];
+                continue 'outer;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+struct Replace;
+
+impl Function for Replace {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(3)?;
+        let replacements = string_list(args.pop_value()?);
+        let searches = string_list(args.pop_value()?);
+        let s = args.pop_string()?;
+
+        Ok(Value::String(replace_all(&s, &searches, &replacements)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(3))
+    }
+}
+
+/// The most characters of padding that `str:padding` will produce, to
+/// avoid allocating without bound.
+const MAX_PADDING: usize = 1 << 24;
+
+struct Padding;
+
+impl Function for Padding {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(1, 2)?;
+        let chars = if args.len() == 2 {
+            args.pop_string()?
+        } else {
+            " ".to_owned()
+        };
+        let length = args.pop_number()?;
+
+        // NaN, negative and infinite lengths all produce nothing
+        let length = if length.is_finite() && length > 0.0 {
+            length.floor()
+        } else {
+            0.0
+        };
+        if length > MAX_PADDING as f64 {
+            return Err(Error::Other {
+                what: format!(
+                    "padding of {} characters is longer than the limit of {}",
+                    length, MAX_PADDING
+                ),
+            });
+        }
+        let length = length as usize;
+
+        let padding = chars.chars().cycle().take(length).collect();
+        Ok(Value::String(padding))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(1, 2))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+impl Alignment {
+    /// Unrecognized alignments fall back to left, as EXSLT specifies.
+    fn parse(s: &str) -> Alignment {
+        match s {
+            "right" => Alignment::Right,
+            "center" => Alignment::Center,
+            _ => Alignment::Left,
+        }
+    }
+}
+
+/// Overlays the target onto the padding, truncating the target if it
+/// is the longer of the two.
+fn align(target: &str, padding: &str, alignment: Alignment) -> String {
+    let target: Vec<char> = target.chars().collect();
+    let padding: Vec<char> = padding.chars().collect();
+
+    if target.len() >= padding.len() {
+        return target[..padding.len()].iter().collect();
+    }
+
+    let spare = padding.len() - target.len();
+    let start = match alignment {
+        Alignment::Left => 0,
+        Alignment::Right => spare,
+        Alignment::Center => spare / 2,
+    };
+
+    padding[..start]
+        .iter()
+        .chain(&target)
+        .chain(&padding[start + target.len()..])
+        .collect()
+}
+
+struct Align;
+
+impl Function for Align {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(2, 3)?;
+        let alignment = if args.len() == 3 {
+            Alignment::parse(&args.pop_string()?)
+        } else {
+            Alignment::Left
+        };
+        let padding = args.pop_string()?;
+        let target = args.pop_string()?;
+
+        Ok(Value::String(align(&target, &padding, alignment)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(2, 3))
+    }
+}
+
+struct Concat;
+
+impl Function for Concat {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.exactly(1)?;
+        let nodes = args.pop_nodeset()?;
+
+        let s = nodes
+            .document_order()
+            .iter()
+            .map(|n| n.string_value())
+            .collect();
+        Ok(Value::String(s))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::exactly(1))
+    }
+}
+
+/// Characters that are never percent-encoded.
+const UNRESERVED_MARKS: &str = "-_.!~*'()";
+/// Characters with a special meaning in URIs, per RFCs 2396 and 2732.
+const RESERVED: &str = ";/?:@&=+$,[]";
+
+/// Only UTF-8 is supported; other encodings produce the empty string.
+fn is_supported_encoding(args: &mut Args<'_>, count: usize) -> Result<bool, Error> {
+    if args.len() < count {
+        return Ok(true);
+    }
+    Ok(args.pop_string()?.eq_ignore_ascii_case("UTF-8"))
+}
+
+fn encode_uri(s: &str, escape_reserved: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        let keep = c.is_ascii_alphanumeric()
+            || UNRESERVED_MARKS.contains(c)
+            || (!escape_reserved && RESERVED.contains(c));
+        if keep {
+            result.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                result.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    result
+}
+
+fn decode_uri(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = &bytes[i + 1..i + 3];
+            // from_str_radix would also accept a sign, as in "%+1"
+            if hex.iter().all(u8::is_ascii_hexdigit) {
+                ::std::str::from_utf8(hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+struct EncodeUri;
+
+impl Function for EncodeUri {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(2, 3)?;
+        if !is_supported_encoding(&mut args, 3)? {
+            return Ok(Value::String(String::new()));
+        }
+        let escape_reserved = args.pop_boolean()?;
+        let s = args.pop_string()?;
+
+        Ok(Value::String(encode_uri(&s, escape_reserved)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(2, 3))
+    }
+}
+
+struct DecodeUri;
+
+impl Function for DecodeUri {
+    fn evaluate<'c, 'd>(
+        &self,
+        _context: &context::Evaluation<'c, 'd>,
+        args: Vec<Value<'d>>,
+    ) -> Result<Value<'d>, Error> {
+        let mut args = Args(args);
+        args.between(1, 2)?;
+        if !is_supported_encoding(&mut args, 2)? {
+            return Ok(Value::String(String::new()));
+        }
+        let s = args.pop_string()?;
+
+        Ok(Value::String(decode_uri(&s)))
+    }
+
+    fn arity(&self) -> Option<Arity> {
+        Some(Arity::between(1, 2))
+    }
+}
+
+/// Adds the EXSLT string functions and binds the `str` prefix to
+/// their namespace.
+pub fn register_exslt_strings(context: &mut context::Context<'_>) {
+    context.set_function((STRINGS_NAMESPACE_URI, "tokenize"), Tokenize);
+    context.set_function((STRINGS_NAMESPACE_URI, "split"), Split);
+    context.set_function((STRINGS_NAMESPACE_URI, "replace"), Replace);
+    context.set_function((STRINGS_NAMESPACE_URI, "padding"), Padding);
+    context.set_function((STRINGS_NAMESPACE_URI, "align"), Align);
+    context.set_function((STRINGS_NAMESPACE_URI, "concat"), Concat);
+    context.set_function((STRINGS_NAMESPACE_URI, "encode-uri"), EncodeUri);
+    context.set_function((STRINGS_NAMESPACE_URI, "decode-uri"), DecodeUri);
+    context.set_namespace(STRINGS_PREFIX, STRINGS_NAMESPACE_URI);
+}
+
+#[cfg(test)]
+mod test {
+    use sxd_document::Package;
+
+    use crate::context;
+    use crate::function::{ArgumentType, Error, Function};
+    use crate::{LiteralValue, Value};
+
+    use super::{
+        align, decode_uri, encode_uri, replace_all, Alignment, Concat, DecodeUri, EncodeUri,
+        Padding, Replace, Split, Tokenize, MAX_PADDING,
+    };
+
+    fn strings(args: &[&str]) -> Vec<LiteralValue> {
+        args.iter().map(|&s| Value::String(s.into())).collect()
+    }
+
+    fn evaluate_strings<F>(f: F, args: &[&str]) -> Result<Vec<String>, Error>
+    where
+        F: Function,
+    {
+        let package = Package::new();
+        let doc = package.as_document();
+        let scratch = Package::new();
+        let mut context = context::Context::without_core_functions();
+        context.set_scratch_document(scratch.as_document());
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        f.evaluate(&evaluation, strings(args)).map(|r| match r {
+            Value::Nodeset(nodes) => nodes
+                .document_order()
+                .iter()
+                .map(|n| n.string_value())
+                .collect(),
+            r => panic!("expected a nodeset, got {:?}", r),
+        })
+    }
+
+    #[test]
+    fn tokenize_splits_on_whitespace_by_default() {
+        assert_eq!(
+            Ok(vec!["one".to_owned(), "two".to_owned(), "three".to_owned()]),
+            evaluate_strings(Tokenize, &["  one \t two\n\nthree "])
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_empty_tokens_with_explicit_delimiters() {
+        assert_eq!(
+            Ok(vec![
+                "a".to_owned(),
+                "".to_owned(),
+                "b".to_owned(),
+                "c".to_owned()
+            ]),
+            evaluate_strings(Tokenize, &["a,,b;c", ",;"])
+        );
+    }
+
+    #[test]
+    fn tokenize_with_no_delimiters_splits_characters() {
+        assert_eq!(
+            Ok(vec!["a".to_owned(), "é".to_owned()]),
+            evaluate_strings(Tokenize, &["aé", ""])
+        );
+    }
+
+    #[test]
+    fn tokenize_of_empty_string_is_empty() {
+        assert_eq!(Ok(vec![]), evaluate_strings(Tokenize, &[""]));
+        assert_eq!(Ok(vec![]), evaluate_strings(Tokenize, &["", ","]));
+    }
+
+    #[test]
+    fn split_uses_the_whole_pattern_as_separator() {
+        assert_eq!(
+            Ok(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]),
+            evaluate_strings(Split, &["a--b--c", "--"])
+        );
+    }
+
+    #[test]
+    fn split_and_tokenize_differ_on_multi_character_separators() {
+        assert_eq!(
+            Ok(vec![
+                "a".to_owned(),
+                "".to_owned(),
+                "b".to_owned(),
+                "".to_owned(),
+                "c".to_owned()
+            ]),
+            evaluate_strings(Tokenize, &["a--b--c", "--"])
+        );
+        assert_eq!(
+            Ok(vec!["a-b".to_owned(), "c".to_owned()]),
+            evaluate_strings(Split, &["a-b--c", "--"])
+        );
+        assert_eq!(
+            Ok(vec![
+                "a".to_owned(),
+                "b".to_owned(),
+                "".to_owned(),
+                "c".to_owned()
+            ]),
+            evaluate_strings(Tokenize, &["a-b--c", "--"])
+        );
+    }
+
+    #[test]
+    fn split_defaults_to_a_single_space() {
+        assert_eq!(
+            Ok(vec!["a".to_owned(), "".to_owned(), "b".to_owned()]),
+            evaluate_strings(Split, &["a  b"])
+        );
+    }
+
+    #[test]
+    fn split_with_empty_pattern_splits_characters() {
+        assert_eq!(
+            Ok(vec!["x".to_owned(), "y".to_owned()]),
+            evaluate_strings(Split, &["xy", ""])
+        );
+        assert_eq!(Ok(vec![]), evaluate_strings(Split, &[""]));
+    }
+
+    fn evaluate_replace(args: &[&str]) -> String {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        Replace
+            .evaluate(&evaluation, strings(args))
+            .expect("replace failed")
+            .string()
+    }
+
+    fn owned(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|&s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn replace_replaces_every_occurrence() {
+        assert_eq!("a+b+c", evaluate_replace(&["a-b-c", "-", "+"]));
+    }
+
+    #[test]
+    fn replace_with_a_longer_string() {
+        assert_eq!(
+            "one and two and three",
+            evaluate_replace(&["one&two&three", "&", " and "])
+        );
+    }
+
+    #[test]
+    fn replace_does_not_rescan_overlapping_occurrences() {
+        assert_eq!("bba", evaluate_replace(&["aaaaa", "aa", "b"]));
+        assert_eq!("aaaa", evaluate_replace(&["aa", "a", "aa"]));
+    }
+
+    #[test]
+    fn replace_with_empty_search_returns_the_input() {
+        assert_eq!("unchanged", evaluate_replace(&["unchanged", "", "x"]));
+    }
+
+    #[test]
+    fn replace_prefers_longer_search_strings() {
+        assert_eq!(
+            "[ab]-[a]",
+            replace_all("ab-a", &owned(&["a", "ab"]), &owned(&["[a]", "[ab]"]))
+        );
+    }
+
+    #[test]
+    fn replace_without_a_matching_replacement_removes_the_search() {
+        assert_eq!(
+            "1-3",
+            replace_all("1-2-3", &owned(&["2", "-"]), &owned(&["-"]))
+        );
+    }
+
+    fn try_padding(args: Vec<LiteralValue>) -> Result<String, Error> {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        Padding.evaluate(&evaluation, args).map(|v| v.string())
+    }
+
+    fn evaluate_padding(args: Vec<LiteralValue>) -> String {
+        try_padding(args).expect("padding failed")
+    }
+
+    #[test]
+    fn padding_defaults_to_spaces() {
+        assert_eq!("   ", evaluate_padding(vec![Value::Number(3.0)]));
+    }
+
+    #[test]
+    fn padding_truncates_the_final_repetition() {
+        assert_eq!(
+            "-=-=-",
+            evaluate_padding(vec![Value::Number(5.0), Value::String("-=".into())])
+        );
+    }
+
+    #[test]
+    fn padding_coerces_its_arguments() {
+        assert_eq!(
+            "1111",
+            evaluate_padding(vec![Value::String("4".into()), Value::Number(1.0)])
+        );
+    }
+
+    #[test]
+    fn padding_of_invalid_lengths_is_empty() {
+        assert_eq!("", evaluate_padding(vec![Value::Number(::std::f64::NAN)]));
+        assert_eq!("", evaluate_padding(vec![Value::Number(-2.0)]));
+        assert_eq!(
+            "",
+            evaluate_padding(vec![Value::Number(3.0), Value::String("".into())])
+        );
+    }
+
+    #[test]
+    fn padding_beyond_the_limit_is_an_error() {
+        let limit = MAX_PADDING as f64;
+        assert_eq!(
+            MAX_PADDING,
+            evaluate_padding(vec![Value::Number(limit)]).len()
+        );
+
+        for &length in &[limit + 1.0, 1e15, 1e300] {
+            let error = try_padding(vec![Value::Number(length)]).expect_err("huge padding");
+            assert!(error.to_string().contains("limit"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn align_left_and_right() {
+        assert_eq!("ab---", align("ab", "-----", Alignment::Left));
+        assert_eq!("---ab", align("ab", "-----", Alignment::Right));
+    }
+
+    #[test]
+    fn align_center_with_odd_spare_space() {
+        assert_eq!("-ab--", align("ab", "-----", Alignment::Center));
+        assert_eq!("-abc-", align("abc", "-----", Alignment::Center));
+    }
+
+    #[test]
+    fn align_truncates_a_target_longer_than_the_padding() {
+        assert_eq!("abc", align("abcdef", "---", Alignment::Left));
+        assert_eq!("abc", align("abcdef", "---", Alignment::Right));
+    }
+
+    #[test]
+    fn align_falls_back_to_left_for_unknown_alignments() {
+        assert_eq!(Alignment::Left, Alignment::parse("middle"));
+    }
+
+    #[test]
+    fn concat_requires_a_nodeset() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        let r = Concat.evaluate(&evaluation, strings(&["a"]));
+
+        assert_eq!(
+            Err(Error::ArgumentNotANodeset {
+                index: 0,
+                actual: ArgumentType::String
+            }),
+            r
+        );
+    }
+
+    #[test]
+    fn encode_uri_escapes_reserved_characters_on_request() {
+        assert_eq!("a%2Fb%3Fc%3Dd", encode_uri("a/b?c=d", true));
+        assert_eq!("a/b?c=d", encode_uri("a/b?c=d", false));
+    }
+
+    #[test]
+    fn encode_uri_always_escapes_spaces_and_percent() {
+        assert_eq!("a%20b%25", encode_uri("a b%", false));
+        assert_eq!("it's-(ok)_~", encode_uri("it's-(ok)_~", true));
+    }
+
+    #[test]
+    fn encode_uri_escapes_non_ascii_as_utf8() {
+        assert_eq!("caf%C3%A9%E2%82%AC", encode_uri("café€", false));
+    }
+
+    #[test]
+    fn decode_uri_reverses_encoding() {
+        assert_eq!("café € a/b", decode_uri("caf%C3%A9%20%E2%82%AC%20a%2fb"));
+    }
+
+    #[test]
+    fn decode_uri_keeps_malformed_escapes() {
+        assert_eq!("100%", decode_uri("100%"));
+        assert_eq!("%zz%4", decode_uri("%zz%4"));
+    }
+
+    #[test]
+    fn decode_uri_requires_two_hex_digits() {
+        assert_eq!("%+1", decode_uri("%+1"));
+        assert_eq!("%-1a", decode_uri("%-1a"));
+        assert_eq!("% 1", decode_uri("% 1"));
+        assert_eq!("%\u{1}", decode_uri("%%01"));
+    }
+
+    fn evaluate_uri<F: Function>(f: F, args: Vec<LiteralValue>) -> String {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = context::Context::without_core_functions();
+        let evaluation = context::Evaluation::new(&context, doc.root().into());
+
+        f.evaluate(&evaluation, args)
+            .expect("evaluation failed")
+            .string()
+    }
+
+    #[test]
+    fn uri_functions_only_support_utf8() {
+        let encode = |encoding: &str| {
+            evaluate_uri(
+                EncodeUri,
+                vec![
+                    Value::String("é".into()),
+                    Value::Boolean(true),
+                    Value::String(encoding.into()),
+                ],
+            )
+        };
+        let decode = |encoding: &str| {
+            evaluate_uri(
+                DecodeUri,
+                vec![
+                    Value::String("%C3%A9".into()),
+                    Value::String(encoding.into()),
+                ],
+            )
+        };
+
+        assert_eq!("%C3%A9", encode("utf-8"));
+        assert_eq!("", encode("ISO-8859-1"));
+        assert_eq!("é", decode("UTF-8"));
+        assert_eq!("", decode("UTF-16"));
+    }
+}
@@ -83,9 +83,9 @@
 //! defined prefixes, some XPath behavior may be confusing:
 //!
 //! 1. The `name` method will not include a prefix, even if the
-//! element or attribute has a namespace.
+//!    element or attribute has a namespace.
 //! 2. The `namespace` axis will not include namespaces without
-//! prefixes.
+//!    prefixes.
 //!
 //! #### Document order
 //!
@@ -93,14 +93,22 @@
 //! nodes to the document, some XPath behavior may be confusing:
 //!
 //! 1. These nodes have no [*document order*]. If you create a
-//! variable containing these nodes and apply a predicate to them,
-//! these nodes will appear after any nodes that are present in the
-//! document, but the relative order of the nodes is undefined.
+//!    variable containing these nodes and apply a predicate to them,
+//!    these nodes will appear after any nodes that are present in the
+//!    document, but the relative order of the nodes is undefined.
+//!
+//! Functions that create nodes should append them beneath
+//! [`Evaluation::scratch_container`][scratch], which gives them a
+//! document order of their own.
+//!
+//! [scratch]: context/struct.Evaluation.html#method.scratch_container
 //!
 //! [*document order*]: https://www.w3.org/TR/xpath/#dt-document-order
 
 use snafu::{ResultExt, Snafu};
 use std::borrow::ToOwned;
+use std::collections::HashSet;
+use std::fmt;
 use std::string;
 use sxd_document::dom::Document;
 use sxd_document::{PrefixedName, QName};
@@ -112,8 +120,11 @@ pub use crate::context::Context;
 
 #[macro_use]
 pub mod macros;
+pub mod ast;
 mod axis;
 pub mod context;
+pub mod convert;
+mod excerpt;
 mod expression;
 pub mod function;
 mod node_test;
@@ -130,6 +141,25 @@ pub struct OwnedPrefixedName {
     local_part: String,
 }
 
+impl OwnedPrefixedName {
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_ref().map(String::as_str)
+    }
+
+    pub fn local_part(&self) -> &str {
+        &self.local_part
+    }
+}
+
+impl fmt::Display for OwnedPrefixedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.prefix {
+            Some(ref prefix) => write!(f, "{}:{}", prefix, self.local_part),
+            None => f.write_str(&self.local_part),
+        }
+    }
+}
+
 impl<'a> From<&'a str> for OwnedPrefixedName {
     fn from(local_part: &'a str) -> Self {
         OwnedPrefixedName {
@@ -215,66 +245,37 @@ pub enum Value<'d> {
     Nodeset(nodeset::Nodeset<'d>),
 }
 
-fn str_to_num(s: &str) -> f64 {
-    s.trim().parse().unwrap_or(::std::f64::NAN)
-}
-
 impl<'d> Value<'d> {
+    /// Converts the value to a boolean as the [`boolean`][] function does.
+    ///
+    /// Numbers are true unless they are zero or NaN, strings and
+    /// nodesets are true unless they are empty.
+    ///
+    /// [`boolean`]: https://www.w3.org/TR/xpath/#function-boolean
     pub fn boolean(&self) -> bool {
-        use crate::Value::*;
-        match *self {
-            Boolean(val) => val,
-            Number(n) => n != 0.0 && !n.is_nan(),
-            String(ref s) => !s.is_empty(),
-            Nodeset(ref nodeset) => nodeset.size() > 0,
-        }
+        convert::to_boolean(self)
     }
 
     pub fn into_boolean(self) -> bool {
         self.boolean()
     }
 
+    /// Converts the value to a number as the [`number`][] function does.
+    ///
+    /// [`number`]: https://www.w3.org/TR/xpath/#function-number
     pub fn number(&self) -> f64 {
-        use crate::Value::*;
-        match *self {
-            Boolean(val) => {
-                if val {
-                    1.0
-                } else {
-                    0.0
-                }
-            }
-            Number(val) => val,
-            String(ref s) => str_to_num(s),
-            Nodeset(..) => str_to_num(&self.string()),
-        }
+        convert::to_number(self)
     }
 
     pub fn into_number(self) -> f64 {
         self.number()
     }
 
+    /// Converts the value to a string as the [`string`][] function does.
+    ///
+    /// [`string`]: https://www.w3.org/TR/xpath/#function-string
     pub fn string(&self) -> string::String {
-        use crate::Value::*;
-        match *self {
-            Boolean(v) => v.to_string(),
-            Number(n) => {
-                if n.is_infinite() {
-                    if n.signum() < 0.0 {
-                        "-Infinity".to_owned()
-                    } else {
-                        "Infinity".to_owned()
-                    }
-                } else {
-                    n.to_string()
-                }
-            }
-            String(ref val) => val.clone(),
-            Nodeset(ref ns) => match ns.document_order_first() {
-                Some(n) => n.string_value(),
-                None => "".to_owned(),
-            },
-        }
+        convert::to_string(self)
     }
 
     pub fn into_string(self) -> string::String {
@@ -372,30 +373,198 @@ impl XPath {
         let context = context::Evaluation::new(context, node.into());
         self.0.evaluate(&context).map_err(ExecutionError)
     }
+
+    /// The variables that this expression refers to, including those
+    /// inside predicates and function arguments. Names are returned as
+    /// written; prefixes are only resolved when evaluating.
+    ///
+    /// ```
+    /// use sxd_xpath::{Factory, OwnedPrefixedName};
+    ///
+    /// let xpath = Factory::new().build("//item[@id = $id or @id = $id]").unwrap();
+    ///
+    /// let variables = xpath.referenced_variables();
+    /// assert_eq!(1, variables.len());
+    /// assert!(variables.contains(&OwnedPrefixedName::from("id")));
+    /// ```
+    pub fn referenced_variables(&self) -> HashSet<OwnedPrefixedName> {
+        self.references().variables
+    }
+
+    /// The functions that this expression calls, including those
+    /// inside predicates and function arguments. Names are returned as
+    /// written; prefixes are only resolved when evaluating.
+    pub fn referenced_functions(&self) -> HashSet<OwnedPrefixedName> {
+        self.references().functions
+    }
+
+    /// Copies the structure of this expression into a read-only
+    /// [`ast::Expr`](ast/enum.Expr.html).
+    ///
+    /// ```
+    /// use sxd_xpath::ast::{BinaryOperator, Expr};
+    /// use sxd_xpath::{Factory, Value};
+    ///
+    /// let xpath = Factory::new().build("1 + 2").unwrap();
+    ///
+    /// let number = |n| Box::new(Expr::Literal(Value::Number(n)));
+    /// let expected = Expr::Binary {
+    ///     operator: BinaryOperator::Addition,
+    ///     left: number(1.0),
+    ///     right: number(2.0),
+    /// };
+    /// assert_eq!(expected, xpath.to_ast());
+    /// ```
+    pub fn to_ast(&self) -> ast::Expr {
+        self.0.to_ast()
+    }
+
+    fn references(&self) -> expression::References {
+        let mut references = expression::References::default();
+        self.0.collect_references(&mut references);
+        references
+    }
+}
+
+/// Displays the expression in unabbreviated XPath syntax. Building
+/// the displayed text gives an equivalent expression.
+///
+/// ```
+/// use sxd_xpath::Factory;
+///
+/// let xpath = Factory::new().build("//a[@b > 1] | $c").unwrap();
+///
+/// assert_eq!(
+///     "/descendant-or-self::node()/child::a[attribute::b > 1] | $c",
+///     xpath.to_string()
+/// );
+/// ```
+impl fmt::Display for XPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_ast().fmt(f)
+    }
 }
 
 /// The primary entrypoint to convert an XPath represented as a string
 /// to a structure that can be evaluated.
 pub struct Factory {
     parser: Parser,
+    local_name_wildcards: bool,
 }
 
 impl Factory {
     pub fn new() -> Factory {
         Factory {
             parser: Parser::new(),
+            local_name_wildcards: false,
         }
     }
 
-    /// Compiles the given string into an XPath structure.
-    pub fn build(&self, xpath: &str) -> Result<XPath, ParserError> {
-        let tokenizer = Tokenizer::new(xpath);
-        let deabbreviator = TokenDeabbreviator::new(tokenizer);
+    /// Checks every function call against these functions while
+    /// parsing. A call to a function that reports its
+    /// [`arity`](function/trait.Function.html#method.arity) fails to
+    /// build if it has the wrong number of arguments.
+    ///
+    /// Calls to functions that are not in the table are allowed
+    /// unless [`set_strict_functions`](#method.set_strict_functions)
+    /// is enabled.
+    ///
+    /// ```
+    /// use sxd_xpath::{Context, Factory};
+    ///
+    /// let mut factory = Factory::new();
+    /// factory.set_functions(Context::new().functions().clone());
+    ///
+    /// assert!(factory.build("contains('a')").is_err());
+    /// assert!(factory.build("contains('a', 'b')").is_ok());
+    /// ```
+    pub fn set_functions(&mut self, functions: context::Functions) {
+        self.parser.signatures_mut().functions = functions;
+    }
 
+    /// Binds a prefix so that calls to prefixed functions can be
+    /// checked. Calls through an unbound prefix are not checked.
+    pub fn set_namespace(&mut self, prefix: &str, uri: &str) {
         self.parser
-            .parse(deabbreviator)
+            .signatures_mut()
+            .namespaces
+            .insert(prefix.into(), uri.into());
+    }
+
+    /// Rejects calls to functions that are not in the table given to
+    /// [`set_functions`](#method.set_functions).
+    pub fn set_strict_functions(&mut self, strict: bool) {
+        self.parser.signatures_mut().strict = strict;
+    }
+
+    /// Accepts the XPath 2.0 `*:local` name test, which matches a
+    /// local name in any namespace. This is not part of XPath 1.0, so
+    /// it is a syntax error unless enabled.
+    ///
+    /// ```
+    /// use sxd_xpath::Factory;
+    ///
+    /// let mut factory = Factory::new();
+    /// assert!(factory.build("//*:item").is_err());
+    ///
+    /// factory.set_local_name_wildcards(true);
+    /// assert!(factory.build("//*:item").is_ok());
+    /// ```
+    pub fn set_local_name_wildcards(&mut self, enabled: bool) {
+        self.local_name_wildcards = enabled;
+    }
+
+    /// Limits how deeply expressions may nest. Parenthesized
+    /// expressions, predicates, function arguments and unary minus
+    /// each add a level. Arithmetic and comparison operators in a
+    /// chain such as `1 + 2 + 3` add a level for every four operators,
+    /// and chains of `or`, `and` and `|` for every time their length
+    /// doubles. Deeper expressions fail to build with
+    /// [`ParseErrorKind::TooDeep`](enum.ParseErrorKind.html#variant.TooDeep)
+    /// instead of overflowing the stack while being parsed or
+    /// evaluated.
+    ///
+    /// The default is 40, which fits on a thread with a 2 MiB stack,
+    /// the default for spawned threads, even in a debug build. Release
+    /// builds take much less of the stack for each level, so a larger
+    /// limit may be set when expressions are known to need it.
+    ///
+    /// ```
+    /// use sxd_xpath::{Factory, ParseErrorKind};
+    ///
+    /// let mut factory = Factory::new();
+    /// factory.set_max_depth(2);
+    ///
+    /// assert!(factory.build("((1))").is_ok());
+    /// let error = factory.build("(((1)))").expect_err("too deep");
+    /// assert_eq!(ParseErrorKind::TooDeep, error.kind());
+    /// ```
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.parser.set_max_depth(max_depth);
+    }
+
+    /// Compiles the given string into an XPath structure.
+    ///
+    /// ```
+    /// use sxd_xpath::{Factory, ParseErrorKind};
+    ///
+    /// let error = Factory::new().build("//foo[bar").expect_err("XPath is invalid");
+    ///
+    /// assert_eq!(ParseErrorKind::UnclosedBracket, error.kind());
+    /// assert_eq!(9, error.offset());
+    /// ```
+    pub fn build(&self, xpath: &str) -> Result<XPath, ParseError> {
+        let mut tokenizer = Tokenizer::new(xpath);
+        tokenizer.set_local_name_wildcards(self.local_name_wildcards);
+
+        let result = {
+            let deabbreviator = TokenDeabbreviator::new(&mut tokenizer);
+            self.parser.parse(deabbreviator)
+        };
+
+        result
             .map(XPath)
-            .map_err(Into::into)
+            .map_err(|e| ParseError::new(xpath, &tokenizer, e))
     }
 }
 
@@ -405,9 +574,195 @@ impl Default for Factory {
     }
 }
 
+/// The broad reason an XPath could not be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    /// The XPath contained no expression
+    Empty,
+    /// Text that is not an XPath token, such as `#` or `!`
+    InvalidToken,
+    /// A string literal without its closing quote
+    UnterminatedLiteral,
+    /// A token that is not allowed where it appears
+    UnexpectedToken,
+    /// The XPath ended before the expression was complete
+    UnexpectedEnd,
+    /// A `[` without its closing `]`
+    UnclosedBracket,
+    /// A `(` without its closing `)`
+    UnclosedParenthesis,
+    /// Tokens left over after a complete expression
+    TrailingTokens,
+    /// A predicate with no expression, `[]`
+    EmptyPredicate,
+    /// A path that ends with `/` after a step
+    TrailingSlash,
+    /// An operator without its right-hand side
+    MissingOperand,
+    /// A function call with a missing argument, such as `f(1,)`
+    MissingArgument,
+    /// A function called with the wrong number of arguments
+    WrongNumberOfArguments,
+    /// A call to a function that is not known to the factory
+    UnknownFunction,
+    /// An expression nested more deeply than the factory allows
+    TooDeep,
+}
+
 /// Errors that may occur when parsing an XPath
-#[derive(Debug, Snafu, Clone, PartialEq)]
-pub struct ParserError(parser::Error);
+///
+/// Besides the [`kind`](#method.kind) of error, this reports the byte
+/// [`offset`](#method.offset) in the XPath where it was detected and,
+/// when the error was caused by a particular token, the
+/// [`token`](#method.token)'s text. Errors detected at the end of the
+/// XPath have an offset equal to its length and no token.
+///
+/// ```
+/// use sxd_xpath::{Factory, ParseErrorKind};
+///
+/// let error = Factory::new().build("1 + * 2").expect_err("XPath is invalid");
+///
+/// assert_eq!(ParseErrorKind::TrailingTokens, error.kind());
+/// assert_eq!(6, error.offset());
+/// assert_eq!(Some("2"), error.token());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    xpath: String,
+    kind: ParseErrorKind,
+    offset: usize,
+    token: Option<String>,
+    source: Box<parser::Error>,
+}
+
+/// The previous name of [`ParseError`](struct.ParseError.html)
+#[deprecated(since = "0.4.3", note = "renamed to ParseError")]
+pub type ParserError = ParseError;
+
+impl ParseError {
+    fn new(xpath: &str, tokenizer: &Tokenizer, source: parser::Error) -> ParseError {
+        use crate::parser::Error::*;
+
+        // The parser never reads past a token it could not get, so a
+        // tokenizer error is the first problem even if the parser
+        // reported something else while peeking at it. The exception
+        // is text left over after a complete expression, which is
+        // trailing whether or not it forms tokens.
+        let trailing_text = source == ExtraUnparsedTokens && tokenizer.error().is_some();
+        let source = match tokenizer.error() {
+            Some(source) if !trailing_text => Tokenizer { source },
+            _ => source,
+        };
+
+        let kind = match source {
+            NoXPath => ParseErrorKind::Empty,
+            EmptyPredicate => ParseErrorKind::EmptyPredicate,
+            ExtraUnparsedTokens => ParseErrorKind::TrailingTokens,
+            RanOutOfInput => ParseErrorKind::UnexpectedEnd,
+            UnclosedBracket => ParseErrorKind::UnclosedBracket,
+            UnclosedParenthesis => ParseErrorKind::UnclosedParenthesis,
+            RightHandSideExpressionMissing => ParseErrorKind::MissingOperand,
+            ArgumentMissing => ParseErrorKind::MissingArgument,
+            Tokenizer {
+                source: tokenizer::Error::MismatchedQuoteCharacters,
+            } => ParseErrorKind::UnterminatedLiteral,
+            Tokenizer { .. } => ParseErrorKind::InvalidToken,
+            TrailingSlash => ParseErrorKind::TrailingSlash,
+            UnexpectedToken { .. } => ParseErrorKind::UnexpectedToken,
+            WrongNumberOfArguments { .. } => ParseErrorKind::WrongNumberOfArguments,
+            UnknownFunction { .. } => ParseErrorKind::UnknownFunction,
+            TooDeep { .. } => ParseErrorKind::TooDeep,
+        };
+
+        // A trailing slash is the last token read, even though the
+        // parser had to look past it to notice.
+        let span = match source {
+            _ if trailing_text => None,
+            Tokenizer { .. } => None,
+            TrailingSlash => tokenizer.last_token_span(),
+            _ if tokenizer.is_exhausted() => None,
+            _ => tokenizer.last_token_span(),
+        };
+
+        let (offset, token) = match (span, &source) {
+            (Some(span), _) => (span.start, Some(xpath[span].to_owned())),
+            (None, Tokenizer { .. }) => (tokenizer.offset(), None),
+            (None, _) if trailing_text => (tokenizer.offset(), None),
+            (None, _) => (xpath.len(), None),
+        };
+
+        ParseError {
+            xpath: xpath.to_owned(),
+            kind,
+            offset,
+            token,
+            source: Box::new(source),
+        }
+    }
+
+    /// The XPath that could not be parsed
+    pub fn xpath(&self) -> &str {
+        &self.xpath
+    }
+
+    /// The broad reason the XPath could not be parsed
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// Whether the XPath could not be split into tokens, as opposed
+    /// to being made of tokens that do not form an expression
+    pub fn is_tokenizer_error(&self) -> bool {
+        match *self.source {
+            parser::Error::Tokenizer { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The byte offset in the XPath where the error was detected
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The text of the token that caused the error, if any
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_ref().map(String::as_str)
+    }
+
+    /// Describes the error along with the XPath, marking where the
+    /// error was detected with carets. Long XPaths are shortened to
+    /// the part around the error.
+    ///
+    /// ```
+    /// use sxd_xpath::Factory;
+    ///
+    /// let error = Factory::new().build("1 + * 2").unwrap_err();
+    ///
+    /// assert_eq!(
+    ///     "error: trailing tokens after a complete expression\n  \
+    ///      | 1 + * 2\n  \
+    ///      |       ^",
+    ///     error.pretty()
+    /// );
+    /// ```
+    pub fn pretty(&self) -> String {
+        let length = self.token.as_ref().map_or(0, String::len);
+        let (line, marker) = excerpt::excerpt(&self.xpath, self.offset, length);
+        format!("error: {}\n  | {}\n  | {}", self.source, line, marker)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.source, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
 
 /// Errors that may occur when executing an XPath
 #[derive(Debug, Snafu, Clone, PartialEq)]
@@ -418,7 +773,7 @@ pub struct ExecutionError(expression::Error);
 pub enum Error {
     /// The XPath was syntactically invalid
     #[snafu(display("Unable to parse XPath: {}", source))]
-    Parsing { source: ParserError },
+    Parsing { source: ParseError },
     /// The XPath could not be executed
     #[snafu(display("Unable to execute XPath: {}", source))]
     Executing { source: ExecutionError },
@@ -489,6 +844,42 @@ mod test {
         assert!(v.number().is_nan());
     }
 
+    #[test]
+    fn number_of_string_with_trailing_decimal_point_is_number() {
+        let v = Value::String("12.".to_owned());
+        assert_eq!(12.0, v.number());
+    }
+
+    #[test]
+    fn number_of_string_with_leading_decimal_point_is_number() {
+        let v = Value::String(".5".to_owned());
+        assert_eq!(0.5, v.number());
+    }
+
+    #[test]
+    fn number_of_string_with_exponent_is_nan() {
+        let v = Value::String("1e3".to_owned());
+        assert!(v.number().is_nan());
+    }
+
+    #[test]
+    fn number_of_string_with_plus_sign_is_nan() {
+        let v = Value::String("+1".to_owned());
+        assert!(v.number().is_nan());
+    }
+
+    #[test]
+    fn number_of_infinity_string_is_nan() {
+        let v = Value::String("Infinity".to_owned());
+        assert!(v.number().is_nan());
+    }
+
+    #[test]
+    fn number_of_lone_decimal_point_is_nan() {
+        let v = Value::String(".".to_owned());
+        assert!(v.number().is_nan());
+    }
+
     #[test]
     fn number_of_boolean_true_is_1() {
         let v = Value::Boolean(true);
@@ -605,14 +996,147 @@ mod test {
         with_document("<root><child>content</child></root>", |doc| {
             let result = evaluate_xpath(&doc, "/root/child/");
 
-            let expected_error = crate::parser::TrailingSlash
-                .fail()
-                .map_err(ParserError::from)
-                .context(Parsing);
+            let expected_error = Err(ParseError {
+                xpath: "/root/child/".to_owned(),
+                kind: ParseErrorKind::TrailingSlash,
+                offset: 11,
+                token: Some("/".to_owned()),
+                source: Box::new(crate::parser::Error::TrailingSlash),
+            })
+            .context(Parsing);
             assert_eq!(expected_error, result);
         });
     }
 
+    fn parse_error(xpath: &str) -> (ParseErrorKind, usize, Option<String>) {
+        let error = Factory::new()
+            .build(xpath)
+            .expect_err("XPath should not parse");
+        (error.kind(), error.offset(), error.token().map(Into::into))
+    }
+
+    #[test]
+    fn parse_errors_report_kind_and_offset() {
+        use self::ParseErrorKind::*;
+
+        let at = |kind, offset, token: &str| (kind, offset, Some(token.to_owned()));
+        let at_end = |kind, offset| (kind, offset, None);
+
+        assert_eq!(at_end(UnclosedBracket, 9), parse_error("//foo[bar"));
+        assert_eq!(at(TrailingTokens, 6, "2"), parse_error("1 + * 2"));
+        assert_eq!(at_end(UnclosedParenthesis, 8), parse_error("count(a "));
+        assert_eq!(at_end(MissingOperand, 3), parse_error("1 +"));
+        assert_eq!(at(MissingOperand, 4, "]"), parse_error("1 + ]"));
+        assert_eq!(at(EmptyPredicate, 3, "]"), parse_error("a[ ]"));
+        assert_eq!(at(TrailingSlash, 1, "/"), parse_error("a/"));
+        assert_eq!(at(TrailingTokens, 2, ")"), parse_error("a )"));
+        assert_eq!(at(MissingArgument, 4, ")"), parse_error("f(1,)"));
+        assert_eq!(at_end(InvalidToken, 4), parse_error("a = #"));
+        assert_eq!(at_end(UnterminatedLiteral, 4), parse_error("a = 'b"));
+        assert_eq!(at_end(Empty, 0), parse_error(""));
+        assert_eq!(at(TooDeep, 40, "("), parse_error(&"(".repeat(100)));
+    }
+
+    fn names(names: &[&str]) -> HashSet<OwnedPrefixedName> {
+        names
+            .iter()
+            .map(|name| match name.find(':') {
+                Some(i) => OwnedPrefixedName::from((&name[..i], &name[i + 1..])),
+                None => OwnedPrefixedName::from(*name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn referenced_variables_include_predicates_and_arguments() {
+        let xpath = Factory::new()
+            .build("$a/b[$c + 1][f($d, g($ns:e))] | $a")
+            .expect("XPath should build");
+
+        assert_eq!(
+            names(&["a", "c", "d", "ns:e"]),
+            xpath.referenced_variables()
+        );
+        assert_eq!(names(&["f", "g"]), xpath.referenced_functions());
+    }
+
+    #[test]
+    fn expressions_without_references_report_none() {
+        let xpath = Factory::new()
+            .build("/a/b[1] = 'c'")
+            .expect("XPath should build");
+
+        assert!(xpath.referenced_variables().is_empty());
+        assert!(xpath.referenced_functions().is_empty());
+    }
+
+    #[test]
+    fn ast_shows_filters_negation_and_processing_instructions() {
+        use crate::ast::{Axis, Expr, NodeTest, Step};
+
+        let xpath = Factory::new()
+            .build("-(processing-instruction('style'))[1]")
+            .expect("XPath should build");
+
+        let instructions = Expr::Path {
+            start: Box::new(Expr::ContextNode),
+            steps: vec![Step {
+                axis: Axis::Child,
+                node_test: NodeTest::ProcessingInstruction(Some("style".into())),
+                predicates: vec![],
+            }],
+        };
+        let expected = Expr::Negation(Box::new(Expr::Filter {
+            expression: Box::new(instructions),
+            predicate: Box::new(Expr::Literal(Value::Number(1.0))),
+        }));
+
+        assert_eq!(expected, xpath.to_ast());
+    }
+
+    #[test]
+    fn parse_errors_render_with_a_caret_excerpt() {
+        let pretty = |xpath: &str| {
+            Factory::new()
+                .build(xpath)
+                .expect_err("XPath should not parse")
+                .pretty()
+        };
+
+        assert_eq!(
+            "error: ran out of input before a closing bracket\n  \
+             | //foo[bar\n  \
+             |          ^",
+            pretty("//foo[bar")
+        );
+        assert_eq!(
+            "error: trailing tokens after a complete expression\n  \
+             | //名前 = 'ü' )\n  \
+             |              ^",
+            pretty("//名前 = 'ü' )")
+        );
+
+        let long = format!("{} or f(1,)", vec!["$v"; 40].join(" or "));
+        assert_eq!(
+            "error: function argument is missing\n  \
+             | ... $v or $v or $v or $v or $v or $v or $v or $v or $v or f(1,)\n  \
+             |                                                               ^",
+            pretty(&long)
+        );
+    }
+
+    #[test]
+    fn parse_error_display_includes_the_offset() {
+        let error = Factory::new()
+            .build("//foo[bar")
+            .expect_err("XPath should not parse");
+
+        assert_eq!(
+            "ran out of input before a closing bracket at offset 9",
+            error.to_string()
+        );
+    }
+
     #[test]
     fn xpath_evaluation_execution_error() {
         with_document("<root><child>content</child></root>", |doc| {
@@ -2,11 +2,21 @@ use std::fmt;
 
 use sxd_document::QName;
 
+use crate::ast;
 use crate::context;
 use crate::nodeset::{self, OrderedNodes};
 
 pub trait NodeTest: fmt::Debug {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>);
+
+    /// The namespace prefix that must be bound in the context for
+    /// this test to be evaluated.
+    fn prefix(&self) -> Option<&str> {
+        None
+    }
+
+    /// Copies this test into the public AST.
+    fn to_ast(&self) -> ast::NodeTest;
 }
 
 impl<T: ?Sized> NodeTest for Box<T>
@@ -16,10 +26,20 @@ where
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
         (**self).test(context, result)
     }
+
+    fn prefix(&self) -> Option<&str> {
+        (**self).prefix()
+    }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        (**self).to_ast()
+    }
 }
 
 pub type SubNodeTest = Box<dyn NodeTest + 'static>;
 
+/// A name test. A `local_part` of `*` matches any local name and a
+/// `prefix` of `*` matches any namespace, as in `*:local`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct NameTest {
     pub prefix: Option<String>,
@@ -27,13 +47,52 @@ pub struct NameTest {
 }
 
 impl NameTest {
+    fn prefix(&self) -> Option<&str> {
+        if self.has_any_namespace() {
+            None
+        } else {
+            self.prefix.as_ref().map(String::as_str)
+        }
+    }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        ast::NodeTest::Name {
+            prefix: self.prefix.clone(),
+            local_part: self.local_part.clone(),
+        }
+    }
+
+    fn has_any_namespace(&self) -> bool {
+        self.prefix.as_ref().map_or(false, |p| p == "*")
+    }
+
     fn matches(&self, context: &context::Evaluation<'_, '_>, node_name: QName<'_>) -> bool {
+        self.matches_with_default(context, node_name, None)
+    }
+
+    /// Matches as if an unprefixed name were in `default_uri`.
+    fn matches_with_default(
+        &self,
+        context: &context::Evaluation<'_, '_>,
+        node_name: QName<'_>,
+        default_uri: Option<&str>,
+    ) -> bool {
         let is_wildcard = self.local_part == "*";
 
-        let test_uri = self.prefix.as_ref().map(|p| {
-            // TODO: Error for undefined prefix
-            context.namespace_for(p).expect("No namespace for prefix")
-        });
+        if self.has_any_namespace() {
+            return is_wildcard || self.local_part == node_name.local_part();
+        }
+
+        // Steps reject unbound prefixes before running the test, so an
+        // unresolved prefix can only be seen when a test is used alone.
+        let test_uri = match self.prefix {
+            Some(ref p) => match context.namespace_for(p) {
+                Some(uri) => Some(uri),
+                None => return false,
+            },
+            None if is_wildcard => None,
+            None => default_uri,
+        };
 
         match (is_wildcard, test_uri) {
             (true, None) => true,
@@ -62,6 +121,14 @@ impl NodeTest for Attribute {
             }
         }
     }
+
+    fn prefix(&self) -> Option<&str> {
+        self.name_test.prefix()
+    }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        self.name_test.to_ast()
+    }
 }
 
 #[derive(Debug)]
@@ -83,6 +150,14 @@ impl NodeTest for Namespace {
             }
         }
     }
+
+    fn prefix(&self) -> Option<&str> {
+        self.name_test.prefix()
+    }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        self.name_test.to_ast()
+    }
 }
 
 #[derive(Debug)]
@@ -99,11 +174,23 @@ impl Element {
 impl NodeTest for Element {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
         if let nodeset::Node::Element(ref e) = context.node {
-            if self.name_test.matches(context, e.name()) {
+            let default_uri = context.default_element_namespace();
+            if self
+                .name_test
+                .matches_with_default(context, e.name(), default_uri)
+            {
                 result.add(context.node);
             }
         }
     }
+
+    fn prefix(&self) -> Option<&str> {
+        self.name_test.prefix()
+    }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        self.name_test.to_ast()
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -114,6 +201,10 @@ impl NodeTest for Node {
     fn test<'c, 'd>(&self, context: &context::Evaluation<'c, 'd>, result: &mut OrderedNodes<'d>) {
         result.add(context.node);
     }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        ast::NodeTest::Node
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -126,6 +217,10 @@ impl NodeTest for Text {
             result.add(context.node);
         }
     }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        ast::NodeTest::Text
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -138,6 +233,10 @@ impl NodeTest for Comment {
             result.add(context.node);
         }
     }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        ast::NodeTest::Comment
+    }
 }
 
 #[derive(Debug)]
@@ -161,6 +260,10 @@ impl NodeTest for ProcessingInstruction {
             }
         }
     }
+
+    fn to_ast(&self) -> ast::NodeTest {
+        ast::NodeTest::ProcessingInstruction(self.target.clone())
+    }
 }
 
 #[cfg(test)]
@@ -181,7 +284,7 @@ mod test {
     }
 
     impl<'d> Setup<'d> {
-        fn new(package: &'d Package) -> Setup<'_> {
+        fn new(package: &'d Package) -> Setup<'d> {
             Setup {
                 doc: package.as_document(),
                 context: Context::without_core_functions(),
@@ -434,4 +537,74 @@ mod test {
         let result = run_element(&context, None, "name");
         assert_eq!(ordered_nodes![], result);
     }
+
+    #[test]
+    fn element_test_reports_its_prefix() {
+        let name = NameTest {
+            prefix: Some("prefix".to_owned()),
+            local_part: "name".to_owned(),
+        };
+        let unprefixed = NameTest {
+            prefix: None,
+            local_part: "name".to_owned(),
+        };
+
+        assert_eq!(Some("prefix"), Element::new(name).prefix());
+        assert_eq!(None, Element::new(unprefixed).prefix());
+        assert_eq!(None, Comment.prefix());
+    }
+
+    #[test]
+    fn element_test_does_not_match_with_an_unbound_prefix() {
+        let package = Package::new();
+        let setup = Setup::new(&package);
+        let (_, context) = setup.context_for_element("name");
+
+        let result = run_element(&context, Some("unbound"), "name");
+        assert_eq!(ordered_nodes![], result);
+    }
+
+    #[test]
+    fn element_test_with_any_namespace_matches_on_local_name() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        let (element, context) = setup.context_for_ns_element("prefix", "uri", "name");
+
+        let result = run_element(&context, Some("*"), "name");
+        assert_eq!(ordered_nodes![element], result);
+
+        let result = run_element(&context, Some("*"), "wrong-name");
+        assert_eq!(ordered_nodes![], result);
+
+        let name = NameTest {
+            prefix: Some("*".to_owned()),
+            local_part: "name".to_owned(),
+        };
+        assert_eq!(None, Element::new(name).prefix());
+    }
+
+    #[test]
+    fn element_test_uses_the_default_element_namespace_for_unprefixed_names() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup.context.set_default_element_namespace("uri");
+        let (element, context) = setup.context_for_element(("uri", "name"));
+
+        let result = run_element(&context, None, "name");
+        assert_eq!(ordered_nodes![element], result);
+
+        let result = run_element(&context, None, "*");
+        assert_eq!(ordered_nodes![element], result);
+    }
+
+    #[test]
+    fn attribute_test_ignores_the_default_element_namespace() {
+        let package = Package::new();
+        let mut setup = Setup::new(&package);
+        setup.context.set_default_element_namespace("uri");
+        let (attribute, context) = setup.context_for_attribute("name", "value");
+
+        let result = run_attribute(&context, None, "name");
+        assert_eq!(ordered_nodes![attribute], result);
+    }
 }
@@ -1,6 +1,7 @@
 //! Support for collections of nodes.
 
 use std::borrow::ToOwned;
+use std::cmp::Ordering;
 use std::collections::hash_set;
 use std::collections::{HashMap, HashSet};
 use std::iter::{FromIterator, IntoIterator};
@@ -63,6 +64,39 @@ impl<'d> Namespace<'d> {
     pub fn expanded_name(&self) -> QName<'d> {
         QName::new(self.prefix)
     }
+
+    /// The namespace nodes of an element: one for each prefix in
+    /// scope, with the nearest declaration winning, and one with an
+    /// empty prefix for the default namespace, if there is one.
+    pub(crate) fn in_scope(element: dom::Element<'d>) -> Vec<Namespace<'d>> {
+        // The DOM does not record `xmlns=""` undeclaring the default
+        // namespace, but an unprefixed element without a namespace
+        // can only occur where there is no default namespace.
+        let undeclared =
+            element.preferred_prefix().is_none() && element.name().namespace_uri().is_none();
+        let default = element
+            .recursive_default_namespace_uri()
+            .filter(|uri| !undeclared && !uri.is_empty())
+            .map(|uri| Namespace {
+                parent: element,
+                prefix: "",
+                uri,
+            });
+
+        default
+            .into_iter()
+            .chain(
+                element
+                    .namespaces_in_scope()
+                    .into_iter()
+                    .map(|ns| Namespace {
+                        parent: element,
+                        prefix: ns.prefix(),
+                        uri: ns.uri(),
+                    }),
+            )
+            .collect()
+    }
 }
 
 /// Any of the various types of nodes found in an XML document.
@@ -110,7 +144,7 @@ impl<'d> Node<'d> {
             } else {
                 name.local_part().to_owned()
             }
-        };
+        }
 
         match *self {
             Root(_) => None,
@@ -220,25 +254,29 @@ impl<'d> Node<'d> {
         }
     }
 
-    /// Returns the [string value] of this node.
+    /// Returns the [string value] of this node. For the root and for
+    /// elements, this is the text of every descendant text node in
+    /// document order, however deeply it is nested.
     ///
     /// [string value]: https://www.w3.org/TR/xpath/#dt-string-value
     pub fn string_value(&self) -> String {
         use self::Node::*;
 
-        fn document_order_text_nodes(node: Node<'_>, result: &mut String) {
-            for child in node.children() {
+        // Uses an explicit stack so that deeply nested documents do
+        // not overflow the call stack.
+        fn text_descendants_string_value(node: Node<'_>) -> String {
+            let mut result = String::new();
+            let mut stack = node.children();
+            stack.reverse();
+
+            while let Some(child) = stack.pop() {
                 match child {
-                    Node::Element(_) => document_order_text_nodes(child, result),
+                    Node::Element(_) => stack.extend(child.children().into_iter().rev()),
                     Node::Text(n) => result.push_str(n.text()),
                     _ => {}
                 }
             }
-        };
 
-        fn text_descendants_string_value(node: Node<'_>) -> String {
-            let mut result = String::new();
-            document_order_text_nodes(node, &mut result);
             result
         }
 
@@ -323,7 +361,9 @@ impl<'d> Nodeset<'d> {
         Default::default()
     }
 
-    /// Checks if the node is present in the set
+    /// Checks if the node is present in the set. Nodes are compared by
+    /// identity, so two distinct nodes with the same name and content
+    /// are not the same node.
     pub fn contains<N>(&self, node: N) -> bool
     where
         N: Into<Node<'d>>,
@@ -351,10 +391,7 @@ impl<'d> Nodeset<'d> {
     ///
     /// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
     pub fn document_order_first(&self) -> Option<Node<'d>> {
-        let node = match self.nodes.iter().next() {
-            Some(n) => n,
-            None => return None,
-        };
+        let node = self.nodes.iter().next()?;
 
         if self.nodes.len() == 1 {
             return Some(*node);
@@ -394,13 +431,18 @@ impl<'d> Extend<Node<'d>> for Nodeset<'d> {
     }
 }
 
+/// The position of every node of a document in [document order],
+/// for comparing arbitrary nodes of that document.
+///
+/// [document order]: https://www.w3.org/TR/xpath/#dt-document-order
 // Rebuilding this multiple times cannot possibly be performant,
 // but I want to see how widely used this is first before
 // picking an appropriate caching point.
-struct DocOrder<'d>(HashMap<Node<'d>, usize>);
+pub(crate) struct DocOrder<'d>(HashMap<Node<'d>, usize>);
 
 impl<'d> DocOrder<'d> {
-    fn new(doc: dom::Document<'d>) -> Self {
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn new(doc: dom::Document<'d>) -> Self {
         let mut idx = 0;
         let mut stack: Vec<Node<'_>> = vec![doc.root().into()];
         let mut order = HashMap::new();
@@ -412,18 +454,30 @@ impl<'d> DocOrder<'d> {
             stack.extend(n.children().into_iter().rev());
 
             if let Node::Element(e) = n {
-                // TODO: namespaces
                 stack.extend(e.attributes().into_iter().map(Node::Attribute));
+                // Pushed last so that they come before the attributes
+                stack.extend(Namespace::in_scope(e).into_iter().map(Node::Namespace));
             }
         }
 
         DocOrder(order)
     }
 
-    fn order_of(&self, node: Node<'d>) -> usize {
+    pub(crate) fn order_of(&self, node: Node<'d>) -> usize {
         // See the library-level docs for rationale on this MAX
         self.0.get(&node).cloned().unwrap_or(usize::MAX)
     }
+
+    /// Whether the node is part of the document's tree. Nodes of
+    /// other documents, or not yet added to this one, are not.
+    pub(crate) fn contains(&self, node: Node<'d>) -> bool {
+        self.0.contains_key(&node)
+    }
+
+    /// Compares two nodes by their position in document order.
+    pub(crate) fn compare(&self, a: Node<'d>, b: Node<'d>) -> Ordering {
+        self.order_of(a).cmp(&self.order_of(b))
+    }
 }
 
 impl<'a, 'd: 'a> IntoIterator for &'a Nodeset<'d> {
@@ -527,11 +581,12 @@ impl<'d> FromIterator<Node<'d>> for OrderedNodes<'d> {
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
+    use std::cmp::Ordering;
 
     use sxd_document::Package;
 
     use super::Node::*;
-    use super::{Node, Nodeset};
+    use super::{DocOrder, Node, Nodeset};
 
     fn into_node<'d, T: Into<Node<'d>>>(n: T) -> Node<'d> {
         n.into()
@@ -621,6 +676,47 @@ mod test {
         assert_eq!(Some(attr.into()), nodes.document_order_first());
     }
 
+    #[test]
+    fn namespaces_come_before_attributes_in_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let parent = doc.create_element("parent");
+        parent.register_prefix("p", "uri");
+        let attr = parent.set_attribute_value("a", "v");
+        doc.root().append_child(parent);
+
+        let ns = Namespace(super::Namespace {
+            parent,
+            prefix: "p",
+            uri: "uri",
+        });
+        let nodes = nodeset![attr, ns];
+
+        assert_eq!(vec![ns, attr.into()], nodes.document_order());
+    }
+
+    #[test]
+    fn nodes_in_sibling_subtrees_are_compared_in_document_order() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let top = doc.create_element("top");
+        let left = doc.create_element("left");
+        let deep = doc.create_element("deep");
+        let right = doc.create_element("right");
+        doc.root().append_child(top);
+        top.append_child(left);
+        left.append_child(deep);
+        top.append_child(right);
+
+        let order = DocOrder::new(doc);
+
+        assert_eq!(Ordering::Less, order.compare(deep.into(), right.into()));
+        assert_eq!(Ordering::Greater, order.compare(right.into(), left.into()));
+        assert_eq!(Ordering::Equal, order.compare(top.into(), top.into()));
+    }
+
     #[test]
     fn prefixed_name_of_element_with_preferred_prefix() {
         let package = Package::new();
@@ -715,6 +811,24 @@ mod test {
         assert_eq!("Presenting: Earth!", into_node(element).string_value());
     }
 
+    #[test]
+    fn string_value_of_element_node_skips_other_descendants() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("hello");
+        element.set_attribute_value("ignored", "attribute");
+        element.append_child(doc.create_text("Hello"));
+        element.append_child(doc.create_comment("comment"));
+        element.append_child(doc.create_processing_instruction("pi", Some("value")));
+        let child = doc.create_element("world");
+        child.append_child(doc.create_comment("nested comment"));
+        child.append_child(doc.create_text(" World"));
+        element.append_child(child);
+
+        assert_eq!("Hello World", into_node(element).string_value());
+    }
+
     #[test]
     fn string_value_of_attribute_node_is_value() {
         let package = Package::new();
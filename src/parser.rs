@@ -1,19 +1,53 @@
 use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::cmp;
+use std::collections::HashMap;
 use std::iter::Peekable;
 
 use crate::axis::{Axis, AxisLike, PrincipalNodeType};
+use crate::context::Functions;
 use crate::expression::{self, SubExpression};
+use crate::function::Arity;
 use crate::node_test::{self, SubNodeTest};
 use crate::token::{AxisName, NodeTestName, Token};
 use crate::tokenizer::{self, TokenResult};
-use crate::Value;
+use crate::{OwnedPrefixedName, OwnedQName, Value};
 
-#[allow(missing_copy_implementations)]
-pub struct Parser;
+/// How deeply expressions may nest unless the factory says otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 40;
+
+/// How many operators in a chain such as `1 + 2 + 3` count as one
+/// level of nesting. Evaluating a chain recurses once per operator,
+/// but each operator takes far less of the stack than a nested
+/// expression, which is parsed from the top of the grammar again.
+const LINKS_PER_LEVEL: usize = 4;
+
+pub struct Parser {
+    signatures: Option<Signatures>,
+    max_depth: usize,
+}
+
+/// The functions that calls are checked against while parsing.
+#[derive(Default)]
+pub struct Signatures {
+    pub functions: Functions,
+    pub namespaces: HashMap<String, String>,
+    pub strict: bool,
+}
 
 impl Parser {
     pub fn new() -> Parser {
-        Parser
+        Parser {
+            signatures: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn signatures_mut(&mut self) -> &mut Signatures {
+        self.signatures.get_or_insert_with(Default::default)
     }
 }
 
@@ -24,10 +58,14 @@ pub enum Error {
     NoXPath,
     /// empty predicate
     EmptyPredicate,
-    /// extra unparsed tokens
+    /// trailing tokens after a complete expression
     ExtraUnparsedTokens,
     /// ran out of input
     RanOutOfInput,
+    /// ran out of input before a closing bracket
+    UnclosedBracket,
+    /// ran out of input before a closing parenthesis
+    UnclosedParenthesis,
     /// right hand side of expression is missing
     RightHandSideExpressionMissing,
     /// function argument is missing
@@ -38,6 +76,21 @@ pub enum Error {
     TrailingSlash,
     #[snafu(display("unexpected token: {:?}", token))]
     UnexpectedToken { token: Token },
+    #[snafu(display(
+        "function {:?} takes {} arguments but was given {}",
+        name,
+        expected,
+        actual
+    ))]
+    WrongNumberOfArguments {
+        name: OwnedPrefixedName,
+        expected: Arity,
+        actual: usize,
+    },
+    #[snafu(display("unknown function {:?}", name))]
+    UnknownFunction { name: OwnedPrefixedName },
+    #[snafu(display("expression nests more than {} levels deep", limit))]
+    TooDeep { limit: usize },
 }
 
 pub type ParseResult<T = Option<SubExpression>> = Result<T, Error>;
@@ -53,15 +106,98 @@ struct LeftAssociativeBinaryParser {
     rules: Vec<BinaryRule>,
 }
 
-type TokenSource<'a, I> = &'a mut Peekable<I>;
+/// The tokens being parsed, along with how deeply the expression
+/// built from them nests. Deep expressions are rejected while parsing
+/// because parsing, evaluating and dropping them all recurse once per
+/// level. Depths are counted in links of a chain, so a level of
+/// nesting is `LINKS_PER_LEVEL` deep.
+struct TokenStream<I>
+where
+    I: Iterator,
+{
+    tokens: Peekable<I>,
+    depth: usize,
+    deepest: usize,
+    max_depth: usize,
+}
+
+impl<I> TokenStream<I>
+where
+    I: Iterator<Item = TokenResult>,
+{
+    fn new(tokens: I, max_depth: usize) -> Self {
+        TokenStream {
+            tokens: tokens.peekable(),
+            depth: 0,
+            deepest: 0,
+            max_depth,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&TokenResult> {
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<TokenResult> {
+        self.tokens.next()
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), Error> {
+        let limit = self.max_depth;
+        ensure!(
+            depth <= limit.saturating_mul(LINKS_PER_LEVEL),
+            TooDeep { limit }
+        );
+        Ok(())
+    }
+
+    /// Parses something one level deeper than the current expression.
+    fn nested<T, F>(&mut self, parse: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        self.depth += LINKS_PER_LEVEL;
+        self.deepest = cmp::max(self.deepest, self.depth);
+        self.check_depth(self.depth)?;
+
+        let result = parse(self);
+
+        self.depth -= LINKS_PER_LEVEL;
+        result
+    }
+
+    /// Starts a chain of expressions that each wrap the previous one,
+    /// such as `a or b or c`. Returns what `end_chain` needs to
+    /// restore.
+    fn start_chain(&mut self) -> usize {
+        let outer = self.deepest;
+        self.deepest = self.depth;
+        outer
+    }
+
+    /// Ensures that a chain `links` long, whose operands reached
+    /// `deepest`, is not too deep.
+    fn check_chain(&self, links: usize) -> Result<(), Error> {
+        self.check_depth(self.deepest + links)
+    }
+
+    fn end_chain(&mut self, outer: usize, links: usize) -> Result<(), Error> {
+        self.check_chain(links)?;
+        self.deepest = cmp::max(outer, self.deepest + links);
+        Ok(())
+    }
+}
+
+type TokenSource<'a, I> = &'a mut TokenStream<I>;
 
 trait XCompat {
     fn has_more_tokens(&mut self) -> bool;
     fn next_token_is(&mut self, token: &Token) -> bool;
     fn consume(&mut self, token: &Token) -> Result<(), Error>;
+    fn consume_closing(&mut self, token: &Token, unclosed: Error) -> Result<(), Error>;
 }
 
-impl<I> XCompat for Peekable<I>
+impl<I> XCompat for TokenStream<I>
 where
     I: Iterator<Item = TokenResult>,
 {
@@ -71,7 +207,7 @@ where
 
     fn next_token_is(&mut self, token: &Token) -> bool {
         match self.peek() {
-            Some(&Ok(ref t)) => t == token,
+            Some(Ok(t)) => t == token,
             _ => false,
         }
     }
@@ -81,6 +217,14 @@ where
         ensure!(&x == token, UnexpectedToken { token: x });
         Ok(())
     }
+
+    fn consume_closing(&mut self, token: &Token, unclosed: Error) -> Result<(), Error> {
+        if self.has_more_tokens() {
+            self.consume(token)
+        } else {
+            Err(unclosed)
+        }
+    }
 }
 
 /// Similar to `consume`, but can be used when the token carries a
@@ -117,12 +261,14 @@ impl LeftAssociativeBinaryParser {
         F: Fn(TokenSource<'_, I>) -> ParseResult,
         I: Iterator<Item = TokenResult>,
     {
+        let outer = source.start_chain();
         let left = child_parse(source)?;
 
         let mut left = match left {
             None => return Ok(None),
             Some(x) => x,
         };
+        let mut links = 0;
 
         while source.has_more_tokens() {
             let mut found = false;
@@ -133,6 +279,8 @@ impl LeftAssociativeBinaryParser {
 
                     let right = child_parse(source)?.context(RightHandSideExpressionMissing)?;
 
+                    links += 1;
+                    source.check_chain(links)?;
                     left = (rule.builder)(left, right);
 
                     found = true;
@@ -145,10 +293,62 @@ impl LeftAssociativeBinaryParser {
             }
         }
 
+        source.end_chain(outer, links)?;
         Ok(Some(left))
     }
 }
 
+/// Parses a chain of an associative operator, such as `a or b or c`,
+/// into a balanced tree. Operands are still evaluated from left to
+/// right, but the tree is only logarithmically deep in the length of
+/// the chain.
+struct AssociativeBinaryParser {
+    rule: BinaryRule,
+}
+
+impl AssociativeBinaryParser {
+    fn new(rule: BinaryRule) -> AssociativeBinaryParser {
+        AssociativeBinaryParser { rule }
+    }
+
+    fn parse<F, I>(&self, source: TokenSource<'_, I>, child_parse: F) -> ParseResult
+    where
+        F: Fn(TokenSource<'_, I>) -> ParseResult,
+        I: Iterator<Item = TokenResult>,
+    {
+        let outer = source.start_chain();
+
+        let mut operands = match child_parse(source)? {
+            None => return Ok(None),
+            Some(x) => vec![x],
+        };
+
+        while source.next_token_is(&self.rule.token) {
+            source.consume(&self.rule.token)?;
+
+            let right = child_parse(source)?.context(RightHandSideExpressionMissing)?;
+            operands.push(right);
+        }
+
+        let mut links = 0;
+        while 1 << links < operands.len() {
+            links += 1;
+        }
+        source.end_chain(outer, links)?;
+
+        Ok(Some(self.balance(operands)))
+    }
+
+    fn balance(&self, mut operands: Vec<SubExpression>) -> SubExpression {
+        if operands.len() == 1 {
+            return operands.pop().expect("a chain has at least one operand");
+        }
+
+        let right = operands.split_off(operands.len() / 2);
+        (self.rule.builder)(self.balance(operands), self.balance(right))
+    }
+}
+
 type Rule<'a, I> = dyn Fn(TokenSource<'_, I>) -> ParseResult + 'a;
 fn first_matching_rule<I>(child_parses: &[&Rule<'_, I>], source: TokenSource<'_, I>) -> ParseResult
 where
@@ -242,7 +442,7 @@ impl Parser {
         if source.next_token_is(&Token::LeftParen) {
             source.consume(&Token::LeftParen)?;
             let result = self.parse_expression(source)?;
-            source.consume(&Token::RightParen)?;
+            source.consume_closing(&Token::RightParen, Error::UnclosedParenthesis)?;
             Ok(result)
         } else {
             Ok(None)
@@ -324,6 +524,42 @@ impl Parser {
         self.parse_function_args_tail(source, arguments)
     }
 
+    /// Compares a call against the registered signatures, if there
+    /// are any. Calls whose prefix is not bound are left to fail when
+    /// they are evaluated.
+    fn check_function_call(&self, name: &OwnedPrefixedName, actual: usize) -> Result<(), Error> {
+        let signatures = match self.signatures {
+            Some(ref signatures) => signatures,
+            None => return Ok(()),
+        };
+
+        let namespace_uri = match name.prefix {
+            None => None,
+            Some(ref prefix) => match signatures.namespaces.get(prefix) {
+                Some(uri) => Some(uri.clone()),
+                None => return Ok(()),
+            },
+        };
+        let qname = OwnedQName {
+            namespace_uri,
+            local_part: name.local_part.clone(),
+        };
+
+        match signatures.functions.get(&qname) {
+            Some(function) => match function.arity() {
+                Some(expected) if !expected.accepts(actual) => WrongNumberOfArguments {
+                    name: name.clone(),
+                    expected,
+                    actual,
+                }
+                .fail(),
+                _ => Ok(()),
+            },
+            None if signatures.strict => UnknownFunction { name: name.clone() }.fail(),
+            None => Ok(()),
+        }
+    }
+
     fn parse_function_call<I>(&self, source: TokenSource<'_, I>) -> ParseResult
     where
         I: Iterator<Item = TokenResult>,
@@ -333,7 +569,9 @@ impl Parser {
 
             source.consume(&Token::LeftParen)?;
             let arguments = self.parse_function_args(source)?;
-            source.consume(&Token::RightParen)?;
+            source.consume_closing(&Token::RightParen, Error::UnclosedParenthesis)?;
+
+            self.check_function_call(&name, arguments.len())?;
 
             Ok(Some(Box::new(expression::Function { name, arguments })))
         } else {
@@ -364,7 +602,7 @@ impl Parser {
             source.consume(&Token::LeftBracket)?;
 
             let predicate = self.parse_expression(source)?.context(EmptyPredicate)?;
-            source.consume(&Token::RightBracket)?;
+            source.consume_closing(&Token::RightBracket, Error::UnclosedBracket)?;
             Ok(Some(predicate))
         } else {
             Ok(None)
@@ -471,9 +709,11 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
+        let outer = source.start_chain();
         match self.parse_primary_expression(source)? {
             Some(expr) => {
                 let predicates = self.parse_predicates(source)?;
+                source.end_chain(outer, predicates.len())?;
 
                 Ok(Some(predicates.into_iter().fold(expr, |expr, pred| {
                     expression::Filter::new(expr, pred)
@@ -513,12 +753,10 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![BinaryRule {
+        let parser = AssociativeBinaryParser::new(BinaryRule {
             token: Token::Pipe,
             builder: expression::Union::new,
-        }];
-
-        let parser = LeftAssociativeBinaryParser::new(rules);
+        });
         parser.parse(source, |source| self.parse_path_expression(source))
     }
 
@@ -534,8 +772,8 @@ impl Parser {
         if source.next_token_is(&Token::MinusSign) {
             source.consume(&Token::MinusSign)?;
 
-            let expression = self
-                .parse_unary_expression(source)?
+            let expression = source
+                .nested(|source| self.parse_unary_expression(source))?
                 .context(RightHandSideExpressionMissing)?;
             let expression: SubExpression = Box::new(expression::Negation { expression });
             Ok(Some(expression))
@@ -638,12 +876,10 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![BinaryRule {
+        let parser = AssociativeBinaryParser::new(BinaryRule {
             token: Token::And,
             builder: expression::And::new,
-        }];
-
-        let parser = LeftAssociativeBinaryParser::new(rules);
+        });
         parser.parse(source, |source| self.parse_equality_expression(source))
     }
 
@@ -651,12 +887,10 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        let rules = vec![BinaryRule {
+        let parser = AssociativeBinaryParser::new(BinaryRule {
             token: Token::Or,
             builder: expression::Or::new,
-        }];
-
-        let parser = LeftAssociativeBinaryParser::new(rules);
+        });
         parser.parse(source, |source| self.parse_and_expression(source))
     }
 
@@ -664,14 +898,14 @@ impl Parser {
     where
         I: Iterator<Item = TokenResult>,
     {
-        self.parse_or_expression(source)
+        source.nested(|source| self.parse_or_expression(source))
     }
 
     pub fn parse<I>(&self, source: I) -> ParseResult<SubExpression>
     where
         I: Iterator<Item = TokenResult>,
     {
-        let mut source = source.peekable();
+        let mut source = TokenStream::new(source, self.max_depth);
 
         let expr = self.parse_or_expression(&mut source)?;
 
@@ -703,10 +937,8 @@ mod test {
 
     macro_rules! tokens(
         ($($e:expr),*) => ({
-            // leading _ to allow empty construction without a warning.
-            let mut _temp: Vec<TokenResult> = ::std::vec::Vec::new();
-            $(_temp.push(Ok($e));)*
-            _temp
+            let temp: Vec<TokenResult> = vec![$(Ok($e)),*];
+            temp
         });
         ($($e:expr),+,) => (tokens!($($e),+))
     );
@@ -731,7 +963,7 @@ mod test {
     impl<'d> ApproxEq for Value<'d> {
         fn is_approx_eq(&self, other: &Value<'d>) -> bool {
             match (self, other) {
-                (&Number(ref x), &Number(ref y)) => x.is_approx_eq(y),
+                (Number(x), Number(y)) => x.is_approx_eq(y),
                 _ => panic!("It's nonsensical to compare these quantities"),
             }
         }
@@ -749,12 +981,12 @@ mod test {
 
     impl<'d> TestDoc<'d> {
         fn root(&'d self) -> Root<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
             doc.root()
         }
 
         fn top_node(&'d self) -> Element<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let kids = doc.root().children();
             match kids.len() {
@@ -773,7 +1005,7 @@ mod test {
         }
 
         fn add_child(&'d self, parent: Element<'d>, name: &str) -> Element<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let n = doc.create_element(name);
             parent.append_child(n);
@@ -781,7 +1013,7 @@ mod test {
         }
 
         fn add_text(&'d self, parent: Element<'d>, value: &str) -> Text<'d> {
-            let &TestDoc(ref doc) = self;
+            let TestDoc(doc) = self;
 
             let tn = doc.create_text(value);
             parent.append_child(tn);
@@ -1789,6 +2021,112 @@ mod test {
         assert_eq!(Some(Error::RanOutOfInput), res.err());
     }
 
+    #[test]
+    fn unclosed_predicate_is_reported_as_an_error() {
+        let tokens = tokens![name_test("a"), Token::LeftBracket, Token::Number(1.0)];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+        assert_eq!(Some(Error::UnclosedBracket), res.err());
+    }
+
+    #[test]
+    fn unclosed_parenthesis_is_reported_as_an_error() {
+        let tokens = tokens![Token::LeftParen, Token::Number(1.0)];
+
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        let res = ex.parse_raw(tokens);
+        assert_eq!(Some(Error::UnclosedParenthesis), res.err());
+
+        let tokens = tokens![
+            Token::Function("f".into()),
+            Token::LeftParen,
+            Token::Number(1.0)
+        ];
+        let res = ex.parse_raw(tokens);
+        assert_eq!(Some(Error::UnclosedParenthesis), res.err());
+    }
+
+    #[test]
+    fn nesting_deeper_than_the_limit_is_reported_as_an_error() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let mut ex = Exercise::new(&doc);
+
+        let nested = |depth| {
+            let mut tokens = vec![Token::LeftParen; depth];
+            tokens.push(Token::Number(1.0));
+            tokens.extend(vec![Token::RightParen; depth]);
+            tokens.into_iter().map(Ok).collect()
+        };
+
+        assert!(ex.parse_raw(nested(DEFAULT_MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Some(Error::TooDeep {
+                limit: DEFAULT_MAX_DEPTH
+            }),
+            ex.parse_raw(nested(DEFAULT_MAX_DEPTH + 1)).err()
+        );
+
+        ex.parser.set_max_depth(3);
+        assert!(ex.parse_raw(nested(3)).is_ok());
+        assert_eq!(
+            Some(Error::TooDeep { limit: 3 }),
+            ex.parse_raw(nested(4)).err()
+        );
+    }
+
+    #[test]
+    fn operators_in_a_chain_count_as_part_of_a_level() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let mut ex = Exercise::new(&doc);
+        ex.parser.set_max_depth(1);
+
+        let chain = |links| {
+            let mut tokens = vec![Ok(Token::Number(1.0))];
+            for _ in 0..links {
+                tokens.push(Ok(Token::PlusSign));
+                tokens.push(Ok(Token::Number(1.0)));
+            }
+            tokens
+        };
+
+        assert!(ex.parse_raw(chain(LINKS_PER_LEVEL)).is_ok());
+        assert_eq!(
+            Some(Error::TooDeep { limit: 1 }),
+            ex.parse_raw(chain(LINKS_PER_LEVEL + 1)).err()
+        );
+    }
+
+    #[test]
+    fn long_or_chains_are_parsed_into_balanced_trees() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let mut ex = Exercise::new(&doc);
+        ex.parser.set_max_depth(3);
+
+        let mut tokens = vec![Ok(Token::Number(0.0))];
+        for _ in 0..6 {
+            tokens.push(Ok(Token::Or));
+            tokens.push(Ok(Token::Number(0.0)));
+        }
+        tokens.push(Ok(Token::Or));
+        tokens.push(Ok(Token::Number(1.0)));
+
+        let expr = ex.parse(tokens);
+        assert_eq!(Value::Boolean(true), ex.evaluate(expr));
+    }
+
     #[test]
     fn having_extra_tokens_is_reported_as_an_error() {
         let tokens = tokens![Token::LeftBracket];
@@ -1821,4 +2159,101 @@ mod test {
             res.err()
         );
     }
+
+    fn function_call(name: &str, argument_count: usize) -> Vec<TokenResult> {
+        let mut tokens = tokens![Token::Function(name.into()), Token::LeftParen];
+        for i in 0..argument_count {
+            if i > 0 {
+                tokens.push(Ok(Token::Comma));
+            }
+            tokens.push(Ok(Token::Number(i as f64)));
+        }
+        tokens.push(Ok(Token::RightParen));
+        tokens
+    }
+
+    fn checking_exercise<'d>(doc: &'d TestDoc<'d>, strict: bool) -> Exercise<'d> {
+        let mut ex = Exercise::new(doc);
+        let signatures = ex.parser.signatures_mut();
+        signatures.functions = ex.context.functions().clone();
+        signatures.strict = strict;
+        ex
+    }
+
+    #[test]
+    fn too_few_arguments_are_reported_when_checking_functions() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = checking_exercise(&doc, false);
+        let res = ex.parse_raw(function_call("contains", 1));
+        assert_eq!(
+            Some(Error::WrongNumberOfArguments {
+                name: "contains".into(),
+                expected: Arity::exactly(2),
+                actual: 1,
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn too_many_arguments_are_reported_when_checking_functions() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = checking_exercise(&doc, false);
+        let res = ex.parse_raw(function_call("substring", 4));
+        assert_eq!(
+            Some(Error::WrongNumberOfArguments {
+                name: "substring".into(),
+                expected: Arity::between(2, 3),
+                actual: 4,
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn arguments_within_the_arity_are_accepted_when_checking_functions() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = checking_exercise(&doc, true);
+        assert!(ex.parse_raw(function_call("concat", 5)).is_ok());
+        assert!(ex.parse_raw(function_call("string", 0)).is_ok());
+    }
+
+    #[test]
+    fn unknown_functions_are_allowed_when_not_strict() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = checking_exercise(&doc, false);
+        assert!(ex.parse_raw(function_call("mystery", 7)).is_ok());
+    }
+
+    #[test]
+    fn unknown_functions_are_reported_when_strict() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = checking_exercise(&doc, true);
+        let res = ex.parse_raw(function_call("mystery", 0));
+        assert_eq!(
+            Some(Error::UnknownFunction {
+                name: "mystery".into()
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn functions_are_not_checked_by_default() {
+        let package = Package::new();
+        let doc = TestDoc(package.as_document());
+
+        let ex = Exercise::new(&doc);
+        assert!(ex.parse_raw(function_call("contains", 1)).is_ok());
+    }
 }
@@ -2,6 +2,7 @@ use peresil::{self, try_parse, Identifier, ParseMaster, Recoverable, StringPoint
 use snafu::Snafu;
 use std::borrow::ToOwned;
 use std::collections::VecDeque;
+use std::ops::Range;
 use std::string;
 use sxd_document::parser::XmlParseExt;
 
@@ -12,6 +13,14 @@ pub struct Tokenizer {
     xpath: string::String,
     start: usize,
     prefer_recognition_of_operator_names: bool,
+    local_name_wildcards: bool,
+    last_span: Option<Range<usize>>,
+    exhausted: bool,
+    error: Option<Error>,
+}
+
+fn is_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
 }
 
 type XPathMaster<'a> = ParseMaster<StringPoint<'a>, Error>;
@@ -87,7 +96,7 @@ trait XPathParseExt<'a> {
 
 impl<'a> XPathParseExt<'a> for StringPoint<'a> {
     fn consume_quoted_string(&self, quote: &str) -> XPathProgress<'a, &'a str, ()> {
-        let end_of_str = self.s.find(quote).or_else(|| Some(self.s.len()));
+        let end_of_str = self.s.find(quote).or(Some(self.s.len()));
         self.consume_to(end_of_str)
     }
 }
@@ -251,14 +260,21 @@ fn parse_node_type<'a>(
 ) -> XPathProgress<'a, Token, Error> {
     fn without_arg(p: StringPoint<'_>) -> XPathProgress<'_, Token, ()> {
         let (p, node_type) = try_parse!(p.consume_identifier(&NODE_TESTS));
-        let (p, _) = try_parse!(p.consume_literal("()"));
+        let (p, _) = p.consume_space().optional(p);
+        let (p, _) = try_parse!(p.consume_literal("("));
+        let (p, _) = p.consume_space().optional(p);
+        let (p, _) = try_parse!(p.consume_literal(")"));
 
         peresil::Progress::success(p, Token::NodeTest(node_type))
     }
 
     fn with_arg<'a>(pm: &mut XPathMaster<'a>, p: StringPoint<'a>) -> XPathProgress<'a, Token, ()> {
-        let (p, _) = try_parse!(p.consume_literal("processing-instruction("));
+        let (p, _) = try_parse!(p.consume_literal("processing-instruction"));
+        let (p, _) = p.consume_space().optional(p);
+        let (p, _) = try_parse!(p.consume_literal("("));
+        let (p, _) = p.consume_space().optional(p);
         let (p, arg) = try_parse!(parse_literal(pm, p).map_err(|_| ()));
+        let (p, _) = p.consume_space().optional(p);
         let (p, _) = try_parse!(p.consume_literal(")"));
 
         let name = NodeTestName::ProcessingInstruction(Some(arg.to_owned()));
@@ -283,6 +299,7 @@ fn parse_function_call(p: StringPoint<'_>) -> XPathProgress<'_, Token, Error> {
 fn parse_name_test<'a>(
     pm: &mut XPathMaster<'a>,
     p: StringPoint<'a>,
+    local_name_wildcards: bool,
 ) -> XPathProgress<'a, Token, Error> {
     fn wildcard(p: StringPoint<'_>) -> XPathProgress<'_, Token, ()> {
         let (p, wc) = try_parse!(p.consume_literal("*"));
@@ -306,6 +323,21 @@ fn parse_name_test<'a>(
         peresil::Progress::success(p, Token::NameTest(name))
     }
 
+    fn any_namespace(p: StringPoint<'_>, enabled: bool) -> XPathProgress<'_, Token, ()> {
+        if !enabled {
+            return peresil::Progress::failure(p, ());
+        }
+
+        let (p, _) = try_parse!(p.consume_literal("*:"));
+        let (p, local_part) = try_parse!(p.consume_ncname());
+
+        let name = node_test::NameTest {
+            prefix: Some("*".to_owned()),
+            local_part: local_part.to_owned(),
+        };
+        peresil::Progress::success(p, Token::NameTest(name))
+    }
+
     fn prefixed_name(p: StringPoint<'_>) -> XPathProgress<'_, Token, ()> {
         p.consume_prefixed_name().map(|name| {
             Token::NameTest(node_test::NameTest {
@@ -316,6 +348,7 @@ fn parse_name_test<'a>(
     }
 
     pm.alternate()
+        .one(|_| any_namespace(p, local_name_wildcards).context(ExpectedNameTest))
         .one(|_| wildcard(p).context(ExpectedNameTest))
         .one(|_| prefixed_wildcard(p).context(ExpectedNameTest))
         .one(|_| prefixed_name(p).context(ExpectedNameTest))
@@ -335,11 +368,44 @@ impl Tokenizer {
             xpath: xpath.to_owned(),
             start: 0,
             prefer_recognition_of_operator_names: false,
+            local_name_wildcards: false,
+            last_span: None,
+            exhausted: false,
+            error: None,
         }
     }
 
+    /// Accepts the `*:local` name test, which matches a local name in
+    /// any namespace.
+    pub fn set_local_name_wildcards(&mut self, enabled: bool) {
+        self.local_name_wildcards = enabled;
+    }
+
     pub fn has_more_tokens(&self) -> bool {
-        self.xpath.len() > self.start
+        self.xpath.len() > self.offset()
+    }
+
+    /// The byte range of the most recently produced token.
+    pub fn last_token_span(&self) -> Option<Range<usize>> {
+        self.last_span.clone()
+    }
+
+    /// Whether the end of the input has been reported.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// The error that stopped tokenizing, if any.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// The byte offset of the next token, after any whitespace. After
+    /// an error, this is where the text that could not be tokenized
+    /// starts.
+    pub fn offset(&self) -> usize {
+        let rest = &self.xpath[self.start..];
+        self.xpath.len() - rest.trim_start_matches(is_space).len()
     }
 
     fn parse_token<'a>(
@@ -366,7 +432,7 @@ impl Tokenizer {
                 .one(|_| parse_axis_specifier(p))
                 .one(|pm| parse_node_type(pm, p))
                 .one(|_| parse_function_call(p))
-                .one(|pm| parse_name_test(pm, p))
+                .one(|pm| parse_name_test(pm, p, self.local_name_wildcards))
                 .one(|_| parse_variable_reference(p))
                 .finish()
         });
@@ -389,6 +455,8 @@ impl Tokenizer {
                 status: peresil::Status::Success(data),
                 point,
             } => {
+                let end = self.xpath[..point.offset].trim_end_matches(is_space).len();
+                self.last_span = Some(self.offset()..end);
                 self.start = point.offset;
                 Ok(data)
             }
@@ -407,7 +475,10 @@ impl Tokenizer {
     }
 
     fn next_token(&mut self) -> TokenResult {
-        let token = self.raw_next_token()?;
+        let token = self.raw_next_token().map_err(|e| {
+            self.error = Some(e);
+            e
+        })?;
 
         if !(token.precedes_node_test() || token.precedes_expression() || token.is_operator()) {
             // See http://www.w3.org/TR/xpath/#exprlex
@@ -427,6 +498,7 @@ impl Iterator for Tokenizer {
         if self.has_more_tokens() {
             Some(self.next_token())
         } else {
+            self.exhausted = true;
             None
         }
     }
@@ -543,6 +615,12 @@ mod test {
         assert!(is_finished(&tokenizer));
     }
 
+    #[test]
+    fn whitespace_only_string_has_no_tokens() {
+        let tokenizer = Tokenizer::new(" \t\n ");
+        assert!(is_finished(&tokenizer));
+    }
+
     #[test]
     fn tokenizes_simple_string() {
         let tokenizer = Tokenizer::new("hello");
@@ -604,6 +682,66 @@ mod test {
         assert_eq!(all_tokens(tokenizer), vec![name_test("*")]);
     }
 
+    #[test]
+    fn tokenizes_prefixed_wildcard_name_test() {
+        let tokenizer = Tokenizer::new("ns:*");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::NameTest(node_test::NameTest {
+                prefix: Some("ns".to_owned()),
+                local_part: "*".to_owned(),
+            })]
+        );
+    }
+
+    #[test]
+    fn tokenizes_any_namespace_name_test_when_enabled() {
+        let mut tokenizer = Tokenizer::new("*:item");
+        tokenizer.set_local_name_wildcards(true);
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::NameTest(node_test::NameTest {
+                prefix: Some("*".to_owned()),
+                local_part: "item".to_owned(),
+            })]
+        );
+    }
+
+    #[test]
+    fn rejects_any_namespace_name_test_by_default() {
+        let tokenizer = Tokenizer::new("*:item");
+
+        assert!(all_tokens_raw(tokenizer).is_err());
+    }
+
+    #[test]
+    fn records_the_span_of_the_last_token() {
+        let mut tokenizer = Tokenizer::new("  foo   = 'a b' ");
+
+        tokenizer.next();
+        assert_eq!(Some(2..5), tokenizer.last_token_span());
+        tokenizer.next();
+        assert_eq!(Some(8..9), tokenizer.last_token_span());
+        tokenizer.next();
+        assert_eq!(Some(10..15), tokenizer.last_token_span());
+        assert!(!tokenizer.is_exhausted());
+        assert_eq!(None, tokenizer.next());
+        assert!(tokenizer.is_exhausted());
+    }
+
+    #[test]
+    fn records_where_tokenizing_failed() {
+        let mut tokenizer = Tokenizer::new("a = #");
+
+        tokenizer.next();
+        tokenizer.next();
+        assert_eq!(Some(Err(UnableToCreateToken)), tokenizer.next());
+        assert_eq!(Some(UnableToCreateToken), tokenizer.error());
+        assert_eq!(4, tokenizer.offset());
+    }
+
     #[test]
     fn tokenizes_axis_selector() {
         let tokenizer = Tokenizer::new("ancestor::world");
@@ -704,6 +842,30 @@ mod test {
         assert_eq!(all_tokens(tokenizer), vec![Token::Number(0.40)]);
     }
 
+    #[test]
+    fn tokenizes_decimal_number_without_fractional_part() {
+        let tokenizer = Tokenizer::new("5.");
+
+        assert_eq!(all_tokens(tokenizer), vec![Token::Number(5.0)]);
+    }
+
+    #[test]
+    fn tokenizes_integral_number_with_leading_zeroes() {
+        let tokenizer = Tokenizer::new("007");
+
+        assert_eq!(all_tokens(tokenizer), vec![Token::Number(7.0)]);
+    }
+
+    #[test]
+    fn tokenizes_current_node_followed_by_a_number() {
+        let tokenizer = Tokenizer::new(". = .5");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::CurrentNode, Token::Equal, Token::Number(0.5)]
+        );
+    }
+
     #[test]
     fn tokenizes_left_bracket() {
         let tokenizer = Tokenizer::new("[");
@@ -738,6 +900,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn tokenizes_literals_containing_the_other_quote() {
+        let tokenizer = Tokenizer::new("\"it's\" 'say \"hi\"'");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Literal("it's".to_owned()),
+                Token::Literal("say \"hi\"".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_empty_literals() {
+        let tokenizer = Tokenizer::new("''\"\"");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::Literal("".to_owned()), Token::Literal("".to_owned())]
+        );
+    }
+
+    #[test]
+    fn tokenizes_literal_containing_newlines() {
+        let tokenizer = Tokenizer::new("'one\ntwo\r\n'");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::Literal("one\ntwo\r\n".to_owned())]
+        );
+    }
+
     #[test]
     fn tokenizes_variable_reference() {
         let tokenizer = Tokenizer::new("$yo");
@@ -755,6 +950,46 @@ mod test {
         );
     }
 
+    #[test]
+    fn tokenizes_names_with_non_ascii_characters() {
+        let tokenizer = Tokenizer::new("données/名前/e\u{301}t\u{e9}·2");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                name_test("données"),
+                Token::Slash,
+                name_test("名前"),
+                Token::Slash,
+                name_test("e\u{301}t\u{e9}·2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_variables_and_functions_with_non_ascii_names() {
+        let tokenizer = Tokenizer::new("$変数 = préfixe:fonction($ns:änder)");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::Variable("変数".into()),
+                Token::Equal,
+                Token::Function(("préfixe", "fonction").into()),
+                Token::LeftParen,
+                Token::Variable(("ns", "änder").into()),
+                Token::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn names_cannot_start_with_combining_characters() {
+        let tokenizer = Tokenizer::new("$\u{301}a");
+
+        assert!(all_tokens_raw(tokenizer).is_err());
+    }
+
     #[test]
     fn tokenizes_plus_sign() {
         let tokenizer = Tokenizer::new("+");
@@ -868,6 +1103,57 @@ mod test {
         );
     }
 
+    #[test]
+    fn operator_names_are_name_tests_where_an_operator_is_not_allowed() {
+        let tokenizer = Tokenizer::new("div div div");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![name_test("div"), Token::Divide, name_test("div")]
+        );
+    }
+
+    #[test]
+    fn operator_names_are_name_tests_after_a_slash_or_axis() {
+        let tokenizer = Tokenizer::new("//and/child::mod");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::DoubleSlash,
+                name_test("and"),
+                Token::Slash,
+                Token::Axis(AxisName::Child),
+                name_test("mod")
+            ]
+        );
+    }
+
+    #[test]
+    fn asterisk_between_wildcards_is_multiplication() {
+        let tokenizer = Tokenizer::new("* * *");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![name_test("*"), Token::Multiply, name_test("*")]
+        );
+    }
+
+    #[test]
+    fn asterisk_after_attribute_wildcard_is_multiplication() {
+        let tokenizer = Tokenizer::new("@* * 2");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![
+                Token::AtSign,
+                name_test("*"),
+                Token::Multiply,
+                Token::Number(2.0)
+            ]
+        );
+    }
+
     #[test]
     fn tokenizes_node_test_without_args() {
         let tokenizer = Tokenizer::new("text()");
@@ -900,6 +1186,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn tokenizes_processing_instruction_node_test_with_spaced_args() {
+        let tokenizer = Tokenizer::new("processing-instruction ( \"hi\" )");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::NodeTest(NodeTestName::ProcessingInstruction(Some(
+                "hi".to_owned()
+            )))]
+        );
+    }
+
+    #[test]
+    fn tokenizes_node_type_with_spaced_parens() {
+        let tokenizer = Tokenizer::new("comment ( )");
+
+        assert_eq!(
+            all_tokens(tokenizer),
+            vec![Token::NodeTest(NodeTestName::Comment)]
+        );
+    }
+
     #[test]
     fn tokenizes_function_call() {
         let tokenizer = Tokenizer::new("hello()");
@@ -1,7 +1,12 @@
 use std::borrow::ToOwned;
-use sxd_document::{dom, parser};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use sxd_document::{dom, parser, Package};
 use sxd_xpath::{context, function, nodeset};
-use sxd_xpath::{evaluate_xpath, Context, Factory, Value};
+use sxd_xpath::{evaluate_xpath, Context, Factory, ParseErrorKind, Value};
 
 #[test]
 fn functions_accept_arguments() {
@@ -23,21 +28,2640 @@ fn functions_implicitly_coerce_argument_types() {
     });
 }
 
+#[test]
+fn boolean_of_nan_is_false() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "boolean(0 div 0)");
+
+        assert_eq!(Ok(Value::Boolean(false)), result);
+    });
+}
+
+#[test]
+fn boolean_of_empty_string_is_false() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "boolean('')");
+
+        assert_eq!(Ok(Value::Boolean(false)), result);
+    });
+}
+
+#[test]
+fn boolean_of_empty_nodeset_is_false() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "boolean(//nonexistent)");
+
+        assert_eq!(Ok(Value::Boolean(false)), result);
+    });
+}
+
+#[test]
+fn boolean_of_non_empty_nodeset_is_true() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "boolean(//a)");
+
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn sum_totals_the_string_values_of_nodes() {
+    with_document(
+        "<a><price>1.5</price><price>2</price><price> 3 </price></a>",
+        |doc| {
+            let result = evaluate_xpath(&doc, "sum(//price)");
+
+            assert_eq!(Ok(Value::Number(6.5)), result);
+        },
+    );
+}
+
+#[test]
+fn round_of_nan_is_nan() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "round(0 div 0)");
+
+        match result {
+            Ok(Value::Number(n)) => assert!(n.is_nan()),
+            other => panic!("Expected NaN, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn namespace_uri_resolves_default_and_prefixed_namespaces() {
+    with_document(
+        r#"<a xmlns="urn:default" xmlns:p="urn:prefixed"><p:b p:c="1">text</p:b></a>"#,
+        |doc| {
+            let default = evaluate_xpath(&doc, "namespace-uri(/*)");
+            let prefixed = evaluate_xpath(&doc, "namespace-uri(/*/*)");
+            let attribute = evaluate_xpath(&doc, "namespace-uri(/*/*/@*)");
+            let text = evaluate_xpath(&doc, "namespace-uri(/*/*/text())");
+
+            assert_eq!(Ok(Value::String("urn:default".to_owned())), default);
+            assert_eq!(Ok(Value::String("urn:prefixed".to_owned())), prefixed);
+            assert_eq!(Ok(Value::String("urn:prefixed".to_owned())), attribute);
+            assert_eq!(Ok(Value::String("".to_owned())), text);
+        },
+    );
+}
+
+#[test]
+fn name_uses_in_scope_prefixes() {
+    with_document(
+        r#"<a xmlns="urn:default" xmlns:p="urn:prefixed"><p:b p:c="1" d="2"/></a>"#,
+        |doc| {
+            let default = evaluate_xpath(&doc, "name(/*)");
+            let prefixed = evaluate_xpath(&doc, "name(/*/*)");
+            let attribute = evaluate_xpath(&doc, "name(/*/*/@*[local-name() = 'c'])");
+            let plain_attribute = evaluate_xpath(&doc, "name(/*/*/@*[local-name() = 'd'])");
+            let empty = evaluate_xpath(&doc, "name(/nothing)");
+
+            assert_eq!(Ok(Value::String("a".to_owned())), default);
+            assert_eq!(Ok(Value::String("p:b".to_owned())), prefixed);
+            assert_eq!(Ok(Value::String("p:c".to_owned())), attribute);
+            assert_eq!(Ok(Value::String("d".to_owned())), plain_attribute);
+            assert_eq!(Ok(Value::String("".to_owned())), empty);
+        },
+    );
+}
+
+#[test]
+fn lang_uses_xml_lang_from_the_document() {
+    with_document(r#"<a xml:lang="en-GB"><b><c/></b></a>"#, |doc| {
+        let result = evaluate_xpath(&doc, "count(//c[lang('en')])");
+
+        assert_eq!(Ok(Value::Number(1.0)), result);
+    });
+}
+
+#[test]
+fn string_functions_default_to_the_context_node() {
+    with_document("<a>  hello \n world  </a>", |doc| {
+        let normalized = evaluate_xpath(&doc, "count(/a[normalize-space() = 'hello world'])");
+        let length = evaluate_xpath(&doc, "string-length(/a[string-length() > 5])");
+
+        assert_eq!(Ok(Value::Number(1.0)), normalized);
+        assert_eq!(Ok(Value::Number(17.0)), length);
+    });
+}
+
+#[test]
+fn string_arguments_are_coerced_from_nodesets() {
+    with_document("<a><title>Alpha</title><title>Beta</title></a>", |doc| {
+        let result = evaluate_xpath(&doc, "starts-with(//title, 'A')");
+
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn boolean_arguments_are_coerced_from_nodesets() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "not(//missing)");
+
+        assert_eq!(Ok(Value::Boolean(true)), result);
+    });
+}
+
+#[test]
+fn number_arguments_are_coerced_from_strings() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "floor('3.7')");
+
+        assert_eq!(Ok(Value::Number(3.0)), result);
+    });
+}
+
+#[test]
+fn count_still_requires_a_nodeset() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "count('a')");
+
+        assert!(result.is_err(), "{:?} should have failed", result);
+    });
+}
+
+#[test]
+fn document_function_loads_nodes_from_another_document() {
+    with_document("<a/>", |doc| {
+        let pool = context::DocumentPool::new();
+        let mut setup = Setup::new();
+        setup.context.set_document_resolver(&pool, |uri| {
+            assert_eq!("other.xml", uri);
+            parser::parse("<items><item>1</item><item>2</item></items>")
+        });
+
+        let result = setup.evaluate(&doc, "sum(document('other.xml')//item) + count(//a)");
+
+        assert_eq!(Value::Number(4.0), result);
+    });
+}
+
+#[test]
+fn document_function_reuses_loaded_documents() {
+    with_document("<a/>", |doc| {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+
+        let pool = context::DocumentPool::new();
+        let mut setup = Setup::new();
+        setup.context.set_document_resolver(&pool, move |_uri| {
+            counter.set(counter.get() + 1);
+            parser::parse("<item/>")
+        });
+
+        let once = setup.evaluate(&doc, "document('x.xml')");
+        let union = setup.evaluate(&doc, "document('x.xml') | document('x.xml')");
+
+        assert_eq!(once, union);
+        assert_eq!(1, calls.get());
+    });
+}
+
+#[test]
+fn document_function_without_resolver_is_an_error() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xslt_xpath(&doc, "document('other.xml')");
+
+        let message = result.expect_err("document() should fail").to_string();
+        assert!(message.contains("no document resolver"), "{}", message);
+    });
+}
+
+#[test]
+fn document_function_reports_resolver_failures() {
+    with_document("<a/>", |doc| {
+        let pool = context::DocumentPool::new();
+        let mut context = Context::new();
+        function::register_xslt_functions(&mut context);
+        context.set_document_resolver(&pool, |uri| -> Result<Package, String> {
+            Err(format!("{} does not exist", uri))
+        });
+
+        let xpath = Factory::new()
+            .build("document('missing.xml')")
+            .expect("Unable to build XPath");
+        let result = xpath.evaluate(&context, doc.root());
+
+        let message = result.expect_err("document() should fail").to_string();
+        assert!(message.contains("missing.xml"), "{}", message);
+    });
+}
+
+const LIBRARY: &str = r#"<library>
+    <book author="a1">One</book>
+    <book author="a2">Two</book>
+    <book author="a1">Three</book>
+    <author id="a1"/>
+    <author id="a2"/>
+</library>"#;
+
+fn book_by_author(setup: &mut Setup<'_>) {
+    let matching = setup
+        .factory
+        .build("//book")
+        .expect("Unable to build XPath");
+    let using = setup
+        .factory
+        .build("@author")
+        .expect("Unable to build XPath");
+    setup.context.set_key("book-by-author", matching, using);
+}
+
+#[test]
+fn key_returns_all_nodes_with_a_value() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        book_by_author(&mut setup);
+
+        let count = setup.evaluate(&doc, "count(key('book-by-author', 'a1'))");
+        let first = setup.evaluate(&doc, "string(key('book-by-author', 'a1'))");
+
+        assert_eq!(Value::Number(2.0), count);
+        assert_eq!(Value::String("One".to_owned()), first);
+    });
+}
+
+#[test]
+fn key_looks_up_each_node_of_a_nodeset() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        book_by_author(&mut setup);
+
+        let result = setup.evaluate(&doc, "count(key('book-by-author', //author/@id))");
+
+        assert_eq!(Value::Number(3.0), result);
+    });
+}
+
+#[test]
+fn key_with_unknown_value_is_empty() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        book_by_author(&mut setup);
+
+        let result = setup.evaluate(&doc, "count(key('book-by-author', 'nobody'))");
+
+        assert_eq!(Value::Number(0.0), result);
+    });
+}
+
+#[test]
+fn key_with_prefixed_name_is_resolved_through_namespaces() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        let matching = setup
+            .factory
+            .build("//author")
+            .expect("Unable to build XPath");
+        let using = setup.factory.build("@id").expect("Unable to build XPath");
+        setup
+            .context
+            .set_key(("uri:keys", "author"), matching, using);
+        setup.context.set_namespace("k", "uri:keys");
+
+        let result = setup.evaluate(&doc, "count(key('k:author', 'a2'))");
+
+        assert_eq!(Value::Number(1.0), result);
+    });
+}
+
+#[test]
+fn key_indexes_see_document_changes_once_cleared() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        book_by_author(&mut setup);
+        let count = "count(key('book-by-author', 'a1'))";
+
+        assert_eq!(Value::Number(2.0), setup.evaluate(&doc, count));
+
+        let library = doc.root().children()[0]
+            .element()
+            .expect("No element library");
+        let book = doc.create_element("book");
+        book.set_attribute_value("author", "a1");
+        library.append_child(book);
+
+        assert_eq!(Value::Number(2.0), setup.evaluate(&doc, count));
+
+        setup.context.clear_key_indexes();
+
+        assert_eq!(Value::Number(3.0), setup.evaluate(&doc, count));
+    });
+}
+
+#[test]
+fn key_that_is_not_registered_is_an_error() {
+    with_document(LIBRARY, |doc| {
+        let result = evaluate_xslt_xpath(&doc, "key('missing', 'a1')");
+
+        let message = result.expect_err("key() should fail").to_string();
+        assert!(message.contains("unknown key"), "{}", message);
+    });
+}
+
+#[test]
+fn key_that_uses_itself_is_an_error() {
+    with_document(LIBRARY, |doc| {
+        let mut setup = Setup::new();
+        let build = |xpath| setup.factory.build(xpath).expect("Unable to build XPath");
+        let (books, own) = (build("//book"), build("key('self', @author)"));
+        let (first, second) = (build("//book"), build("key('second', @author)"));
+        let (authors, ids) = (build("//author"), build("key('first', @id)"));
+        setup.context.set_key("self", books, own);
+        setup.context.set_key("first", first, second);
+        setup.context.set_key("second", authors, ids);
+
+        for xpath in &[
+            "key('self', 'a1')",
+            "key('first', 'a1')",
+            "key('self', 'a1')",
+        ] {
+            let xpath = setup.factory.build(xpath).expect("Unable to build XPath");
+            let error = xpath
+                .evaluate(&setup.context, doc.root())
+                .expect_err("recursive key should fail");
+            assert!(error.to_string().contains("its own"), "{}", error);
+        }
+
+        // Keys that do not use themselves are unaffected
+        book_by_author(&mut setup);
+        let result = setup.evaluate(&doc, "count(key('book-by-author', 'a1'))");
+        assert_eq!(Value::Number(2.0), result);
+    });
+}
+
+const REFERENCES: &str = r#"<doc>
+    <target id="one" ref="one"/>
+    <item ref="one"/>
+    <item ref="two"/>
+    <item ref="one"/>
+</doc>"#;
+
+#[test]
+fn current_refers_to_the_starting_node_inside_predicates() {
+    with_document(REFERENCES, |doc| {
+        let target = doc.root().children()[0].element().expect("No element doc");
+        let target = target.children()[1].element().expect("No element target");
+
+        let setup = Setup::new();
+        let xpath = setup
+            .factory
+            .build("count(//item[@ref = current()/@id])")
+            .expect("Unable to build XPath");
+        let result = xpath.evaluate(&setup.context, target);
+
+        assert_eq!(Ok(Value::Number(2.0)), result);
+    });
+}
+
+#[test]
+fn current_differs_from_the_context_node_inside_predicates() {
+    with_document(REFERENCES, |doc| {
+        let target = doc.root().children()[0].element().expect("No element doc");
+        let target = target.children()[1].element().expect("No element target");
+
+        let setup = Setup::new();
+        let xpath = setup
+            .factory
+            .build("count(//*[@ref = ./@id])")
+            .expect("Unable to build XPath");
+        let result = xpath.evaluate(&setup.context, target);
+
+        // Only the target refers to its own id
+        assert_eq!(Ok(Value::Number(1.0)), result);
+    });
+}
+
+#[test]
+fn generate_id_depends_only_on_document_structure() {
+    let xml = "<a><b/><b x='1'/></a>";
+    let ids = |doc: dom::Document<'_>| {
+        evaluate_xslt_xpath(&doc, "concat(generate-id(//b[2]), ' ', generate-id(//@x))")
+            .expect("Unable to evaluate XPath")
+            .string()
+    };
+
+    let first = parser::parse(xml).expect("Unable to parse test XML");
+    let second = parser::parse(xml).expect("Unable to parse test XML");
+
+    let first_ids = ids(first.as_document());
+    assert_eq!(first_ids, ids(first.as_document()));
+    assert_eq!(first_ids, ids(second.as_document()));
+}
+
+#[test]
+fn generate_id_tells_apart_nodes_of_different_documents() {
+    let xml = "<a><b/></a>";
+    let first = parser::parse(xml).expect("Unable to parse test XML");
+    let first = first.as_document();
+    let second = parser::parse(xml).expect("Unable to parse test XML");
+    let second = second.as_document();
+
+    let mut setup = Setup::new();
+    let other_b = second.root().children()[0]
+        .element()
+        .expect("No element a")
+        .children()[0];
+    setup.context.set_variable("other", nodeset![other_b]);
+
+    let same = setup.evaluate(&first, "generate-id(//b) = generate-id($other)");
+    let again = setup.evaluate(&first, "generate-id(//b)");
+
+    assert_eq!(Value::Boolean(false), same);
+    assert_eq!(again, setup.evaluate(&first, "generate-id(//b)"));
+}
+
+#[test]
+fn generate_id_numbers_the_evaluated_document_first() {
+    with_document("<a><b/></a>", |doc| {
+        let pool = context::DocumentPool::new();
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_document_resolver(&pool, |_uri| parser::parse("<other/>"));
+
+        let ids = setup.evaluate(
+            &doc,
+            "concat(generate-id(document('other.xml')/other), ' ', generate-id(//b))",
+        );
+
+        assert_eq!(Value::String("id1-0 id0-0-0".into()), ids);
+    });
+}
+
+#[test]
+fn xslt_functions_are_only_available_once_registered() {
+    with_document("<a/>", |doc| {
+        let xslt = [
+            "current()",
+            "key('k', 'v')",
+            "generate-id()",
+            "function-available('concat')",
+            "element-available('xsl:if')",
+            "system-property('xsl:version')",
+            "unparsed-entity-uri('e')",
+            "document('other.xml')",
+        ];
+
+        for xpath in &xslt {
+            let error = evaluate_xpath(&doc, xpath).expect_err("XSLT function in core context");
+            assert!(error.to_string().contains("unknown function"), "{}", error);
+        }
+
+        let functions = function::FunctionLibrary::new().with_core().build();
+        assert!(!functions.contains_key(&"generate-id".into()));
+        let functions = function::FunctionLibrary::new().with_xslt().build();
+        assert!(functions.contains_key(&"generate-id".into()));
+        assert!(!functions.contains_key(&"concat".into()));
+    });
+}
+
+#[test]
+fn function_available_reports_registered_functions() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_function(("uri:namespace", "constant"), ConstantValueFunction(42.0));
+        setup.context.set_namespace("prefix", "uri:namespace");
+
+        let builtin = setup.evaluate(&doc, "function-available('concat')");
+        let custom = setup.evaluate(&doc, "function-available('prefix:constant')");
+        let unknown = setup.evaluate(&doc, "function-available('no-such-function')");
+
+        assert_eq!(Value::Boolean(true), builtin);
+        assert_eq!(Value::Boolean(true), custom);
+        assert_eq!(Value::Boolean(false), unknown);
+    });
+}
+
+#[test]
+fn system_property_reports_built_in_properties() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_namespace("xsl", "http://www.w3.org/1999/XSL/Transform");
+
+        let version = setup.evaluate(&doc, "system-property('xsl:version')");
+        let vendor = setup.evaluate(&doc, "system-property('xsl:vendor')");
+
+        assert_eq!(Value::Number(1.0), version);
+        assert_eq!(Value::String("sxd-xpath".to_owned()), vendor);
+    });
+}
+
+#[cfg(feature = "regexp")]
+#[test]
+fn exslt_regexp_functions_use_the_conventional_prefix() {
+    with_document("<a>Hello</a>", |doc| {
+        let mut setup = Setup::new();
+        function::exslt::register_exslt_regexp(&mut setup.context);
+
+        let result = setup.evaluate(&doc, "regexp:test(/a, '^h', 'i')");
+
+        assert_eq!(Value::Boolean(true), result);
+    });
+}
+
+#[cfg(feature = "regexp")]
+#[test]
+fn exslt_regexp_match_creates_nodes_in_the_scratch_document() {
+    with_document("<a/>", |doc| {
+        let scratch = Package::new();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch.as_document());
+        function::exslt::register_exslt_regexp(&mut setup.context);
+
+        let second = setup.evaluate(&doc, r"string(regexp:match('a1b22c333', '\d+', 'g')[2])");
+        let count = setup.evaluate(&doc, r"count(regexp:match('a1b22c333', '\d+', 'g'))");
+
+        assert_eq!(Value::String("22".to_owned()), second);
+        assert_eq!(Value::Number(3.0), count);
+    });
+}
+
+#[test]
+fn scratch_document_can_be_cleared_between_evaluations() {
+    with_document("<a>x y z</a>", |doc| {
+        let scratch = Package::new();
+        let scratch = scratch.as_document();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch);
+        function::exslt::register_exslt_strings(&mut setup.context);
+        let created = || {
+            scratch
+                .root()
+                .children()
+                .into_iter()
+                .filter_map(|c| c.element())
+                .map(|e| e.children().len())
+                .sum::<usize>()
+        };
+
+        setup.evaluate(&doc, "str:tokenize(/a)");
+        setup.evaluate(&doc, "str:tokenize(/a)");
+        assert_eq!(6, created());
+
+        setup.context.clear_scratch_document();
+        assert_eq!(0, created());
+
+        let second = setup.evaluate(&doc, "string(str:tokenize(/a)[2])");
+        assert_eq!(Value::String("y".to_owned()), second);
+        assert_eq!(3, created());
+    });
+}
+
+#[test]
+fn exslt_str_tokenize_returns_token_elements() {
+    with_document("<a>x  y</a>", |doc| {
+        let scratch = Package::new();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch.as_document());
+        function::exslt::register_exslt_strings(&mut setup.context);
+
+        let default = setup.evaluate(&doc, "count(str:tokenize(/a))");
+        let explicit = setup.evaluate(&doc, "count(str:tokenize('1,,2', ','))");
+        let second = setup.evaluate(&doc, "string(str:tokenize(/a)[2])");
+
+        assert_eq!(Value::Number(2.0), default);
+        assert_eq!(Value::Number(3.0), explicit);
+        assert_eq!(Value::String("y".to_owned()), second);
+    });
+}
+
+#[test]
+fn exslt_str_replace_accepts_parallel_nodesets() {
+    with_document(
+        "<a><from>cat</from><from>dog</from><to>dog</to><to>cat</to></a>",
+        |doc| {
+            let mut setup = Setup::new();
+            function::exslt::register_exslt_strings(&mut setup.context);
+
+            let result = setup.evaluate(&doc, "str:replace('cat chases dog', /a/from, /a/to)");
+
+            assert_eq!(Value::String("dog chases cat".to_owned()), result);
+        },
+    );
+}
+
+#[test]
+fn exslt_str_concat_joins_a_nodeset_in_document_order() {
+    with_document(
+        "<list><item>a</item><item>b</item><other>x</other><item>c</item></list>",
+        |doc| {
+            let mut setup = Setup::new();
+            function::exslt::register_exslt_strings(&mut setup.context);
+
+            let items = setup.evaluate(&doc, "str:concat(//item/text())");
+            let none = setup.evaluate(&doc, "str:concat(//missing)");
+
+            assert_eq!(Value::String("abc".to_owned()), items);
+            assert_eq!(Value::String("".to_owned()), none);
+        },
+    );
+}
+
+#[test]
+fn exslt_math_min_and_max() {
+    with_document("<a><n>4</n><n>-1</n><n>12</n><x>oops</x></a>", |doc| {
+        let mut setup = Setup::new();
+        function::exslt::register_exslt_math(&mut setup.context);
+
+        let min = setup.evaluate(&doc, "math:min(/a/n)");
+        let max = setup.evaluate(&doc, "math:max(/a/n)");
+        let mixed = setup.evaluate(&doc, "math:max(/a/*)");
+
+        assert_eq!(Value::Number(-1.0), min);
+        assert_eq!(Value::Number(12.0), max);
+        assert!(mixed.number().is_nan());
+    });
+}
+
+#[test]
+fn exslt_sets_of_overlapping_path_expressions() {
+    with_document(
+        "<a><b id='1'>x</b><b id='2'>y</b><c>x</c><b id='3'>x</b></a>",
+        |doc| {
+            let mut setup = Setup::new();
+            function::exslt::register_exslt_sets(&mut setup.context);
+
+            let difference = setup.evaluate(&doc, "set:difference(/a/b, /a/*[. = 'x'])");
+            let intersection = setup.evaluate(&doc, "count(set:intersection(/a/b, //*[. = 'x']))");
+            let distinct = setup.evaluate(&doc, "count(set:distinct(/a/*))");
+            let first_x = setup.evaluate(&doc, "name(set:distinct(/a/*)[. = 'x'])");
+
+            assert_eq!(
+                Value::String("y".to_owned()),
+                Value::String(difference.string())
+            );
+            assert_eq!(Value::Number(2.0), intersection);
+            assert_eq!(Value::Number(2.0), distinct);
+            assert_eq!(Value::String("b".to_owned()), first_x);
+        },
+    );
+}
+
+#[test]
+fn exslt_node_set_results_support_further_steps() {
+    with_document("<a/>", |doc| {
+        let scratch = Package::new();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch.as_document());
+        setup.context.set_variable("csv", "red,green,blue");
+        function::exslt::register_exslt_common(&mut setup.context);
+        function::exslt::register_exslt_strings(&mut setup.context);
+
+        let second = setup.evaluate(&doc, "string(exsl:node-set(str:tokenize($csv, ','))[2])");
+        let texts = setup.evaluate(
+            &doc,
+            "string((exsl:node-set(str:tokenize($csv, ','))/text())[. != 'red'][2])",
+        );
+        let wrapped = setup.evaluate(&doc, "string(exsl:node-set(42)/self::text())");
+
+        assert_eq!(Value::String("green".to_owned()), second);
+        assert_eq!(Value::String("blue".to_owned()), texts);
+        assert_eq!(Value::String("42".to_owned()), wrapped);
+    });
+}
+
+#[test]
+fn exslt_object_type_names_each_kind_of_value() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_variable("s", "text");
+        setup.context.set_variable("n", 4.0);
+        setup.context.set_variable("b", false);
+        setup
+            .context
+            .set_variable("ns", Value::Nodeset(nodeset![doc.root()]));
+        function::exslt::register_exslt_common(&mut setup.context);
+
+        let types: Vec<_> = ["$s", "$n", "$b", "$ns"]
+            .iter()
+            .map(|v| {
+                setup
+                    .evaluate(&doc, &format!("exsl:object-type({})", v))
+                    .string()
+            })
+            .collect();
+
+        assert_eq!(vec!["string", "number", "boolean", "node-set"], types);
+    });
+}
+
+#[test]
+fn exslt_date_components_of_a_fixed_present() {
+    with_document("<a when='1999-12-31T23:59:58+01:00'/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_current_date_time("2024-02-29T12:30:00Z");
+        function::exslt::register_exslt_dates(&mut setup.context);
+
+        let now = setup.evaluate(&doc, "date:date-time()");
+        let day = setup.evaluate(&doc, "date:day-in-month()");
+        let year = setup.evaluate(&doc, "date:year(/a/@when)");
+        let hour = setup.evaluate(&doc, "date:hour-in-day(/a/@when)");
+
+        assert_eq!(Value::String("2024-02-29T12:30:00Z".to_owned()), now);
+        assert_eq!(Value::Number(29.0), day);
+        assert_eq!(Value::Number(1999.0), year);
+        assert_eq!(Value::Number(23.0), hour);
+    });
+}
+
+#[test]
+fn extended_string_functions_are_opt_in() {
+    with_document("<a>Straße</a>", |doc| {
+        let mut setup = Setup::new();
+        let unregistered = setup
+            .factory
+            .build("upper-case(/a)")
+            .expect("Unable to build XPath")
+            .evaluate(&setup.context, doc.root());
+        function::register_extended_string_functions(&mut setup.context);
+
+        let upper = setup.evaluate(&doc, "upper-case(/a)");
+        let matches = setup.evaluate(&doc, "lower-case(/a) = 'straße'");
+
+        assert!(unregistered.is_err());
+        assert_eq!(Value::String("STRASSE".to_owned()), upper);
+        assert_eq!(Value::Boolean(true), matches);
+    });
+}
+
+#[test]
+fn string_join_of_list_items() {
+    with_document("<ul><li>one</li><li>two</li><li>three</li></ul>", |doc| {
+        let mut setup = Setup::new();
+        function::register_extended_string_functions(&mut setup.context);
+
+        let joined = setup.evaluate(&doc, "string-join(//li, ', ')");
+        let empty = setup.evaluate(&doc, "string-join(//missing, ', ')");
+
+        assert_eq!(Value::String("one, two, three".to_owned()), joined);
+        assert_eq!(Value::String("".to_owned()), empty);
+    });
+}
+
+#[test]
+fn exslt_register_all_adds_every_library() {
+    with_document("<a><n>3</n><n>1</n></a>", |doc| {
+        let scratch = Package::new();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch.as_document());
+        setup.context.set_current_date_time("2001-02-03T04:05:06Z");
+
+        assert_eq!(Ok(()), function::exslt::register_all(&mut setup.context));
+
+        let results: Vec<_> = [
+            "exsl:object-type(1)",
+            "date:year()",
+            "dyn:evaluate('1 + 1')",
+            "math:min(/a/n)",
+            "count(set:distinct(/a/n))",
+            "str:concat(/a/n)",
+        ]
+        .iter()
+        .map(|xpath| setup.evaluate(&doc, xpath).string())
+        .collect();
+
+        assert_eq!(vec!["number", "2001", "2", "1", "2", "31"], results);
+    });
+}
+
+#[test]
+fn exslt_register_all_adds_regexp_only_with_the_feature() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+
+        assert_eq!(Ok(()), function::exslt::register_all(&mut setup.context));
+        let xpath = setup
+            .factory
+            .build("regexp:test('abc', 'b')")
+            .expect("Unable to build XPath");
+        let result = xpath.evaluate(&setup.context, doc.root());
+
+        if cfg!(feature = "regexp") {
+            assert_eq!(Ok(Value::Boolean(true)), result);
+        } else {
+            assert!(result.is_err());
+        }
+    });
+}
+
+#[test]
+fn exslt_register_all_reports_prefix_collisions() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_namespace("str", "urn:my-strings");
+
+        let collisions = function::exslt::register_all(&mut setup.context)
+            .expect_err("collision was not reported");
+        setup
+            .context
+            .set_namespace("exslt-str", function::exslt::STRINGS_NAMESPACE_URI);
+        let concat = setup.evaluate(&doc, "exslt-str:concat(/a)");
+
+        assert_eq!(1, collisions.len());
+        assert_eq!("str", collisions[0].prefix);
+        assert_eq!("urn:my-strings", collisions[0].existing_uri);
+        assert_eq!(Some("urn:my-strings"), setup.context.namespace_for("str"));
+        assert_eq!(Value::String("".to_owned()), concat);
+    });
+}
+
+#[test]
+fn closures_can_be_registered_as_functions() {
+    with_document("<a><b/><b/></a>", |doc| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+
+        let mut setup = Setup::new();
+        setup
+            .context
+            .set_function_fn("tally", move |context, args| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                function::Args(args).exactly(0)?;
+                Ok(Value::Number(context.position as f64))
+            });
+
+        let result = setup.evaluate(&doc, "count(/a/b[tally() = 2])");
+
+        assert_eq!(Value::Number(1.0), result);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+fn functions_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<context::Functions>();
+
+    let mut library = Context::new();
+    library.set_function_fn("answer", |_, _| Ok(Value::Number(42.0)));
+    let functions = library.functions().clone();
+
+    let threads: Vec<_> = (0..2)
+        .map(|i| {
+            let functions = functions.clone();
+            thread::spawn(move || {
+                let package = parser::parse("<a><b/><b/></a>").expect("failed to parse XML");
+                let doc = package.as_document();
+                let mut context = Context::without_core_functions();
+                context.set_functions(functions);
+
+                let xpath = Factory::new()
+                    .build(&format!("answer() + count(//b) + {}", i))
+                    .expect("Unable to build XPath");
+                xpath
+                    .evaluate(&context, doc.root())
+                    .expect("Unable to evaluate XPath")
+                    .number()
+            })
+        })
+        .collect();
+
+    let results: Vec<_> = threads
+        .into_iter()
+        .map(|t| t.join().expect("thread panicked"))
+        .collect();
+    assert_eq!(vec![44.0, 45.0], results);
+}
+
+#[test]
+fn functions_with_the_same_local_name_are_distinguished_by_namespace() {
+    with_document("<a>text</a>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_namespace("up", "urn:upper");
+        setup.context.set_namespace("rev", "urn:reverse");
+        setup
+            .context
+            .set_function_fn(("urn:upper", "convert"), |_, args| {
+                let mut args = function::Args(args);
+                args.exactly(1)?;
+                Ok(Value::String(args.pop_string()?.to_uppercase()))
+            });
+        setup
+            .context
+            .set_function_fn(("urn:reverse", "convert"), |_, args| {
+                let mut args = function::Args(args);
+                args.exactly(1)?;
+                Ok(Value::String(args.pop_string()?.chars().rev().collect()))
+            });
+
+        let result = setup.evaluate(&doc, "concat(up:convert(/a), '-', rev:convert(/a))");
+
+        assert_eq!(Value::String("TEXT-txet".to_owned()), result);
+    });
+}
+
+#[test]
+fn function_with_undeclared_prefix_names_the_prefix() {
+    with_document("<a/>", |doc| {
+        let mut context = Context::new();
+        context.set_function_fn(("urn:ext", "f"), |_, _| Ok(Value::Boolean(true)));
+        let xpath = Factory::new()
+            .build("ext:f()")
+            .expect("Unable to build XPath");
+
+        let error = xpath
+            .evaluate(&context, doc.root())
+            .expect_err("prefix should be unknown");
+
+        assert!(
+            error.to_string().contains("unknown namespace prefix ext"),
+            "{}",
+            error
+        );
+    });
+}
+
+#[test]
+fn strict_factory_checks_arity_of_exslt_functions() {
+    let mut context = Context::new();
+    assert_eq!(Ok(()), function::exslt::register_all(&mut context));
+
+    let mut factory = Factory::new();
+    factory.set_functions(context.functions().clone());
+    factory.set_strict_functions(true);
+    for &(prefix, uri) in &[
+        (
+            function::exslt::MATH_PREFIX,
+            function::exslt::MATH_NAMESPACE_URI,
+        ),
+        (
+            function::exslt::STRINGS_PREFIX,
+            function::exslt::STRINGS_NAMESPACE_URI,
+        ),
+    ] {
+        factory.set_namespace(prefix, uri);
+    }
+
+    for xpath in &["math:power(1)", "str:padding()", "str:align('a')"] {
+        let error = factory.build(xpath).expect_err("arity should be checked");
+        assert!(error.to_string().contains("arguments"), "{}", error);
+    }
+    assert!(factory.build("math:power(2, 3)").is_ok());
+    assert!(factory.build("str:padding(3)").is_ok());
+}
+
+#[test]
+fn factory_checks_arity_of_prefixed_functions() {
+    struct Pair;
+
+    impl function::Function for Pair {
+        fn evaluate<'c, 'd>(
+            &self,
+            _context: &context::Evaluation<'c, 'd>,
+            _args: Vec<Value<'d>>,
+        ) -> Result<Value<'d>, function::Error> {
+            Ok(Value::Boolean(true))
+        }
+
+        fn arity(&self) -> Option<function::Arity> {
+            Some(function::Arity::exactly(2))
+        }
+    }
+
+    let mut context = Context::new();
+    context.set_function(("urn:ext", "pair"), Pair);
+
+    let mut factory = Factory::new();
+    factory.set_functions(context.functions().clone());
+    factory.set_namespace("ext", "urn:ext");
+
+    let error = factory
+        .build("ext:pair(1)")
+        .expect_err("arity should be checked");
+    assert!(
+        error
+            .to_string()
+            .contains("exactly 2 arguments but was given 1"),
+        "{}",
+        error
+    );
+    assert!(factory.build("ext:pair(1, 2)").is_ok());
+}
+
+fn setup_get_fallback(context: &mut Context<'_>) {
+    context.set_namespace("my", "urn:my");
+    context.set_function_fallback(|name, _context, args| {
+        if name.namespace_uri() != Some("urn:my") {
+            return None;
+        }
+        let args = function::Args(args);
+        match name.local_part() {
+            "get-count" => Some(Ok(Value::Number(args.len() as f64))),
+            "get-sum" => Some(Ok(Value::Number(args.0.iter().map(Value::number).sum()))),
+            _ => None,
+        }
+    });
+}
+
+#[test]
+fn function_fallback_handles_unregistered_functions() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup_get_fallback(&mut setup.context);
+
+        let count = setup.evaluate(&doc, "my:get-count(1, 2, 3)");
+        let sum = setup.evaluate(&doc, "my:get-sum(1, 2, 3)");
+
+        assert_eq!(Value::Number(3.0), count);
+        assert_eq!(Value::Number(6.0), sum);
+    });
+}
+
+#[test]
+fn function_fallback_is_not_consulted_for_registered_functions() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup_get_fallback(&mut setup.context);
+        setup
+            .context
+            .set_function_fn(("urn:my", "get-count"), |_, _| Ok(Value::Number(-1.0)));
+
+        let result = setup.evaluate(&doc, "my:get-count(1, 2, 3)");
+
+        assert_eq!(Value::Number(-1.0), result);
+    });
+}
+
+#[test]
+fn function_fallback_can_decline_calls() {
+    with_document("<a/>", |doc| {
+        let mut context = Context::new();
+        setup_get_fallback(&mut context);
+        let xpath = Factory::new()
+            .build("my:set-count(1)")
+            .expect("Unable to build XPath");
+
+        let error = xpath
+            .evaluate(&context, doc.root())
+            .expect_err("call should be declined");
+
+        assert!(error.to_string().contains("unknown function"), "{}", error);
+    });
+}
+
+#[test]
+fn custom_function_errors_reach_the_caller_with_the_function_name() {
+    with_document("<a>2023-02-30</a>", |doc| {
+        let mut context = Context::new();
+        context.set_function_fn("checked-date", |_, args| {
+            let mut args = function::Args(args);
+            args.exactly(1)?;
+            let date = args.pop_string()?;
+            Err(function::Error::Other {
+                what: format!("{} is not a valid date", date),
+            })
+        });
+        let xpath = Factory::new()
+            .build("checked-date(/a)")
+            .expect("Unable to build XPath");
+
+        let error = xpath
+            .evaluate(&context, doc.root())
+            .expect_err("function should fail");
+
+        let message = error.to_string();
+        assert!(message.contains("checked-date"), "{}", message);
+        assert!(
+            message.contains("2023-02-30 is not a valid date"),
+            "{}",
+            message
+        );
+    });
+}
+
+fn evaluate_expanded_name(xpath: &str) -> Result<String, String> {
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let mut context = Context::new();
+    context.set_namespace("ex", "urn:example");
+    context.set_function_fn("expand", |context, args| {
+        let mut args = function::Args(args);
+        args.exactly(1)?;
+        let name = args.pop_string()?;
+        let mut parts = name.splitn(2, ':');
+        let expanded = match (parts.next(), parts.next()) {
+            (Some(prefix), Some(local)) => match context.namespace_for(prefix) {
+                Some(uri) => format!("{{{}}}{}", uri, local),
+                None => {
+                    return Err(function::Error::UnknownNamespace {
+                        prefix: prefix.into(),
+                    })
+                }
+            },
+            _ => name,
+        };
+        Ok(Value::String(expanded))
+    });
+
+    let xpath = Factory::new().build(xpath).expect("Unable to build XPath");
+    xpath
+        .evaluate(&context, doc.root())
+        .map(|v| v.string())
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn functions_can_expand_declared_prefixes() {
+    assert_eq!(
+        Ok("{urn:example}thing".to_owned()),
+        evaluate_expanded_name("expand('ex:thing')")
+    );
+}
+
+#[test]
+fn functions_can_report_undeclared_prefixes() {
+    let error = evaluate_expanded_name("expand('nope:thing')").expect_err("prefix is undeclared");
+
+    assert!(error.contains("unknown namespace prefix nope"), "{}", error);
+}
+
+#[test]
+fn functions_can_list_namespaces() {
+    with_document("<a/>", |doc| {
+        let mut setup = Setup::new();
+        setup.context.set_namespace("b", "urn:b");
+        setup.context.set_namespace("a", "urn:a");
+        setup.context.set_function_fn("prefixes", |context, _| {
+            let mut mappings: Vec<_> = context
+                .namespaces()
+                .map(|(prefix, uri)| format!("{}={}", prefix, uri))
+                .collect();
+            mappings.sort();
+            Ok(Value::String(mappings.join(" ")))
+        });
+
+        let result = setup.evaluate(&doc, "prefixes()");
+
+        assert_eq!(Value::String("a=urn:a b=urn:b".to_owned()), result);
+    });
+}
+
+#[test]
+fn function_library_can_be_given_to_a_context() {
+    with_document("<a><b/><b/></a>", |doc| {
+        let functions = function::FunctionLibrary::new()
+            .with_core()
+            .with("seven", ConstantValueFunction(7.0))
+            .build();
+
+        let mut setup = Setup::new();
+        setup.context.set_functions(functions);
+
+        let result = setup.evaluate(&doc, "seven() + count(//b)");
+
+        assert_eq!(Value::Number(9.0), result);
+    });
+}
+
+#[test]
+fn functions_can_return_created_nodes_in_creation_order() {
+    with_document("<a/>", |doc| {
+        let scratch = Package::new();
+        let mut setup = Setup::new();
+        setup.context.set_scratch_document(scratch.as_document());
+        setup.context.set_function_fn("pair", |context, _| {
+            let doc = context.scratch_document()?;
+            let container = context.scratch_container()?;
+            let mut nodes = nodeset::Nodeset::new();
+            for name in &["first", "second"] {
+                let element = doc.create_element(*name);
+                element.append_child(doc.create_text(name));
+                container.append_child(element);
+                nodes.add(element);
+            }
+            Ok(Value::Nodeset(nodes))
+        });
+
+        let second = setup.evaluate(&doc, "string(pair()[2])");
+        let last = setup.evaluate(&doc, "string(pair()[last()])");
+        let first = setup.evaluate(&doc, "string(pair()[position() = 1])");
+
+        assert_eq!(Value::String("second".to_owned()), second);
+        assert_eq!(Value::String("second".to_owned()), last);
+        assert_eq!(Value::String("first".to_owned()), first);
+    });
+}
+
 #[test]
 fn axis_predicate_order() {
     with_document("<a><b><c/></b><b><c/></b></a>", |doc| {
         // All the `c` elements that are the first child
         let result = evaluate_xpath(&doc, "//c[1]");
 
-        let a = doc.root().children()[0].element().expect("No element a");
-        let b0 = a.children()[0].element().expect("No element b0");
-        let b1 = a.children()[1].element().expect("No element b1");
-        let c0 = b0.children()[0].element().expect("No element c0");
-        let c1 = b1.children()[0].element().expect("No element c1");
-        assert_eq!(Ok(Value::Nodeset(nodeset![c0, c1])), result);
+        let a = doc.root().children()[0].element().expect("No element a");
+        let b0 = a.children()[0].element().expect("No element b0");
+        let b1 = a.children()[1].element().expect("No element b1");
+        let c0 = b0.children()[0].element().expect("No element c0");
+        let c1 = b1.children()[0].element().expect("No element c1");
+        assert_eq!(Ok(Value::Nodeset(nodeset![c0, c1])), result);
+    });
+}
+
+#[test]
+fn ancestor_positions_count_from_the_nearest_ancestor() {
+    with_document(
+        "<a title='outer'><b title='middle'><c title='inner'><d/></c></b></a>",
+        |doc| {
+            let nearest = evaluate_xpath(&doc, "string(//d/ancestor::*[1]/@title)");
+            let second = evaluate_xpath(&doc, "string(//d/ancestor::*[2]/@title)");
+            let last = evaluate_xpath(&doc, "string(//d/ancestor::*[last()]/@title)");
+            let count = evaluate_xpath(&doc, "count(//d/ancestor::node())");
+
+            assert_eq!(Ok(Value::String("inner".to_owned())), nearest);
+            assert_eq!(Ok(Value::String("middle".to_owned())), second);
+            assert_eq!(Ok(Value::String("outer".to_owned())), last);
+            // The three elements and the root node
+            assert_eq!(Ok(Value::Number(4.0)), count);
+        },
+    );
+}
+
+#[test]
+fn ancestor_node_tests_and_predicates_apply_as_usual() {
+    with_document("<a><s t='1'><b><s t='2'><d/></s></b></s></a>", |doc| {
+        let result = evaluate_xpath(&doc, "string(//d/ancestor::s[2]/@t)");
+        let filtered = evaluate_xpath(&doc, "count(//d/ancestor::*[@t])");
+
+        assert_eq!(Ok(Value::String("1".to_owned())), result);
+        assert_eq!(Ok(Value::Number(2.0)), filtered);
+    });
+}
+
+#[test]
+fn ancestor_of_the_root_is_empty() {
+    with_document("<a/>", |doc| {
+        let result = evaluate_xpath(&doc, "count(/ancestor::node())");
+
+        assert_eq!(Ok(Value::Number(0.0)), result);
+    });
+}
+
+#[test]
+fn ancestor_or_self_starts_with_the_context_node() {
+    with_document("<a><b><c/></b></a>", |doc| {
+        let first = evaluate_xpath(&doc, "name(//c/ancestor-or-self::*[1])");
+        let count = evaluate_xpath(&doc, "count(//c/ancestor-or-self::*)");
+
+        assert_eq!(Ok(Value::String("c".to_owned())), first);
+        assert_eq!(Ok(Value::Number(3.0)), count);
+    });
+}
+
+#[test]
+fn ancestor_or_self_of_an_attribute_continues_with_its_element() {
+    with_document("<a><b><c id='x'/></b></a>", |doc| {
+        let own = evaluate_xpath(&doc, "string(//@id/ancestor-or-self::node()[1])");
+        let element = evaluate_xpath(&doc, "name(//@id/ancestor-or-self::node()[2])");
+        let elements = evaluate_xpath(&doc, "count(//@id/ancestor-or-self::*)");
+
+        assert_eq!(Ok(Value::String("x".to_owned())), own);
+        assert_eq!(Ok(Value::String("c".to_owned())), element);
+        assert_eq!(Ok(Value::Number(3.0)), elements);
+    });
+}
+
+#[test]
+fn ancestor_or_self_name_test_matches_one_ancestor() {
+    with_document("<a><b><c><d/></c></b></a>", |doc| {
+        let result = evaluate_xpath(&doc, "count(//d/ancestor-or-self::b)");
+        let name = evaluate_xpath(&doc, "name(//d/ancestor-or-self::b)");
+
+        assert_eq!(Ok(Value::Number(1.0)), result);
+        assert_eq!(Ok(Value::String("b".to_owned())), name);
+    });
+}
+
+#[test]
+fn following_spans_later_subtrees() {
+    with_document(
+        "<doc><sec><h>A</h><p>1</p></sec><sec><h>B</h><p>2<b>bold</b></p></sec></doc>",
+        |doc| {
+            let paragraphs = evaluate_xpath(&doc, "count(//h[. = 'A']/following::p)");
+            let next_heading = evaluate_xpath(&doc, "string(//h[. = 'A']/following::h[1])");
+            let after_section = evaluate_xpath(&doc, "count(/doc/sec[1]/following::*)");
+
+            assert_eq!(Ok(Value::Number(2.0)), paragraphs);
+            assert_eq!(Ok(Value::String("B".to_owned())), next_heading);
+            // The second section, its heading, paragraph and bold
+            // text, but none of the first section's descendants
+            assert_eq!(Ok(Value::Number(4.0)), after_section);
+        },
+    );
+}
+
+#[test]
+fn preceding_excludes_ancestors_and_counts_from_the_nearest() {
+    with_document(
+        concat!(
+            "<p id='1'>",
+            "<p id='2'/>",
+            "<p id='3'>",
+            "<p id='4'><p id='5'/></p>",
+            "<p id='6'><p id='7'><x/></p></p>",
+            "</p>",
+            "</p>"
+        ),
+        |doc| {
+            let nearest = evaluate_xpath(&doc, "string(//x/preceding::p[1]/@id)");
+            let farthest = evaluate_xpath(&doc, "string(//x/preceding::p[last()]/@id)");
+            let all = evaluate_xpath(&doc, "count(//x/preceding::p)");
+            let ancestors = evaluate_xpath(&doc, "count(//x/preceding::p[descendant::x])");
+
+            assert_eq!(Ok(Value::String("5".to_owned())), nearest);
+            assert_eq!(Ok(Value::String("2".to_owned())), farthest);
+            // Only 2, 4 and 5; 1, 3, 6 and 7 are ancestors of x
+            assert_eq!(Ok(Value::Number(3.0)), all);
+            assert_eq!(Ok(Value::Number(0.0)), ancestors);
+        },
+    );
+}
+
+#[test]
+fn following_sibling_positions_count_forwards() {
+    with_document(
+        "<t><tr id='1'/>text<tr id='2'/><!--note--><tr id='3'/></t>",
+        |doc| {
+            let next = evaluate_xpath(&doc, "string(/t/tr[1]/following-sibling::tr[1]/@id)");
+            let last = evaluate_xpath(&doc, "string(/t/tr[1]/following-sibling::tr[last()]/@id)");
+            let text = evaluate_xpath(&doc, "string(/t/tr[1]/following-sibling::text())");
+            let comments = evaluate_xpath(&doc, "count(/t/tr[1]/following-sibling::comment())");
+
+            assert_eq!(Ok(Value::String("2".to_owned())), next);
+            assert_eq!(Ok(Value::String("3".to_owned())), last);
+            assert_eq!(Ok(Value::String("text".to_owned())), text);
+            assert_eq!(Ok(Value::Number(1.0)), comments);
+        },
+    );
+}
+
+#[test]
+fn following_sibling_of_the_last_child_attributes_and_root_is_empty() {
+    with_document("<t a='1' b='2'><tr/><tr/></t>", |doc| {
+        let last_child = evaluate_xpath(&doc, "count(/t/tr[2]/following-sibling::node())");
+        let attribute = evaluate_xpath(&doc, "count(/t/@a/following-sibling::node())");
+        let root = evaluate_xpath(&doc, "count(/following-sibling::node())");
+
+        assert_eq!(Ok(Value::Number(0.0)), last_child);
+        assert_eq!(Ok(Value::Number(0.0)), attribute);
+        assert_eq!(Ok(Value::Number(0.0)), root);
+    });
+}
+
+#[test]
+fn preceding_sibling_positions_count_backwards_from_the_context_node() {
+    with_document(
+        "<r><i id='1'/><i id='2'/><i id='3'/><i id='4'/></r>",
+        |doc| {
+            let ids = |xpath: &str| -> String {
+                let value = evaluate_xpath(&doc, xpath).expect("XPath evaluation failed");
+                match value {
+                    Value::Nodeset(nodes) => nodes
+                        .document_order()
+                        .iter()
+                        .map(|n| {
+                            n.element()
+                                .and_then(|e| e.attribute_value("id"))
+                                .unwrap_or("")
+                        })
+                        .collect(),
+                    other => other.string(),
+                }
+            };
+
+            assert_eq!("3", ids("/r/i[4]/preceding-sibling::*[1]"));
+            assert_eq!("1", ids("/r/i[4]/preceding-sibling::*[last()]"));
+            assert_eq!("23", ids("/r/i[4]/preceding-sibling::*[position() < 3]"));
+            assert_eq!("2", ids("/r/i[4]/preceding-sibling::*[position() > 1][1]"));
+            assert_eq!("2", ids("/r/i[4]/preceding-sibling::*[2]"));
+            // A parenthesized step is a filter expression, whose
+            // positions follow document order
+            assert_eq!("1", ids("(/r/i[4]/preceding-sibling::*)[1]"));
+            assert_eq!("3", ids("string(/r/i[4]/preceding-sibling::*[1]/@id)"));
+        },
+    );
+}
+
+#[test]
+fn preceding_sibling_of_the_first_child_and_attributes_is_empty() {
+    with_document("<r a='1' b='2'><i/><i/></r>", |doc| {
+        let first_child = evaluate_xpath(&doc, "count(/r/i[1]/preceding-sibling::node())");
+        let attribute = evaluate_xpath(&doc, "count(/r/@b/preceding-sibling::node())");
+
+        assert_eq!(Ok(Value::Number(0.0)), first_child);
+        assert_eq!(Ok(Value::Number(0.0)), attribute);
+    });
+}
+
+#[test]
+fn namespace_axis_includes_inherited_and_xml_bindings() {
+    with_document(
+        "<a xmlns:p='urn:outer' xmlns:q='urn:q'><b xmlns:p='urn:inner'><c/></b></a>",
+        |doc| {
+            let count = evaluate_xpath(&doc, "count(/a/b/c/namespace::*)");
+            let shadowed = evaluate_xpath(&doc, "string(/a/b/c/namespace::p)");
+            let outer = evaluate_xpath(&doc, "string(/a/namespace::p)");
+            let inherited = evaluate_xpath(&doc, "string(/a/b/c/namespace::q)");
+            let xml = evaluate_xpath(&doc, "string(/a/b/c/namespace::xml)");
+            let names = evaluate_xpath(&doc, "count(/a/b/c/namespace::*[name() = 'p'])");
+
+            // p, q and xml, with p only once
+            assert_eq!(Ok(Value::Number(3.0)), count);
+            assert_eq!(Ok(Value::String("urn:inner".to_owned())), shadowed);
+            assert_eq!(Ok(Value::String("urn:outer".to_owned())), outer);
+            assert_eq!(Ok(Value::String("urn:q".to_owned())), inherited);
+            assert_eq!(
+                Ok(Value::String(
+                    "http://www.w3.org/XML/1998/namespace".to_owned()
+                )),
+                xml
+            );
+            assert_eq!(Ok(Value::Number(1.0)), names);
+        },
+    );
+}
+
+#[test]
+fn namespace_axis_is_empty_for_non_elements() {
+    with_document("<a xmlns:p='urn:p' p:x='1'>text</a>", |doc| {
+        let attribute = evaluate_xpath(&doc, "count(/a/@*/namespace::*)");
+        let text = evaluate_xpath(&doc, "count(/a/text()/namespace::*)");
+
+        assert_eq!(Ok(Value::Number(0.0)), attribute);
+        assert_eq!(Ok(Value::Number(0.0)), text);
+    });
+}
+
+#[test]
+fn namespace_axis_includes_the_default_namespace() {
+    with_document("<a xmlns='urn:d'><b/><c xmlns=''/></a>", |doc| {
+        let count = evaluate_xpath(&doc, "count(/*/*[1]/namespace::*)");
+        let default = evaluate_xpath(&doc, "string(/*/*[1]/namespace::*[name() = ''])");
+        let undeclared = evaluate_xpath(&doc, "count(/*/*[2]/namespace::*)");
+
+        // The default namespace and xml
+        assert_eq!(Ok(Value::Number(2.0)), count);
+        assert_eq!(Ok(Value::String("urn:d".to_owned())), default);
+        assert_eq!(Ok(Value::Number(1.0)), undeclared);
+    });
+}
+
+#[test]
+fn star_selects_the_principal_node_type_of_the_axis() {
+    with_document(
+        "<a xmlns:p='urn:p' href='x' id='y'>text<b/><!--c--><?pi?><c/></a>",
+        |doc| {
+            let attributes = evaluate_xpath(&doc, "count(/a/@*)");
+            let long_attributes = evaluate_xpath(&doc, "count(/a/attribute::*)");
+            let href = evaluate_xpath(&doc, "string(/a/attribute::href)");
+            let children = evaluate_xpath(&doc, "count(/a/child::*)");
+            let nodes = evaluate_xpath(&doc, "count(/a/child::node())");
+            let namespaces = evaluate_xpath(&doc, "count(/a/namespace::*)");
+            let self_attribute = evaluate_xpath(&doc, "count(/a/@href/self::*)");
+
+            assert_eq!(Ok(Value::Number(2.0)), attributes);
+            assert_eq!(Ok(Value::Number(2.0)), long_attributes);
+            assert_eq!(Ok(Value::String("x".to_owned())), href);
+            assert_eq!(Ok(Value::Number(2.0)), children);
+            assert_eq!(Ok(Value::Number(5.0)), nodes);
+            // p and xml
+            assert_eq!(Ok(Value::Number(2.0)), namespaces);
+            // self:: selects elements, so an attribute does not match
+            assert_eq!(Ok(Value::Number(0.0)), self_attribute);
+        },
+    );
+}
+
+#[test]
+fn deeply_nested_documents_do_not_overflow_the_stack() {
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let top = doc.create_element("top");
+    doc.root().append_child(top);
+    let mut parent = top;
+    for _ in 0..100_000 {
+        let element = doc.create_element("level");
+        parent.append_child(element);
+        parent = element;
+    }
+    let leaf = doc.create_element("leaf");
+    leaf.append_child(doc.create_text("bottom"));
+    parent.append_child(leaf);
+
+    let leaves = evaluate_xpath(&doc, "count(//leaf)");
+    let nodes = evaluate_xpath(&doc, "count(/top/descendant::node())");
+    let text = evaluate_xpath(&doc, "string(/top)");
+    let after = evaluate_xpath(&doc, "count(//leaf/preceding::node())");
+
+    assert_eq!(Ok(Value::Number(1.0)), leaves);
+    assert_eq!(Ok(Value::Number(100_002.0)), nodes);
+    assert_eq!(Ok(Value::String("bottom".to_owned())), text);
+    assert_eq!(Ok(Value::Number(0.0)), after);
+}
+
+#[test]
+fn parent_of_every_node_type_is_its_container() {
+    with_document(
+        "<a><b id='x' n='1'>text<!--c--><?pi?></b><b n='2'/></a>",
+        |doc| {
+            let from_attribute = evaluate_xpath(&doc, "string(//@id/../@n)");
+            let then_sibling = evaluate_xpath(&doc, "string(//@id/../following-sibling::b/@n)");
+            let from_text = evaluate_xpath(&doc, "name(//text()/parent::*)");
+            let then_parent = evaluate_xpath(&doc, "name(//text()/../..)");
+            let from_comment = evaluate_xpath(&doc, "string(//comment()/../@n)");
+            let from_pi = evaluate_xpath(&doc, "string(//processing-instruction()/../@n)");
+            let from_document_element = evaluate_xpath(&doc, "count(/a/.. | /)");
+            let from_root = evaluate_xpath(&doc, "count(/..)");
+
+            assert_eq!(Ok(Value::String("1".to_owned())), from_attribute);
+            assert_eq!(Ok(Value::String("2".to_owned())), then_sibling);
+            assert_eq!(Ok(Value::String("b".to_owned())), from_text);
+            assert_eq!(Ok(Value::String("a".to_owned())), then_parent);
+            assert_eq!(Ok(Value::String("1".to_owned())), from_comment);
+            assert_eq!(Ok(Value::String("1".to_owned())), from_pi);
+            assert_eq!(Ok(Value::Number(1.0)), from_document_element);
+            assert_eq!(Ok(Value::Number(0.0)), from_root);
+        },
+    );
+}
+
+#[test]
+fn processing_instruction_test_can_select_by_target() {
+    with_document(
+        "<?xml-stylesheet href='style.css'?><a><?other data?><?xml-stylesheet type='x'?></a>",
+        |doc| {
+            let stylesheets =
+                evaluate_xpath(&doc, "count(//processing-instruction('xml-stylesheet'))");
+            let other = evaluate_xpath(&doc, "string(//processing-instruction(\"other\"))");
+            let all = evaluate_xpath(&doc, "count(//processing-instruction())");
+            let spaced = evaluate_xpath(&doc, "count(//processing-instruction( 'other' ))");
+            let missing = evaluate_xpath(&doc, "count(//processing-instruction('missing'))");
+            let name = evaluate_xpath(&doc, "name(/a/processing-instruction()[1])");
+
+            assert_eq!(Ok(Value::Number(2.0)), stylesheets);
+            assert_eq!(Ok(Value::String("data".to_owned())), other);
+            assert_eq!(Ok(Value::Number(3.0)), all);
+            assert_eq!(Ok(Value::Number(1.0)), spaced);
+            assert_eq!(Ok(Value::Number(0.0)), missing);
+            assert_eq!(Ok(Value::String("other".to_owned())), name);
+        },
+    );
+}
+
+#[test]
+fn comment_test_works_on_every_axis() {
+    with_document(
+        "<!--top--><a>one<!-- TODO: first --><b/>two<!--done--><c><!-- TODO: nested --></c></a>",
+        |doc| {
+            let all = evaluate_xpath(&doc, "count(//comment())");
+            let todos = evaluate_xpath(&doc, "count(//comment()[contains(., 'TODO')])");
+            let after_b = evaluate_xpath(&doc, "string(/a/b/following-sibling::comment())");
+            let before_b = evaluate_xpath(&doc, "string(/a/b/preceding-sibling::comment())");
+            let following = evaluate_xpath(&doc, "count(/a/b/following::comment())");
+            let preceding = evaluate_xpath(&doc, "count(/a/c/preceding::comment())");
+            let descendants = evaluate_xpath(&doc, "count(/a/descendant::comment())");
+            let at_root = evaluate_xpath(&doc, "string(/comment())");
+            let own_self = evaluate_xpath(&doc, "count(//comment()/self::comment())");
+            let text_nodes = evaluate_xpath(&doc, "count(/a/text())");
+            let ancestor = evaluate_xpath(&doc, "name((//comment())[last()]/ancestor::*[1])");
+            let positioned = evaluate_xpath(&doc, "string(/a/comment()[2])");
+
+            assert_eq!(Ok(Value::Number(4.0)), all);
+            assert_eq!(Ok(Value::Number(2.0)), todos);
+            assert_eq!(Ok(Value::String("done".to_owned())), after_b);
+            assert_eq!(Ok(Value::String(" TODO: first ".to_owned())), before_b);
+            assert_eq!(Ok(Value::Number(2.0)), following);
+            assert_eq!(Ok(Value::Number(3.0)), preceding);
+            assert_eq!(Ok(Value::Number(3.0)), descendants);
+            assert_eq!(Ok(Value::String("top".to_owned())), at_root);
+            assert_eq!(Ok(Value::Number(4.0)), own_self);
+            assert_eq!(Ok(Value::Number(2.0)), text_nodes);
+            assert_eq!(Ok(Value::String("c".to_owned())), ancestor);
+            assert_eq!(Ok(Value::String("done".to_owned())), positioned);
+        },
+    );
+}
+
+#[test]
+fn name_tests_compare_expanded_names_not_document_prefixes() {
+    with_document(
+        "<a:root xmlns:a='urn:x'><a:item>1</a:item><item>2</item><a:item>3</a:item></a:root>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_namespace("b", "urn:x");
+            setup.context.set_namespace("other", "urn:y");
+
+            let prefixed = setup.evaluate(&doc, "count(/b:root/b:item)");
+            let unprefixed = setup.evaluate(&doc, "string(/b:root/item)");
+            let wildcard = setup.evaluate(&doc, "count(/b:root/b:*)");
+            let other = setup.evaluate(&doc, "count(/other:root)");
+
+            assert_eq!(Value::Number(2.0), prefixed);
+            assert_eq!(Value::String("2".to_owned()), unprefixed);
+            assert_eq!(Value::Number(2.0), wildcard);
+            assert_eq!(Value::Number(0.0), other);
+        },
+    );
+}
+
+#[test]
+fn name_test_with_undeclared_prefix_is_an_error() {
+    with_document("<a:root xmlns:a='urn:x'/>", |doc| {
+        let context = Context::new();
+        let xpath = Factory::new()
+            .build("count(/a:root)")
+            .expect("Unable to build XPath");
+
+        let error = xpath
+            .evaluate(&context, doc.root())
+            .expect_err("prefix should be unknown");
+
+        assert!(
+            error.to_string().contains("unknown namespace prefix a"),
+            "{}",
+            error
+        );
+    });
+}
+
+#[test]
+fn name_tests_match_default_namespaced_documents_through_a_prefix() {
+    with_document(
+        "<root xmlns='urn:x'><item>1</item><item>2</item></root>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_namespace("x", "urn:x");
+
+            let prefixed = setup.evaluate(&doc, "count(/x:root/x:item)");
+            let unprefixed = setup.evaluate(&doc, "count(/root/item)");
+
+            assert_eq!(Value::Number(2.0), prefixed);
+            assert_eq!(Value::Number(0.0), unprefixed);
+        },
+    );
+}
+
+#[test]
+fn prefixed_wildcard_selects_everything_in_one_namespace() {
+    with_document(
+        "<root xmlns:a='urn:a' xmlns:b='urn:b' xmlns:xlink='http://www.w3.org/1999/xlink'>\
+         <a:one/><b:two/><a:three/><four/>\
+         <link xlink:href='#x' xlink:type='simple' title='t'/>\
+         </root>",
+        |doc| {
+            let mut setup = Setup::new();
+            setup.context.set_namespace("x", "urn:a");
+            setup.context.set_namespace("y", "urn:b");
+            setup
+                .context
+                .set_namespace("xl", "http://www.w3.org/1999/xlink");
+
+            let in_a = setup.evaluate(&doc, "count(/root/x:*)");
+            let names = setup.evaluate(&doc, "concat(name(/root/x:*[1]), name(/root/x:*[2]))");
+            let in_b = setup.evaluate(&doc, "local-name(/root/y:*)");
+            let xlink = setup.evaluate(&doc, "count(//link/@xl:*)");
+            let first_xlink = setup.evaluate(&doc, "string(//link/@xl:*[1])");
+            let all_attributes = setup.evaluate(&doc, "count(//link/@*)");
+
+            assert_eq!(Value::Number(2.0), in_a);
+            assert_eq!(Value::String("a:onea:three".to_owned()), names);
+            assert_eq!(Value::String("two".to_owned()), in_b);
+            assert_eq!(Value::Number(2.0), xlink);
+            assert_eq!(Value::String("#x".to_owned()), first_xlink);
+            assert_eq!(Value::Number(3.0), all_attributes);
+        },
+    );
+}
+
+#[test]
+fn local_name_wildcard_matches_across_namespaces_when_enabled() {
+    with_document(
+        "<root xmlns:a='urn:a' xmlns:b='urn:b'><a:item>1</a:item><b:item>2</b:item><item>3</item><a:other/></root>",
+        |doc| {
+            let mut factory = Factory::new();
+            assert!(factory.build("/root/*:item").is_err());
+
+            factory.set_local_name_wildcards(true);
+            let xpath = factory
+                .build("/root/*:item")
+                .expect("Unable to build XPath");
+            let items = xpath
+                .evaluate(&Context::new(), doc.root())
+                .expect("Unable to evaluate XPath");
+
+            match items {
+                Value::Nodeset(ns) => {
+                    let values: Vec<_> = ns
+                        .document_order()
+                        .iter()
+                        .map(|n| n.string_value())
+                        .collect();
+                    assert_eq!(vec!["1", "2", "3"], values);
+                }
+                other => panic!("expected a nodeset, got {:?}", other),
+            }
+        },
+    );
+}
+
+#[test]
+fn default_element_namespace_applies_to_unprefixed_element_names() {
+    with_document(
+        "<feed xmlns='http://www.w3.org/2005/Atom'>\
+         <title>Example</title>\
+         <entry><title>First</title><link href='/1'/></entry>\
+         <entry><title>Second</title><link href='/2'/></entry>\
+         </feed>",
+        |doc| {
+            let mut setup = Setup::new();
+            let spec_titles = setup.evaluate(&doc, "count(/feed/entry/title)");
+
+            setup
+                .context
+                .set_default_element_namespace("http://www.w3.org/2005/Atom");
+            let titles = setup.evaluate(&doc, "count(/feed/entry/title)");
+            let second = setup.evaluate(&doc, "string(/feed/entry[2]/title)");
+            let href = setup.evaluate(&doc, "string(//entry[1]/link/@href)");
+            let everything = setup.evaluate(&doc, "count(//*)");
+
+            assert_eq!(Value::Number(0.0), spec_titles);
+            assert_eq!(Value::Number(2.0), titles);
+            assert_eq!(Value::String("Second".to_owned()), second);
+            assert_eq!(Value::String("/1".to_owned()), href);
+            assert_eq!(Value::Number(8.0), everything);
+        },
+    );
+}
+
+#[test]
+fn build_reports_why_an_expression_is_invalid() {
+    let factory = Factory::new();
+
+    let empty = factory.build("  ").expect_err("empty XPath should fail");
+    assert_eq!(ParseErrorKind::Empty, empty.kind());
+    assert!(!empty.is_tokenizer_error());
+    assert_eq!("  ", empty.xpath());
+
+    let trailing = factory
+        .build("/a/b c")
+        .expect_err("trailing tokens should fail");
+    assert_eq!(ParseErrorKind::TrailingTokens, trailing.kind());
+    assert!(!trailing.is_tokenizer_error());
+    assert_eq!(Some("c"), trailing.token());
+    assert_eq!("/a/b c", trailing.xpath());
+
+    let tokenizer = factory
+        .build("/a[@b = \"c]")
+        .expect_err("unterminated literal should fail");
+    assert_eq!(ParseErrorKind::UnterminatedLiteral, tokenizer.kind());
+    assert!(tokenizer.is_tokenizer_error());
+    assert_eq!(8, tokenizer.offset());
+}
+
+#[test]
+fn build_rejects_anything_after_a_complete_expression() {
+    let factory = Factory::new();
+    let error = |xpath| {
+        let e = factory.build(xpath).expect_err("XPath should fail");
+        (e.kind(), e.offset(), e.to_string())
+    };
+
+    let (kind, offset, _) = error("1 +");
+    assert_eq!((ParseErrorKind::MissingOperand, 3), (kind, offset));
+
+    let (kind, offset, message) = error("/a/b !!!garbage");
+    assert_eq!((ParseErrorKind::TrailingTokens, 5), (kind, offset));
+    assert_eq!(
+        "trailing tokens after a complete expression at offset 5",
+        message
+    );
+
+    let (kind, offset, _) = error("1 2");
+    assert_eq!((ParseErrorKind::TrailingTokens, 2), (kind, offset));
+
+    let (kind, offset, _) = error("'a' 'b'");
+    assert_eq!((ParseErrorKind::TrailingTokens, 4), (kind, offset));
+}
+
+#[test]
+fn string_literals_switch_delimiters_to_contain_quotes() {
+    with_document("<a/>", |doc| {
+        let apostrophe = evaluate_xpath(&doc, "concat(\"it's\", '')");
+        let quotes = evaluate_xpath(&doc, "'say \"hi\"'");
+        let newline = evaluate_xpath(&doc, "string-length('a\nb')");
+
+        assert_eq!(Ok(Value::String("it's".to_owned())), apostrophe);
+        assert_eq!(Ok(Value::String("say \"hi\"".to_owned())), quotes);
+        assert_eq!(Ok(Value::Number(3.0)), newline);
+    });
+
+    let error = Factory::new()
+        .build("concat('a', \"b, 'c')")
+        .expect_err("unterminated literal should fail");
+    assert_eq!(ParseErrorKind::UnterminatedLiteral, error.kind());
+    assert_eq!(12, error.offset());
+}
+
+#[test]
+fn numeric_literals_accept_every_form_of_the_grammar() {
+    with_document("<a><b/><b>0.5</b><b>.5</b></a>", |doc| {
+        let halves = evaluate_xpath(&doc, ".5 + .5");
+        let trailing_dot = evaluate_xpath(&doc, "5. * 2");
+        let leading_zeroes = evaluate_xpath(&doc, "007 + 1");
+        let positional = evaluate_xpath(&doc, "count(/a/b[position() = .5 * 4])");
+        let compared = evaluate_xpath(&doc, "count(/a/b[. = .5])");
+
+        assert_eq!(Ok(Value::Number(1.0)), halves);
+        assert_eq!(Ok(Value::Number(10.0)), trailing_dot);
+        assert_eq!(Ok(Value::Number(8.0)), leading_zeroes);
+        assert_eq!(Ok(Value::Number(1.0)), positional);
+        assert_eq!(Ok(Value::Number(2.0)), compared);
+    });
+
+    assert!(Factory::new().build("foo/.5").is_err());
+}
+
+#[test]
+fn operators_follow_xpath_precedence_and_associativity() {
+    use sxd_xpath::Value::{Boolean, Number};
+
+    let cases = [
+        ("1 + 2 * 3 > 4 and 2 = 2 or false()", Boolean(true)),
+        ("8 - 4 - 2", Number(2.0)),
+        ("8 div 4 div 2", Number(1.0)),
+        ("7 mod 4 mod 2", Number(1.0)),
+        ("2 + 3 * 4", Number(14.0)),
+        ("(2 + 3) * 4", Number(20.0)),
+        ("10 - 2 * 3", Number(4.0)),
+        ("12 div 3 * 2", Number(8.0)),
+        ("2 * 3 mod 4", Number(2.0)),
+        ("5 mod 3 * 2", Number(4.0)),
+        ("1 < 2 = 2 > 1", Boolean(true)),
+        ("3 > 2 > 1", Boolean(false)),
+        ("1 = 1 = 1", Boolean(true)),
+        ("2 = 2 != 0", Boolean(true)),
+        ("1 + 2 > 2 + 0", Boolean(true)),
+        ("1 < 2 and 3 < 2", Boolean(false)),
+        ("true() or false() and false()", Boolean(true)),
+        ("false() and false() or true()", Boolean(true)),
+        ("0 or 1 and 0", Boolean(false)),
+        ("1 - 1 = 0 and 2 div 2 = 1", Boolean(true)),
+        ("- 2 + 3", Number(1.0)),
+        ("-2 * -3", Number(6.0)),
+        ("- - 4", Number(4.0)),
+        ("- 3 - - 2", Number(-1.0)),
+        ("2 - -1", Number(3.0)),
+        ("- //b | //c", Number(-3.0)),
+        ("count(//b | //c) * 2", Number(4.0)),
+    ];
+
+    with_document("<a><b>3</b><c>4</c></a>", |doc| {
+        for &(xpath, ref expected) in cases.iter() {
+            assert_eq!(
+                Ok(expected.clone()),
+                evaluate_xpath(&doc, xpath),
+                "{}",
+                xpath
+            );
+        }
+    });
+}
+
+#[test]
+fn unary_minus_converts_its_operand_to_a_number() {
+    with_document(
+        "<a><x/><x/><item price='2.5'/><item price='4'/></a>",
+        |doc| {
+            let number = evaluate_xpath(&doc, "-1");
+            let double = evaluate_xpath(&doc, "--1");
+            let count = evaluate_xpath(&doc, "-count(//x) + 5");
+            let string = evaluate_xpath(&doc, "-'3'");
+            let attribute = evaluate_xpath(&doc, "-(//item/@price)");
+            let in_step = evaluate_xpath(&doc, "sum(//item[-@price < -3]/@price)");
+            let predicate = evaluate_xpath(&doc, "count(//x[-1])");
+            let missing = evaluate_xpath(&doc, "-//missing");
+
+            assert_eq!(Ok(Value::Number(-1.0)), number);
+            assert_eq!(Ok(Value::Number(1.0)), double);
+            assert_eq!(Ok(Value::Number(3.0)), count);
+            assert_eq!(Ok(Value::Number(-3.0)), string);
+            assert_eq!(Ok(Value::Number(-2.5)), attribute);
+            assert_eq!(Ok(Value::Number(4.0)), in_step);
+            assert_eq!(Ok(Value::Number(0.0)), predicate);
+            match missing {
+                Ok(Value::Number(n)) => assert!(n.is_nan(), "{} is not NaN", n),
+                other => panic!("expected NaN, got {:?}", other),
+            }
+        },
+    );
+}
+
+#[test]
+fn union_of_several_operands_is_deduplicated_in_document_order() {
+    with_document(
+        "<r><a n='1'><c n='1c'/></a><b n='2'><c n='2c'/></b><a n='3'/><d n='4'/></r>",
+        |doc| {
+            let names = |xpath| match evaluate_xpath(&doc, xpath) {
+                Ok(Value::Nodeset(ns)) => ns
+                    .document_order()
+                    .iter()
+                    .filter_map(|n| n.element())
+                    .filter_map(|e| e.attribute_value("n"))
+                    .collect::<Vec<_>>(),
+                other => panic!("{} did not select nodes: {:?}", xpath, other),
+            };
+
+            assert_eq!(
+                vec!["1", "2", "3", "4"],
+                names("/r/d | /r/b | /r/a | /r/*[1]")
+            );
+            assert_eq!(vec!["1c", "2c"], names("(/r/b | /r/a)/c"));
+            assert_eq!(vec!["2", "3"], names("/r/a[2] | /r/b | /r/a[2]"));
+
+            let count = evaluate_xpath(&doc, "count(/r/a | /r/b | /r/*)");
+            assert_eq!(Ok(Value::Number(4.0)), count);
+
+            let error = evaluate_xpath(&doc, "1 | //a").expect_err("union of a number");
+            assert!(error.to_string().contains("nodeset"), "{}", error);
+        },
+    );
+}
+
+#[test]
+fn double_slash_expands_to_descendant_or_self_steps() {
+    with_document(
+        "<doc><div><para>1</para><para>2</para><span>a</span></div>\
+         <div><x><para>3</para><span>b</span></x></div><para>4</para></doc>",
+        |doc| {
+            let first_children = evaluate_xpath(&doc, "count(//para[1])");
+            let first_overall = evaluate_xpath(&doc, "count((//para)[1])");
+            let expanded =
+                evaluate_xpath(&doc, "count(/descendant-or-self::node()/child::para[1])");
+            let spans = evaluate_xpath(&doc, "count(/doc/div//span)");
+            let nested = evaluate_xpath(&doc, "string(/doc/div//x//span)");
+
+            assert_eq!(Ok(Value::Number(3.0)), first_children);
+            assert_eq!(Ok(Value::Number(1.0)), first_overall);
+            assert_eq!(first_children, expanded);
+            assert_eq!(Ok(Value::Number(2.0)), spans);
+            assert_eq!(Ok(Value::String("b".to_owned())), nested);
+
+            let second_div = match evaluate_xpath(&doc, "/doc/div[2]") {
+                Ok(Value::Nodeset(ns)) => ns.document_order_first().expect("no second div"),
+                other => panic!("expected a nodeset, got {:?}", other),
+            };
+            let context = Context::new();
+            let evaluate = |xpath| {
+                Factory::new()
+                    .build(xpath)
+                    .expect("Unable to build XPath")
+                    .evaluate(&context, second_div)
+            };
+
+            assert_eq!(Ok(Value::Number(4.0)), evaluate("count(//para)"));
+            assert_eq!(Ok(Value::Number(1.0)), evaluate("count(.//para)"));
+            assert_eq!(
+                Ok(Value::String("3".to_owned())),
+                evaluate("string(.//para)")
+            );
+        },
+    );
+}
+
+#[test]
+fn operator_names_can_select_elements() {
+    with_document("<div><div>6</div><and>x</and><or/><mod/></div>", |doc| {
+        let divs = evaluate_xpath(&doc, "count(//div)");
+        let divided = evaluate_xpath(&doc, "div/div div div/div");
+        let named = evaluate_xpath(&doc, "count(/div/and | /div/or | /div/child::mod)");
+        let and_and = evaluate_xpath(&doc, "div/and and div/and");
+
+        assert_eq!(Ok(Value::Number(2.0)), divs);
+        assert_eq!(Ok(Value::Number(1.0)), divided);
+        assert_eq!(Ok(Value::Number(3.0)), named);
+        assert_eq!(Ok(Value::Boolean(true)), and_and);
+    });
+
+    with_document("<div>6</div>", |doc| {
+        let divided = evaluate_xpath(&doc, "div div div");
+        let anded = evaluate_xpath(&doc, "div and and");
+
+        assert_eq!(Ok(Value::Number(1.0)), divided);
+        assert_eq!(Ok(Value::Boolean(false)), anded);
+    });
+
+    let error = Factory::new()
+        .build("a and")
+        .expect_err("operator needs a right-hand side");
+    assert_eq!(ParseErrorKind::MissingOperand, error.kind());
+}
+
+#[test]
+fn asterisk_is_multiplication_only_after_an_operand() {
+    with_document(
+        "<order><item price='3'><price>2.5</price><quantity>4</quantity></item></order>",
+        |doc| {
+            let product = evaluate_xpath(&doc, "/order/item/price * /order/item/quantity");
+            let relative = evaluate_xpath(&doc, "sum(//item[price * quantity = 10]/@price)");
+            let wildcards = evaluate_xpath(&doc, "count(//*)");
+            let attribute = evaluate_xpath(&doc, "//item/@* * 2");
+            let numbers = evaluate_xpath(&doc, "2 * 3");
+
+            assert_eq!(Ok(Value::Number(10.0)), product);
+            assert_eq!(Ok(Value::Number(3.0)), relative);
+            assert_eq!(Ok(Value::Number(4.0)), wildcards);
+            assert_eq!(Ok(Value::Number(6.0)), attribute);
+            assert_eq!(Ok(Value::Number(6.0)), numbers);
+        },
+    );
+
+    with_document("<n>7</n>", |doc| {
+        let squared = evaluate_xpath(&doc, "* * *");
+
+        assert_eq!(Ok(Value::Number(49.0)), squared);
+    });
+}
+
+#[test]
+fn deeply_nested_expressions_fail_to_build_instead_of_overflowing() {
+    const LENGTH: usize = 100_000;
+
+    let deep = vec![
+        format!("{}1{}", "(".repeat(LENGTH), ")".repeat(LENGTH)),
+        format!("{}1", "-".repeat(LENGTH)),
+        vec!["1"; LENGTH].join(" + "),
+        format!("(/){}", "[1]".repeat(LENGTH)),
+        format!("{}1{}", "f(".repeat(LENGTH), ")".repeat(LENGTH)),
+    ];
+
+    let mut factory = Factory::new();
+    factory.set_max_depth(32);
+    for xpath in &deep {
+        let error = factory.build(xpath).expect_err("XPath should not build");
+        assert_eq!(ParseErrorKind::TooDeep, error.kind());
+    }
+}
+
+#[test]
+fn default_nesting_limit_allows_ordinary_expressions() {
+    with_document("<a/>", |doc| {
+        let factory = Factory::new();
+        let context = Context::new();
+        let evaluate = |xpath: &str| {
+            factory
+                .build(xpath)
+                .expect("Unable to build XPath")
+                .evaluate(&context, doc.root())
+        };
+
+        let sum = vec!["1"; 100].join(" + ");
+        assert_eq!(Ok(Value::Number(100.0)), evaluate(&sum));
+
+        let concat = format!("{}'a'{}", "concat('a', ".repeat(40), ")".repeat(40));
+        assert_eq!(Ok(Value::String("a".repeat(41))), evaluate(&concat));
+
+        let parens = format!("{}1{}", "(".repeat(40), ")".repeat(40));
+        assert_eq!(Ok(Value::Number(1.0)), evaluate(&parens));
     });
 }
 
+#[test]
+fn default_nesting_limit_fits_on_a_spawned_thread() {
+    let deepest_allowed = |open: &str, close: &str| {
+        let depth = (1..)
+            .find(|&depth| {
+                let xpath = format!("{}1{}", open.repeat(depth), close.repeat(depth));
+                Factory::new().build(&xpath).is_err()
+            })
+            .expect("no depth is too deep");
+        format!("{}1{}", open.repeat(depth - 1), close.repeat(depth - 1))
+    };
+
+    // The default stack of a spawned thread is smaller than that of
+    // the main thread, and overflowing it aborts the whole process.
+    let result = thread::spawn(move || {
+        let package = Package::new();
+        let doc = package.as_document();
+        let context = Context::new();
+        let factory = Factory::new();
+
+        let parens = format!("{}1{}", "(".repeat(5000), ")".repeat(5000));
+        let error = factory.build(&parens).expect_err("should be too deep");
+        assert_eq!(ParseErrorKind::TooDeep, error.kind());
+
+        let sum = vec!["1"; 5000].join(" + ");
+        let error = factory.build(&sum).expect_err("should be too deep");
+        assert_eq!(ParseErrorKind::TooDeep, error.kind());
+
+        for &(open, close) in &[
+            ("(", ")"),
+            ("concat('a', ", ")"),
+            ("/a[", "]"),
+            ("-", ""),
+            ("1 + ", ""),
+        ] {
+            let xpath = deepest_allowed(open, close);
+            let value = factory
+                .build(&xpath)
+                .expect("Unable to build XPath")
+                .evaluate(&context, doc.root());
+            assert!(value.is_ok(), "{}", xpath);
+        }
+    })
+    .join();
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn long_chains_of_associative_operators_can_be_evaluated() {
+    const LENGTH: usize = 100_000;
+
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let or = format!("{} or 1", vec!["0"; LENGTH].join(" or "));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate_xpath(&doc, &or));
+
+    let and = format!("{} and 0", vec!["1"; LENGTH].join(" and "));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate_xpath(&doc, &and));
+
+    let union = format!("count({})", vec!["/"; LENGTH].join(" | "));
+    assert_eq!(Ok(Value::Number(1.0)), evaluate_xpath(&doc, &union));
+}
+
+#[test]
+fn referenced_variables_can_be_bound_before_evaluating() {
+    let package = parser::parse("<root><item id='1'>a</item><item id='2'>b</item></root>")
+        .expect("document should parse");
+    let doc = package.as_document();
+
+    let xpath = Factory::new()
+        .build("string(//item[@id = $wanted][string-length(.) >= $length])")
+        .expect("XPath should build");
+
+    let mut variables: Vec<_> = xpath
+        .referenced_variables()
+        .into_iter()
+        .map(|name| name.local_part().to_owned())
+        .collect();
+    variables.sort();
+    assert_eq!(vec!["length", "wanted"], variables);
+
+    let mut functions: Vec<_> = xpath
+        .referenced_functions()
+        .into_iter()
+        .map(|name| name.local_part().to_owned())
+        .collect();
+    functions.sort();
+    assert_eq!(vec!["string", "string-length"], functions);
+
+    let mut context = Context::new();
+    context.set_variable("wanted", "2");
+    context.set_variable("length", 1.0);
+
+    let value = xpath.evaluate(&context, doc.root());
+    assert_eq!(Ok(Value::String("b".into())), value);
+}
+
+#[test]
+fn compiled_expressions_can_be_inspected_as_an_ast() {
+    use sxd_xpath::ast::{Axis, BinaryOperator, Expr, NodeTest, Step};
+
+    let xpath = Factory::new()
+        .build("//book[@year > 2000][last()]/title | $extra")
+        .expect("XPath should build");
+
+    let step = |axis, node_test, predicates| Step {
+        axis,
+        node_test,
+        predicates,
+    };
+    let binary = |operator, left, right| Expr::Binary {
+        operator,
+        left: Box::new(left),
+        right: Box::new(right),
+    };
+    let path = |start, steps| Expr::Path {
+        start: Box::new(start),
+        steps,
+    };
+
+    let year = path(
+        Expr::ContextNode,
+        vec![step(Axis::Attribute, NodeTest::name(None, "year"), vec![])],
+    );
+    let last = Expr::Function {
+        name: "last".into(),
+        arguments: vec![],
+    };
+
+    let books = path(
+        Expr::RootNode,
+        vec![
+            step(Axis::DescendantOrSelf, NodeTest::Node, vec![]),
+            step(
+                Axis::Child,
+                NodeTest::name(None, "book"),
+                vec![
+                    binary(
+                        BinaryOperator::GreaterThan,
+                        year,
+                        Expr::Literal(Value::Number(2000.0)),
+                    ),
+                    last,
+                ],
+            ),
+            step(Axis::Child, NodeTest::name(None, "title"), vec![]),
+        ],
+    );
+    let expected = binary(BinaryOperator::Union, books, Expr::Variable("extra".into()));
+
+    assert_eq!(expected, xpath.to_ast());
+}
+
+#[test]
+fn names_may_contain_non_ascii_characters() {
+    let package = parser::parse("<racine><données valeur='42'/><名前 値='はい'/></racine>")
+        .expect("document should parse");
+    let doc = package.as_document();
+
+    let factory = Factory::new();
+    let mut context = Context::new();
+    context.set_variable("変数", "はい");
+
+    let evaluate = |xpath: &str| {
+        factory
+            .build(xpath)
+            .expect("XPath should build")
+            .evaluate(&context, doc.root())
+            .map(|v| v.string())
+    };
+
+    assert_eq!(Ok("42".to_owned()), evaluate("//données/@valeur"));
+    assert_eq!(Ok("はい".to_owned()), evaluate("$変数"));
+    assert_eq!(Ok("true".to_owned()), evaluate("//名前/@値 = $変数"));
+}
+
+#[test]
+fn equality_with_a_nodeset_is_existential() {
+    let package = parser::parse("<root><a x='1'/><a x='2'/><b x='1'/></root>")
+        .expect("document should parse");
+    let doc = package.as_document();
+
+    let evaluate = |xpath| evaluate_xpath(&doc, xpath);
+
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//a/@x = '1'"));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//a/@x != '1'"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//b/@x != 1"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//c/@x = //c/@x"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//c/@x != //c/@x"));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//a/@x != //b/@x"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//b/@x != //b/@x"));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//c = false()"));
+    assert_eq!(Ok(Value::Number(1.0)), evaluate("count(//a[@x != //b/@x])"));
+}
+
+#[test]
+fn relational_comparisons_with_a_nodeset_are_existential() {
+    let package = parser::parse(
+        "<root><price>5</price><price>15</price><price>n/a</price><limit>10</limit></root>",
+    )
+    .expect("document should parse");
+    let doc = package.as_document();
+
+    let evaluate = |xpath| evaluate_xpath(&doc, xpath);
+
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//price < 10"));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//price > 10"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//price > 20"));
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("//price >= //limit"));
+    assert_eq!(Ok(Value::Boolean(false)), evaluate("//limit < //limit"));
+    assert_eq!(
+        Ok(Value::Number(1.0)),
+        evaluate("count(//price[. > //limit])")
+    );
+    assert_eq!(
+        Ok(Value::Number(0.0)),
+        evaluate("count(//price[. = 'n/a'][. <= 0 or . > 0])")
+    );
+    assert_eq!(Ok(Value::Boolean(true)), evaluate("'2' < '10'"));
+}
+
+#[test]
+fn arithmetic_follows_ieee_754_and_never_fails() {
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let number = |xpath| match evaluate_xpath(&doc, xpath) {
+        Ok(Value::Number(n)) => n,
+        other => panic!("{} did not give a number: {:?}", xpath, other),
+    };
+    let boolean = |xpath: &str| evaluate_xpath(&doc, xpath);
+
+    assert_eq!(::std::f64::INFINITY, number("1 div 0"));
+    assert_eq!(::std::f64::NEG_INFINITY, number("-1 div 0"));
+    assert!(number("0 div 0").is_nan());
+    assert!(number("number('x') + 1").is_nan());
+    assert!(number("-number('x') * 0").is_nan());
+    assert!(number("(1 div 0) - (1 div 0)").is_nan());
+    assert!(number("5 mod 0").is_nan());
+    assert_eq!(0.0, number("1 div (1 div 0)"));
+
+    assert_eq!(Ok(Value::Boolean(false)), boolean("0 div 0 = 0 div 0"));
+    assert_eq!(Ok(Value::Boolean(true)), boolean("0 div 0 != 0 div 0"));
+    for operator in &["<", "<=", ">", ">="] {
+        let xpath = format!("number('x') {} 1", operator);
+        assert_eq!(Ok(Value::Boolean(false)), boolean(&xpath), "{}", xpath);
+    }
+    assert_eq!(
+        Ok(Value::String("Infinity -Infinity NaN".into())),
+        evaluate_xpath(&doc, "concat(1 div 0, ' ', -1 div 0, ' ', 0 div 0)")
+    );
+}
+
+#[test]
+fn mod_is_a_truncating_remainder() {
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let number = |xpath| match evaluate_xpath(&doc, xpath) {
+        Ok(Value::Number(n)) => n,
+        other => panic!("{} did not give a number: {:?}", xpath, other),
+    };
+
+    assert_eq!(1.0, number("5 mod 2"));
+    assert_eq!(1.0, number("5 mod -2"));
+    assert_eq!(-1.0, number("-5 mod 2"));
+    assert_eq!(-1.0, number("-5 mod -2"));
+    assert!(number("5 mod 0").is_nan());
+    assert!(number("0 mod 0").is_nan());
+
+    let fractional = number("6.4 mod 2.1");
+    assert!((fractional - 0.1).abs() < 1e-9, "{}", fractional);
+    let negative = number("-6.4 mod 2.1");
+    assert!((negative + 0.1).abs() < 1e-9, "{}", negative);
+    assert_eq!(0.5, number("2.5 mod 1"));
+}
+
+#[test]
+fn numbers_are_converted_to_strings_without_exponents() {
+    let package = Package::new();
+    let doc = package.as_document();
+
+    let string = |xpath| evaluate_xpath(&doc, xpath);
+    let expect = |s: &str| Ok(Value::String(s.to_owned()));
+
+    assert_eq!(expect("0.3333333333333333"), string("string(1 div 3)"));
+    assert_eq!(
+        expect("1000000000000000000000"),
+        string("string(1000000000000000000000)")
+    );
+    assert_eq!(
+        expect("1000000000000000000000"),
+        string("string(100000000000 * 10000000000)")
+    );
+    assert_eq!(expect("0"), string("string(-0)"));
+    assert_eq!(expect("0"), string("string(0 div -1)"));
+    assert_eq!(expect("0.0000001"), string("string(0.0000001)"));
+    assert_eq!(expect("0.0000001"), string("string(1 div 10000000)"));
+    assert_eq!(expect("12"), string("string(12.0)"));
+    assert_eq!(expect("-3.5"), string("concat(-3.50, '')"));
+    assert_eq!(expect("NaN"), string("string(number('1e3'))"));
+    assert_eq!(Ok(Value::Boolean(true)), string("12.0 = '12'"));
+}
+
+#[test]
+fn numeric_predicates_compare_with_the_position() {
+    with_document(
+        "<list><item>a</item><item>b</item><item>c</item><item>d</item><item>e</item></list>",
+        |doc| {
+            let selected = |xpath: &str| {
+                let xpath = format!("//item[{}]", xpath);
+                match evaluate_xpath(&doc, &xpath) {
+                    Ok(Value::Nodeset(ns)) => ns
+                        .document_order()
+                        .iter()
+                        .map(|n| n.string_value())
+                        .collect::<Vec<_>>()
+                        .concat(),
+                    other => panic!("{} did not select nodes: {:?}", xpath, other),
+                }
+            };
+
+            assert_eq!("c", selected("3"));
+            assert_eq!("e", selected("last()"));
+            assert_eq!("d", selected("last() - 1"));
+            assert_eq!("b", selected("1 + 1"));
+            assert_eq!("c", selected("number('3')"));
+            assert_eq!("", selected("2.5"));
+            assert_eq!("", selected("0"));
+            assert_eq!("", selected("6"));
+            assert_eq!("", selected("-1"));
+            assert_eq!("", selected("0 div 0"));
+            assert_eq!("", selected("number('x')"));
+            assert_eq!("", selected("1 div 0"));
+            assert_eq!("e", selected("5.0"));
+
+            // Only a number is compared with the position. Other values
+            // are converted to booleans.
+            assert_eq!("abcde", selected("'3'"));
+            assert_eq!("abcde", selected("true()"));
+            assert_eq!("", selected("''"));
+            assert_eq!("c", selected("position() = 3"));
+        },
+    );
+}
+
+#[test]
+fn stacked_predicates_renumber_the_nodes_they_receive() {
+    with_document(
+        "<table>\
+           <row n='1'><cell/></row>\
+           <row n='2'><cell/><cell/></row>\
+           <row n='3'><cell/><cell/><cell/></row>\
+           <row n='4'><cell/></row>\
+         </table>",
+        |doc| {
+            let rows = |xpath| match evaluate_xpath(&doc, xpath) {
+                Ok(Value::Nodeset(ns)) => ns
+                    .document_order()
+                    .iter()
+                    .filter_map(|n| n.element())
+                    .filter_map(|e| e.attribute_value("n"))
+                    .collect::<Vec<_>>()
+                    .concat(),
+                other => panic!("{} did not select nodes: {:?}", xpath, other),
+            };
+
+            assert_eq!("4", rows("//row[position() > 1][last()]"));
+            assert_eq!("3", rows("//row[position() > 1][2]"));
+            assert_eq!("23", rows("//row[position() > 1][position() < last()]"));
+            assert_eq!("3", rows("//row[cell[2]][last()]"));
+            assert_eq!("24", rows("//row[last()][1] | //row[2][last()]"));
+            assert_eq!("", rows("//row[1][2]"));
+
+            // last() inside a nested expression refers to its own
+            // step, not the rows being filtered.
+            assert_eq!(
+                "3",
+                rows("//row[count(cell) = count(../row[last()]/preceding-sibling::row)]")
+            );
+            assert_eq!("13", rows("//row[count(cell[last()]) = 1][cell[position() = last()]][position() = 1 or position() = 3]"));
+            assert_eq!(
+                "14",
+                rows("//row[count(cell) = 1][position() = last() or position() = 1]")
+            );
+            assert_eq!(
+                "123",
+                rows("//row[position() = count(cell[last()]/../cell)]")
+            );
+        },
+    );
+}
+
+#[test]
+fn reverse_axes_number_nodes_nearest_first() {
+    with_document(
+        "<r id='r'><s id='s'><a id='a'/><b id='b'/><c id='c'><d id='d'/></c><e id='e'/></s></r>",
+        |doc| {
+            let ids = |xpath| match evaluate_xpath(&doc, xpath) {
+                Ok(Value::Nodeset(ns)) => ns
+                    .document_order()
+                    .iter()
+                    .filter_map(|n| n.element())
+                    .filter_map(|e| e.attribute_value("id"))
+                    .collect::<Vec<_>>()
+                    .concat(),
+                other => panic!("{} did not select nodes: {:?}", xpath, other),
+            };
+
+            assert_eq!("c", ids("//e/preceding-sibling::*[1]"));
+            assert_eq!("a", ids("//e/preceding-sibling::*[last()]"));
+            assert_eq!("b", ids("//e/preceding-sibling::*[2][position() = 1]"));
+            assert_eq!("c", ids("//d/ancestor::*[1]"));
+            assert_eq!("r", ids("//d/ancestor::*[last()]"));
+            assert_eq!("d", ids("//d/ancestor-or-self::*[1]"));
+            assert_eq!("s", ids("//d/ancestor-or-self::*[3]"));
+            assert_eq!("b", ids("//d/preceding::*[1]"));
+            assert_eq!("a", ids("//d/preceding::*[last()]"));
+            assert_eq!("e", ids("//a/following-sibling::*[last()]"));
+
+            // The nodes a reverse step keeps are still in document order
+            // for the steps and functions that follow it
+            assert_eq!("abc", ids("//e/preceding-sibling::*"));
+            assert_eq!("d", ids("//e/preceding-sibling::*[1]/*"));
+            assert_eq!(
+                Ok(Value::String("a".into())),
+                evaluate_xpath(&doc, "string(//e/preceding-sibling::*/@id)")
+            );
+            assert_eq!("a", ids("(//e/preceding-sibling::*)[1]"));
+        },
+    );
+}
+
+#[test]
+fn union_orders_nodes_from_the_right_operand_first_when_they_come_first() {
+    with_document(
+        "<r><a id='a1'/><b id='b1'/><a id='a2'/><b id='b2'><a id='a3'/></b></r>",
+        |doc| {
+            let ids = |xpath| match evaluate_xpath(&doc, xpath) {
+                Ok(Value::Nodeset(ns)) => ns
+                    .document_order()
+                    .iter()
+                    .filter_map(|n| n.element())
+                    .filter_map(|e| e.attribute_value("id"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                other => panic!("{} did not select nodes: {:?}", xpath, other),
+            };
+
+            assert_eq!("a1", ids("(//b | //a)[1]"));
+            assert_eq!("a3", ids("(//b | //a)[last()]"));
+            assert_eq!("b1", ids("(//b | //a)[2]"));
+            assert_eq!("a1 b1 a2 b2 a3", ids("//a | //b/a | //b"));
+            assert_eq!("a3", ids("(//b/a | //a)[last()]"));
+            assert_eq!(
+                Ok(Value::Number(5.0)),
+                evaluate_xpath(&doc, "count(//a | //b/a | //b)")
+            );
+            assert_eq!(
+                Ok(Value::String("a1".into())),
+                evaluate_xpath(&doc, "string((//b | //a)/@id)")
+            );
+        },
+    );
+}
+
+#[test]
+fn string_value_of_mixed_content_includes_nested_text() {
+    with_document(
+        "<doc><title>Hello <em>World</em></title>\
+         <para>Some <em>very <b>deeply</b></em>  nested\ttext<!-- not this -->.</para></doc>",
+        |doc| {
+            let string = |xpath| evaluate_xpath(&doc, xpath);
+
+            assert_eq!(
+                Ok(Value::String("Some very deeply  nested\ttext.".into())),
+                string("string(//para)")
+            );
+            assert_eq!(
+                Ok(Value::String("Some very deeply nested text.".into())),
+                string("normalize-space(//para)")
+            );
+            assert_eq!(Ok(Value::Boolean(true)), string("//title = 'Hello World'"));
+            assert_eq!(Ok(Value::Boolean(false)), string("//title = 'Hello '"));
+            assert_eq!(Ok(Value::Number(30.0)), string("string-length(//para)"));
+            assert_eq!(
+                Ok(Value::Boolean(true)),
+                string("contains(/doc, 'WorldSome')")
+            );
+        },
+    );
+}
+
+#[test]
+fn string_of_each_node_kind_is_its_string_value() {
+    with_document(
+        "<?xml version='1.0'?>\
+         <!-- before -->\
+         <doc xmlns:p='urn:p' a='attribute value'>\
+         <?target data for the pi?>\
+         <!-- comment text -->\
+         <e>element <i>text</i></e>\
+         <?empty?>\
+         </doc>",
+        |doc| {
+            let string = |xpath| match evaluate_xpath(&doc, xpath) {
+                Ok(Value::String(s)) => s,
+                other => panic!("{} did not produce a string: {:?}", xpath, other),
+            };
+
+            assert_eq!("element text", string("string(/)"));
+            assert_eq!("element text", string("string(/doc)"));
+            assert_eq!("attribute value", string("string(/doc/@a)"));
+            assert_eq!("text", string("string(//i/text())"));
+            assert_eq!(" before ", string("string(/comment())"));
+            assert_eq!(" comment text ", string("string(/doc/comment())"));
+            assert_eq!(
+                "data for the pi",
+                string("string(//processing-instruction())")
+            );
+            assert_eq!("", string("string(//processing-instruction('empty'))"));
+            assert_eq!("urn:p", string("string(/doc/namespace::p)"));
+            assert_eq!(" comment text ", string("concat((//comment())[2], '')"));
+        },
+    );
+}
+
 #[test]
 fn position_function_in_predicate() {
     with_document("<a><b/><b/></a>", |doc| {
@@ -92,7 +2716,6 @@ where
     f(package.as_document());
 }
 
-#[derive(Default)]
 struct Setup<'d> {
     context: Context<'d>,
     factory: Factory,
@@ -100,7 +2723,12 @@ struct Setup<'d> {
 
 impl<'d> Setup<'d> {
     fn new() -> Setup<'d> {
-        Default::default()
+        let mut context = Context::new();
+        function::register_xslt_functions(&mut context);
+        Setup {
+            context,
+            factory: Factory::new(),
+        }
     }
 
     fn evaluate(&self, doc: &'d dom::Document<'d>, xpath: &str) -> Value<'d> {
@@ -111,6 +2739,16 @@ impl<'d> Setup<'d> {
     }
 }
 
+/// Like `evaluate_xpath`, but with the XSLT functions available
+fn evaluate_xslt_xpath<'d>(
+    doc: &'d dom::Document<'d>,
+    xpath: &str,
+) -> Result<Value<'d>, sxd_xpath::ExecutionError> {
+    let setup = Setup::new();
+    let xpath = setup.factory.build(xpath).expect("Unable to build XPath");
+    xpath.evaluate(&setup.context, doc.root())
+}
+
 struct ConstantValueFunction(f64);
 
 impl function::Function for ConstantValueFunction {